@@ -1,31 +1,24 @@
 #version 330
 
+// Defaults, which can be changed with shadertoy -D NAME=VALUE
+#ifndef MAX_STEPS
 #define MAX_STEPS 100
+#endif
+#ifndef MAX_DIST
 #define MAX_DIST 100.
+#endif
+#ifndef HIT_DIST
 #define HIT_DIST .01
+#endif
+#ifndef SHADOW_INTENSITY
 #define SHADOW_INTENSITY .3
+#endif
 
-uniform vec3 iResolution; // The viewport resolution (z is pixel aspect ratio, usually 1.0) */
-uniform float iTime; // Current time in seconds
+#include "shaders/common.glsl"
 out vec4 color;
 
 
-float SphereDist(vec3 point, float radius) {
-    return length(point) - radius;
-}
-
-float Fold(float position, float foldloc) {
-  return abs(mod(position - foldloc/2., foldloc) - foldloc/2.);
-}
-
-float smin(float a, float b, float smoothness) {
-  float h = max(smoothness-abs(a-b), 0.0)/smoothness;
-  return min(a, b) - h * h * smoothness * (1.0/4.0);
-}
-
-float smin(float a, float b) {
-  return smin(a, b, 0.1);
-}
+#include "shaders/raymarch.glsl"
 
 float GetDist(vec3 point) {
     // Fold space
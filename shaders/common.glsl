@@ -0,0 +1,4 @@
+// Uniforms that every program is given
+
+uniform vec3 iResolution; // The viewport resolution (z is pixel aspect ratio, usually 1.0) */
+uniform float iTime; // Current time in seconds
@@ -1,8 +1,8 @@
 #version 330
 
 in vec2 v_tex_coords;
-uniform vec3 iResolution; // The viewport resolution (z is pixel aspect ratio, usually 1.0) */
-uniform float iTime; // Current time in seconds
+#include "common.glsl"
+uniform int iObjectId; // Stays the same while an object is tracked
 out vec4 color;
 
 
@@ -12,5 +12,8 @@ void main() {
     // Scale to fit width
     uv.x *= iResolution.z;
 
-    color = vec4(1.0, 0.0, 0.0, 1.0);
+    // Give every object its own hue
+    float hue = fract(float(iObjectId) * 0.618034);
+    vec3 rgb = clamp(abs(mod(hue * 6.0 + vec3(0.0, 4.0, 2.0), 6.0) - 3.0) - 1.0, 0.0, 1.0);
+    color = vec4(rgb, 1.0);
 }
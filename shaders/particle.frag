@@ -1,14 +1,17 @@
 #version 330
 
-in vec2 v_tex_coords;
-uniform vec3 iResolution; // The viewport resolution (z is pixel aspect ratio, usually 1.0) */
-uniform float iTime; // Current time in seconds
-uniform sampler2D iTexture; // Texture
+in vec4 v_color;
+in float v_age;
+#include "common.glsl"
 out vec4 color;
 
 
 void main() {
-    vec4 pix = texture(iVideo, v_tex_coords);
-    color = vec4(pix.rgb, 1.0);
+    // Round sprites with a soft edge
+    float dist = 2.0 * length(gl_PointCoord - vec2(0.5));
+    float alpha = v_color.a * (1.0 - smoothstep(0.6, 1.0, dist));
+    if (alpha <= 0.0) {
+        discard;
+    }
+    color = vec4(v_color.rgb, alpha);
 }
-
@@ -1,13 +1,17 @@
-#version 140
+#version 330
 
-in vec2 position;
-in vec2 tex_coords;
-out vec2 v_tex_coords;
-uniform vec3 iResolution; // The viewport resolution (z is pixel aspect ratio, usually 1.0) */
-uniform float iTime; // Current time in seconds
+in vec2 particle_position; // Center of the particle
+in float size; // Diameter in pixels
+in vec4 color;
+in float age; // 0 when the particle is emitted and 1 when it dies
+uniform vec4 iArea; // Lower left and upper right corners that -1 to 1 is drawn into
+out vec4 v_color;
+out float v_age;
+#include "common.glsl"
 
 void main() {
-    v_tex_coords = tex_coords;
-    gl_Position = vec4(position, 0.0, 1.0);
+    v_color = color;
+    v_age = age;
+    gl_PointSize = size;
+    gl_Position = vec4(mix(iArea.xy, iArea.zw, (particle_position + 1.0) / 2.0), 0.0, 1.0);
 }
-
@@ -0,0 +1,66 @@
+// Forces from ParticleSystem::forces, packed by GpuSimulation. Keep this in step with
+// src/particles/forces.rs.
+
+uniform sampler2D iForces; // Two texels per force
+uniform int iNumForces;
+
+const int ATTRACTOR = 1;
+const int VORTEX = 2;
+const int TURBULENCE = 3;
+const int WIND = 4;
+
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return x;
+}
+
+// Smooth noise between 0 and 1, made by blending random values at the corners of a grid
+float value_noise(vec3 p, uint seed) {
+    vec3 p0 = floor(p);
+    vec3 t = p - p0;
+    vec3 f = t * t * (3.0 - 2.0 * t);
+    ivec3 i = ivec3(p0);
+    float corners[8];
+    for (int n = 0; n < 8; n++) {
+        ivec3 c = i + ivec3(n & 1, (n >> 1) & 1, n >> 2);
+        uint h = hash(uint(c.x) ^ hash(uint(c.y) ^ hash(uint(c.z) ^ seed)));
+        corners[n] = float(h >> 8) / 16777216.0;
+    }
+    float face0 = mix(mix(corners[0], corners[1], f.x), mix(corners[2], corners[3], f.x), f.y);
+    float face1 = mix(mix(corners[4], corners[5], f.x), mix(corners[6], corners[7], f.x), f.y);
+    return mix(face0, face1, f.z);
+}
+
+vec2 force_acceleration(vec2 position, vec2 velocity, float time) {
+    vec2 total = vec2(0.0);
+    for (int n = 0; n < iNumForces; n++) {
+        vec4 a = texelFetch(iForces, ivec2(2 * n, 0), 0);
+        vec4 b = texelFetch(iForces, ivec2(2 * n + 1, 0), 0);
+        int kind = int(a.x);
+        float strength = a.y;
+        if (kind == ATTRACTOR) {
+            vec2 offset = b.xy - position;
+            float distance2 = dot(offset, offset) + a.z * a.z;
+            total += offset * (strength / max(distance2 * sqrt(distance2), 1e-6));
+        } else if (kind == VORTEX) {
+            vec2 offset = position - b.xy;
+            float distance2 = dot(offset, offset) + a.z * a.z;
+            total += vec2(-offset.y, offset.x) * (strength / max(distance2, 1e-6));
+        } else if (kind == TURBULENCE) {
+            // The seed is split in two halves, which floats can hold exactly
+            uint seed = uint(b.z) | (uint(b.w) << 16);
+            vec3 p = vec3(position / a.z, time * a.w);
+            float e = 0.01;
+            float dx = value_noise(p + vec3(e, 0.0, 0.0), seed) - value_noise(p - vec3(e, 0.0, 0.0), seed);
+            float dy = value_noise(p + vec3(0.0, e, 0.0), seed) - value_noise(p - vec3(0.0, e, 0.0), seed);
+            total += vec2(dy, -dx) * (strength / (2.0 * e));
+        } else if (kind == WIND) {
+            total += (b.xy - velocity) * strength;
+        }
+    }
+    return total;
+}
@@ -0,0 +1,35 @@
+#version 330
+
+uniform sampler2D iMotion; // Position in xy and velocity in zw of every particle
+uniform sampler2D iLife; // Seconds left to live in x
+uniform sampler2D iColorRamp; // Color over age, with the opacity applied
+uniform sampler2D iSizeRamp; // Diameter in pixels over age, in x
+uniform float iLifetime;
+uniform vec4 iArea; // Lower left and upper right corners that -1 to 1 is drawn into
+out vec4 v_color;
+out float v_age;
+#include "common.glsl"
+
+void main() {
+    // One instance per particle, in slot order
+    int width = textureSize(iMotion, 0).x;
+    ivec2 texel = ivec2(gl_InstanceID % width, gl_InstanceID / width);
+    float life = texelFetch(iLife, texel, 0).x;
+    if (life <= 0.0) {
+        // Outside the clip volume, so dead particles aren't drawn
+        v_color = vec4(0.0);
+        v_age = 1.0;
+        gl_PointSize = 1.0;
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        return;
+    }
+
+    float age = 1.0 - life / iLifetime;
+    // Sample between the texel centers, so age 0 and 1 land on the first and last sample
+    int ramp_size = textureSize(iColorRamp, 0).x;
+    vec2 ramp = vec2((age * float(ramp_size - 1) + 0.5) / float(ramp_size), 0.5);
+    v_color = texture(iColorRamp, ramp);
+    v_age = age;
+    gl_PointSize = texture(iSizeRamp, ramp).x;
+    gl_Position = vec4(mix(iArea.xy, iArea.zw, (texelFetch(iMotion, texel, 0).xy + 1.0) / 2.0), 0.0, 1.0);
+}
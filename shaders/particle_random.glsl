@@ -0,0 +1,11 @@
+// The same hash as particles::random, so that the GPU scatters new particles exactly like the CPU
+
+float random(uint seed, uint index) {
+    uint x = index ^ (seed * 0x9e3779b9u);
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return float(x >> 8) / 16777216.0;
+}
@@ -0,0 +1,149 @@
+#version 330
+
+uniform sampler2D iMotion; // Position in xy and velocity in zw of every particle
+uniform sampler2D iLife; // Seconds left to live in x
+uniform float iTime; // Seconds into the simulation at the end of this step
+uniform float iDelta; // Seconds since the last step
+uniform vec2 iGravity;
+uniform float iDrag; // Fraction of its velocity a particle loses every second
+uniform sampler2D iMask; // Solid where red is over a half, from the bottom row up
+uniform bool iUseMask;
+uniform float iBounce;
+uniform float iFriction;
+uniform vec2 iEmitterPosition;
+uniform vec2 iEmitterVelocity;
+uniform float iSpread; // New particles leave up to this many radians either side of the velocity
+uniform float iLifetime;
+uniform int iNumParticles;
+uniform int iEmitStart; // First slot that gets a new particle this step
+uniform int iEmitCount;
+uniform uint iEmitIndex; // How many particles were emitted before this step
+uniform uint iSeed;
+uniform int iShape; // Where new particles appear, one of the SHAPE_ constants
+uniform vec2 iBoxHalf; // Half extents of a box
+uniform sampler2D iContour; // Each point of a contour in xy and the distance around to it in z
+uniform int iContourPoints;
+uniform float iPerimeter;
+out vec4 motion;
+out vec4 life;
+
+#include "particle_forces.glsl"
+#include "particle_random.glsl"
+
+// Keep these in step with src/particles/gpu.rs
+const int SHAPE_POINT = 0;
+const int SHAPE_BOX = 1;
+const int SHAPE_CONTOUR = 2;
+
+vec4 contour_point(int i) {
+    int width = textureSize(iContour, 0).x;
+    return texelFetch(iContour, ivec2(i % width, i / width), 0);
+}
+
+// Keep this in step with Contour::sample
+vec2 sample_contour(float u) {
+    if (iContourPoints == 0) {
+        return vec2(0.0);
+    }
+    float along = u * iPerimeter;
+    // The last edge that starts at or before `along`
+    int low = 0;
+    int high = iContourPoints - 1;
+    while (low < high) {
+        int middle = (low + high + 1) / 2;
+        if (contour_point(middle).z <= along) {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    vec4 a = contour_point(low);
+    vec4 b = contour_point((low + 1) % iContourPoints);
+    float end = low + 1 < iContourPoints ? b.z : iPerimeter;
+    float edge_length = end - a.z;
+    if (edge_length > 0.0) {
+        return a.xy + (b.xy - a.xy) * ((along - a.z) / edge_length);
+    }
+    return a.xy;
+}
+
+// Keep this in step with EmitterShape::sample
+vec2 sample_shape(float u, float v) {
+    if (iShape == SHAPE_BOX) {
+        return vec2(iBoxHalf.x * (2.0 * u - 1.0), iBoxHalf.y * (2.0 * v - 1.0));
+    } else if (iShape == SHAPE_CONTOUR) {
+        return sample_contour(u);
+    }
+    return vec2(0.0);
+}
+
+// Keep the mask lookups in step with src/particles/collision.rs
+const int NORMAL_REACH = 2;
+
+ivec2 mask_texel(vec2 position) {
+    return ivec2(floor((position + 1.0) / 2.0 * vec2(textureSize(iMask, 0))));
+}
+
+bool solid(ivec2 texel) {
+    ivec2 size = textureSize(iMask, 0);
+    if (any(lessThan(texel, ivec2(0))) || any(greaterThanEqual(texel, size))) {
+        return false;
+    }
+    return texelFetch(iMask, texel, 0).r > 0.5;
+}
+
+vec2 collide(vec2 from, vec2 to, inout vec2 velocity) {
+    ivec2 texel = mask_texel(to);
+    if (!solid(texel) || solid(mask_texel(from))) {
+        return to;
+    }
+    vec2 gradient = vec2(
+        float(solid(texel + ivec2(NORMAL_REACH, 0))) - float(solid(texel - ivec2(NORMAL_REACH, 0))),
+        float(solid(texel + ivec2(0, NORMAL_REACH))) - float(solid(texel - ivec2(0, NORMAL_REACH))));
+    vec2 normal = vec2(0.0);
+    if (dot(gradient, gradient) > 0.0) {
+        normal = -normalize(gradient);
+    } else if (dot(velocity, velocity) > 0.0) {
+        normal = -normalize(velocity);
+    }
+    float into = dot(velocity, normal);
+    if (into < 0.0) {
+        vec2 along = velocity - normal * into;
+        velocity = along * (1.0 - iFriction) - normal * (into * iBounce);
+    }
+    return from;
+}
+
+// Keep this in step with ParticleSystem::step and ParticleSystem::spawn
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    int slot = texel.y * textureSize(iMotion, 0).x + texel.x;
+    vec4 current = texelFetch(iMotion, texel, 0);
+    float remaining = texelFetch(iLife, texel, 0).x;
+
+    // Slots are handed out in a ring, so this step's new particles can wrap around to slot 0
+    int offset = (slot - iEmitStart + iNumParticles) % max(iNumParticles, 1);
+    if (slot < iNumParticles && offset < iEmitCount) {
+        uint index = iEmitIndex + uint(offset);
+        float angle = iSpread * (2.0 * random(iSeed, index) - 1.0);
+        float s = sin(angle);
+        float c = cos(angle);
+        vec2 v = iEmitterVelocity;
+        vec2 position = iEmitterPosition
+            + sample_shape(random(iSeed + 1u, index), random(iSeed + 2u, index));
+        motion = vec4(position, v.x * c - v.y * s, v.x * s + v.y * c);
+        life = vec4(iLifetime, 0.0, 0.0, 0.0);
+    } else if (remaining > 0.0) {
+        vec2 acceleration = iGravity + force_acceleration(current.xy, current.zw, iTime);
+        vec2 velocity = (current.zw + acceleration * iDelta) * max(1.0 - iDrag * iDelta, 0.0);
+        vec2 position = current.xy + velocity * iDelta;
+        if (iUseMask) {
+            position = collide(current.xy, position, velocity);
+        }
+        motion = vec4(position, velocity);
+        life = vec4(remaining - iDelta, 0.0, 0.0, 0.0);
+    } else {
+        motion = current;
+        life = vec4(remaining, 0.0, 0.0, 0.0);
+    }
+}
@@ -0,0 +1,7 @@
+#version 330
+
+void main() {
+    // A triangle strip over the whole state texture, without any vertex buffer
+    vec2 corner = vec2(gl_VertexID % 2, gl_VertexID / 2);
+    gl_Position = vec4(corner * 2.0 - 1.0, 0.0, 1.0);
+}
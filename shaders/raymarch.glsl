@@ -0,0 +1,18 @@
+// Distance functions for raymarching
+
+float SphereDist(vec3 point, float radius) {
+    return length(point) - radius;
+}
+
+float Fold(float position, float foldloc) {
+  return abs(mod(position - foldloc/2., foldloc) - foldloc/2.);
+}
+
+float smin(float a, float b, float smoothness) {
+  float h = max(smoothness-abs(a-b), 0.0)/smoothness;
+  return min(a, b) - h * h * smoothness * (1.0/4.0);
+}
+
+float smin(float a, float b) {
+  return smin(a, b, 0.1);
+}
@@ -0,0 +1,12 @@
+#version 330
+
+in vec2 v_tex_coords;
+uniform sampler2D iText; // Status messages, starting with the top row of pixels
+out vec4 color;
+
+
+void main() {
+    // The first row of the texture is the top of the text, but GL puts it at the bottom
+    vec2 tex = vec2(v_tex_coords.x, 1.0 - v_tex_coords.y);
+    color = vec4(texture(iText, tex).rgb, 1.0);
+}
@@ -1,9 +1,9 @@
 #version 330
 
 in vec2 v_tex_coords;
-uniform vec3 iResolution; // The viewport resolution (z is pixel aspect ratio, usually 1.0) */
-uniform float iTime; // Current time in seconds
+#include "common.glsl"
 uniform sampler2D iVideo; // Video texture
+uniform float iVideoTime; // Presentation time of the video frame in seconds
 out vec4 color;
 
 
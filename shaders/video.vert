@@ -3,8 +3,7 @@
 in vec2 position;
 in vec2 tex_coords;
 out vec2 v_tex_coords;
-uniform vec3 iResolution; // The viewport resolution (z is pixel aspect ratio, usually 1.0) */
-uniform float iTime; // Current time in seconds
+#include "common.glsl"
 
 void main() {
     v_tex_coords = tex_coords;
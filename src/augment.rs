@@ -1,28 +1,73 @@
-use ffmpeg::util::frame::video::Video;
-use ffmpeg::{
-    format::Pixel,
-    software::scaling::{Context, Flags},
-};
+use ffmpeg::{format::Pixel, software::scaling::Flags};
 use glium::{
     glutin::event::{ElementState, VirtualKeyCode},
     implement_vertex,
 };
-use glium::{
-    glutin::{self, window::Fullscreen},
-    Surface,
-};
-use std::path::Path;
-use std::sync::mpsc::*;
-use std::thread;
+use glium::{backend::Facade, glutin, Surface};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::*;
+use structopt::StructOpt;
 
-use self::filters::ConnectedComponent;
-#[allow(unused_imports)]
-use self::filters::{bgsub, blur, denoise, edges, find_objects, pixelate};
+use crate::particles::CollisionMask;
+use crate::window::{self, WindowOptions};
 
+use self::capture::{Recorder, WindowCapture};
+use self::config::{ConfigHandle, PanelConfig, PipelineConfig};
+use self::emitters::{EmitterParams, ObjectEmitters};
+use self::encode::VideoEncoder;
+use self::error::AugmentError;
+use self::filters::{ChainOutput, ConnectedComponent, FilterKind, StageSpec};
+use self::layout::{Arrangement, Corner};
+use self::shaders::{ProgramSource, Stage};
+use self::source::{RestartingSource, SourceSpec};
+use self::status::{Area, Edge, StatusOverlay, TextBox};
+use self::textures::TextureCache;
+use self::tracker::{TrackEvent, TrackedObject, Tracker, TrackerParams};
+use self::video::{FramePool, PlaybackCommand, TimedFrame};
+use self::worker::{FilterWorker, FilteredFrame};
+
+mod capture;
+mod clock;
+mod config;
+mod emitters;
+mod encode;
+pub mod error;
 mod filters;
+mod layout;
+mod queue;
 pub mod shaders;
+mod source;
+mod status;
+mod textures;
+mod tracker;
 mod video;
+mod worker;
+
+/// Source frames held on to until the filters are done with them. Older ones are dropped if the
+/// filters fall this far behind.
+const MAX_PENDING_FRAMES: usize = 8;
+
+/// How far the arrow keys seek
+const SEEK_STEP_SECS: f64 = 5.0;
+
+/// Panel source that shows the unfiltered video
+const RAW_SOURCE: &str = "raw";
+
+/// Panel source that shows the tracked objects filled in with their colors
+const OBJECTS_SOURCE: &str = "objects";
+
+/// Status overlay slots for errors from the source and from rendering its frames
+const SOURCE_STATUS: &str = "source";
+const FRAME_STATUS: &str = "frame";
+
+/// Gaps between frames longer than this, or going backwards, mean we seeked and the tracked
+/// objects are stale
+const MAX_TRACKING_GAP_SECS: f32 = 1.0;
 
 #[derive(Copy, Clone)]
 struct Vertex {
@@ -30,70 +75,189 @@ struct Vertex {
     tex_coords: [f32; 2],
 }
 
-#[allow(dead_code)]
-pub fn start() {
-    let (tx, rx) = channel();
+implement_vertex!(Vertex, position, tex_coords);
+
+/// How detected objects are drawn over the lower left panel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectStyle {
+    /// Filled bounding box
+    Box,
+    /// Outline of the object's pixels
+    Contour,
+    /// Outline of the convex hull
+    Hull,
+    /// Outline of the smallest rotated rectangle
+    Rotated,
+}
 
-    thread::spawn(move || {
-        let filename = Path::new("Bliss Dance - Nicky Evers.mp4");
-        let result = video::load_video(&filename, tx);
-        if result.is_err() {
-            println!("Error loading video: {:?}", result.err().unwrap());
+impl ObjectStyle {
+    fn next(self) -> ObjectStyle {
+        match self {
+            ObjectStyle::Box => ObjectStyle::Contour,
+            ObjectStyle::Contour => ObjectStyle::Hull,
+            ObjectStyle::Hull => ObjectStyle::Rotated,
+            ObjectStyle::Rotated => ObjectStyle::Box,
         }
-    });
+    }
+}
 
-    let event_loop = glutin::event_loop::EventLoop::new();
-    let wb = glutin::window::WindowBuilder::new();
-    let cb = glutin::ContextBuilder::new().with_depth_buffer(24);
-    let display = glium::Display::new(wb, cb, &event_loop).unwrap();
+impl FromStr for ObjectStyle {
+    type Err = String;
 
-    implement_vertex!(Vertex, position, tex_coords);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(ObjectStyle::Box),
+            "contour" => Ok(ObjectStyle::Contour),
+            "hull" => Ok(ObjectStyle::Hull),
+            "rotated" => Ok(ObjectStyle::Rotated),
+            _ => Err(format!("Unknown object style '{}'", s)),
+        }
+    }
+}
 
-    let now = Instant::now();
+#[derive(Debug, StructOpt)]
+pub struct AugmentOptions {
+    /// Video file, "v4l2:/dev/videoN", numbered images such as "frames/%04d.png", a still
+    /// image, or "test-pattern". Can also be set in the config file.
+    pub source: Option<SourceSpec>,
+
+    /// TOML file describing the source, filter stages and panels. It is reloaded whenever it
+    /// changes and takes precedence over the command line.
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Frame rate for image sequences, still images and the test pattern
+    #[structopt(long, parse(try_from_str = source::parse_fps), default_value = "30")]
+    pub fps: f64,
+
+    /// Vertex shader for the video panels
+    #[structopt(long, parse(from_os_str), default_value = "shaders/video.vert")]
+    pub vertex_shader: PathBuf,
+
+    /// Fragment shader for the video panels
+    #[structopt(long, parse(from_os_str), default_value = "shaders/video.frag")]
+    pub fragment_shader: PathBuf,
+
+    /// Vertex shader for the detected object overlays
+    #[structopt(long, parse(from_os_str), default_value = "shaders/obj.vert")]
+    pub object_vertex_shader: PathBuf,
 
-    let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+    /// Fragment shader for the detected object overlays
+    #[structopt(long, parse(from_os_str), default_value = "shaders/obj.frag")]
+    pub object_fragment_shader: PathBuf,
 
-    let main_panel = Panel {
-        vbo: make_square([-1.0, -1.0], [1.0, 1.0], &display).unwrap(),
-        indices,
+    /// How detected objects are drawn: box, contour, hull or rotated
+    #[structopt(long, default_value = "box")]
+    pub object_style: ObjectStyle,
+
+    /// Size of the decoded frames: "native", "WxH", or "fit:WxH"
+    #[structopt(long, default_value = "native")]
+    pub video_size: video::OutputSize,
+
+    /// Scaling algorithm used when resizing decoded frames
+    #[structopt(long, parse(try_from_str = video::parse_scaler), default_value = "bilinear")]
+    pub scaler: Flags,
+
+    /// Filter stage as "[name=]filter[:key=value,...]". Repeat to build a chain. Filters are
+    /// blur, edges, denoise, pixelate, bgsub and find_objects. Use find_objects:background=mog2
+    /// or background=knn to detect moving objects instead of bright ones. The background model
+    /// takes history, var_threshold, detect_shadows and learning_rate, as bgsub does.
+    #[structopt(long = "stage", default_value = "find_objects", number_of_values = 1)]
+    pub stages: Vec<StageSpec>,
+
+    /// What to show in each panel: "raw", "objects" or the name of a filter stage. Add
+    /// "+objects" to draw the tracked objects over a panel, and "+particles" for the particles
+    /// they give off. The panels are shown in a grid, on
+    /// their own, side by side and picture in picture, which S cycles through.
+    #[structopt(
+        long,
+        use_delimiter = true,
+        default_value = "raw,find_objects,raw+objects,raw"
+    )]
+    pub panels: Vec<String>,
+
+    /// Render to an .mp4 or .webm file instead of opening a window. The output has the size
+    /// given by --width and --height and keeps the audio of video file sources. Audio that the
+    /// container can't hold, such as AAC in .webm, is an error.
+    #[structopt(long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// Stop rendering to --output after this many seconds of video
+    #[structopt(long)]
+    pub duration: Option<f64>,
+
+    /// Where screenshots and recordings are saved
+    #[structopt(long, parse(from_os_str), default_value = ".")]
+    pub capture_dir: PathBuf,
+}
+
+pub fn start(window_options: &WindowOptions, options: AugmentOptions) {
+    let video_options = video::VideoOptions {
+        size: options.video_size,
+        flags: options.scaler,
+        pool: FramePool::new(),
     };
-    let panel_upper_left = Panel {
-        vbo: make_square([-1.0, 0.0], [0.0, 1.0], &display).unwrap(),
-        indices,
+    let mut config_handle = match options.config.as_ref().map(|path| ConfigHandle::new(path)) {
+        Some(Err(err)) => {
+            println!("Error loading config: {}", err);
+            return;
+        }
+        handle => handle.map(Result::unwrap),
     };
-    let panel_upper_right = Panel {
-        vbo: make_square([0.0, 0.0], [1.0, 1.0], &display).unwrap(),
-        indices,
+    let settings =
+        match PipelineSettings::resolve(&options, config_handle.as_ref().map(|h| h.config())) {
+            Ok(settings) => settings,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+
+    if let Some(output) = &options.output {
+        if let Err(err) = render_to_file(window_options, &options, settings, output) {
+            println!("Error rendering to {:?}: {}", output, err);
+        }
+        return;
+    }
+
+    // A source that fails to open is shown in the status overlay and retried
+    let mut source = RestartingSource::open(&settings.source, video_options.clone(), options.fps);
+
+    let event_loop = glutin::event_loop::EventLoop::new();
+    let display = window_options.create_display("Augment", &event_loop);
+
+    let now = Instant::now();
+
+    let renderer = Renderer::new(&display, &options, settings, video_options.pool.clone());
+    let mut renderer = match renderer {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            println!("Error starting the renderer: {}", err);
+            return;
+        }
     };
-    let panel_lower_left = Panel {
-        vbo: make_square([-1.0, -1.0], [0.0, 0.0], &display).unwrap(),
-        indices,
+    let mut status = match StatusOverlay::new(&display) {
+        Ok(status) => status,
+        Err(err) => {
+            println!("Error starting the status overlay: {}", err);
+            return;
+        }
     };
-    let panel_lower_right = Panel {
-        vbo: make_square([0.0, -1.0], [1.0, 0.0], &display).unwrap(),
-        indices,
+    let mut fullscreen = window_options.fullscreen;
+    let mut rate_index = video::PLAYBACK_RATES
+        .iter()
+        .position(|&rate| rate == 1.0)
+        .unwrap();
+    let mut loop_mode = video::LoopMode::Loop;
+    let window_size = display.get_framebuffer_dimensions();
+    let mut window_capture = match WindowCapture::new(&display, window_size) {
+        Ok(capture) => capture,
+        Err(err) => {
+            println!("Error starting the window capture: {}", err);
+            return;
+        }
     };
-
-    let mut program_handle = shaders::ProgramHandle::new(
-        &display,
-        Path::new("shaders/video.vert"),
-        Path::new("shaders/video.frag"),
-    )
-    .unwrap();
-    let mut obj_prog_handle = shaders::ProgramHandle::new(
-        &display,
-        Path::new("shaders/obj.vert"),
-        Path::new("shaders/obj.frag"),
-    )
-    .unwrap();
-
-    let frame = rx.recv().unwrap();
-    let mut processor = ImageProcessor::new(frame.width(), frame.height()).unwrap();
-    let mut split_screen = true;
-    let mut fullscreen = false;
-    // TODO
-    // let p = ParticleSystem::new().
-    // let particles = vec![ParticleSystemRunner::new(display, ];
+    let mut recorder: Option<Recorder> = None;
 
     let mut last_frame = Instant::now();
     event_loop.run(move |ev, _, control_flow| {
@@ -101,118 +265,62 @@ pub fn start() {
         let delta = last_frame.elapsed().as_secs_f32();
         last_frame = Instant::now();
         if cfg!(debug_assertions) {
-            program_handle.poll(&display);
-            obj_prog_handle.poll(&display);
-        }
-        if let Ok(new_frame) = rx.try_recv() {
-            let image = glium::texture::RawImage2d::from_raw_rgb(
-                new_frame.data(0).to_vec(),
-                (new_frame.width(), new_frame.height()),
-            );
-            let video_texture = glium::texture::Texture2d::new(&display, image).unwrap();
-
-            let (components_frame, components) = processor
-                .find_components_with_intermediate_frame(&display, &new_frame)
-                .unwrap();
-
-            let mut objects = vec![];
-            for component in components {
-                let left = component.left as f32 / new_frame.width() as f32;
-                let right =
-                    (component.left as f32 + component.width as f32) / new_frame.width() as f32;
-                let top = component.top as f32 / new_frame.height() as f32;
-                let bottom =
-                    (component.top as f32 + component.height as f32) / new_frame.height() as f32;
-                // The video is upside down because it goes from top to bottom and GL is from
-                // bottom to top
-                let top = 1f32 - top;
-                let bottom = 1f32 - bottom;
-                // Shift this into the lower left panel
-                let top = top - 1f32;
-                let bottom = bottom - 1f32;
-                let left = left - 1f32;
-                let right = right - 1f32;
-                objects.push(Panel {
-                    vbo: make_square([left, top], [right, bottom], &display).unwrap(),
-                    indices: glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
-                });
-            }
-
-            let mut target = display.draw();
-            target.clear_color(0.0, 0.0, 0.0, 1.0);
-
-            let (width, height) = target.get_dimensions();
-            let aspect_ratio = height as f32 / width as f32;
-            let resolution = [width as f32, height as f32, aspect_ratio];
-            let program = program_handle.as_program();
-            if program.is_ok() {
-                let prog = program.unwrap();
-
-                if split_screen {
-                    panel_upper_left.draw(
-                        &mut target,
-                        prog,
-                        &uniform! {
-                            iResolution: resolution,
-                            iTime: now.elapsed().as_secs_f32(),
-                            iVideo: &video_texture,
-                        },
-                    );
-
-                    panel_upper_right.draw(
-                        &mut target,
-                        prog,
-                        &uniform! {
-                            iResolution: resolution,
-                            iTime: now.elapsed().as_secs_f32(),
-                            iVideo: &components_frame,
-                        },
-                    );
-
-                    panel_lower_left.draw(
-                        &mut target,
-                        prog,
-                        &uniform! {
-                            iResolution: resolution,
-                            iTime: now.elapsed().as_secs_f32(),
-                            iVideo: &video_texture,
-                        },
-                    );
-
-                    panel_lower_right.draw(
-                        &mut target,
-                        prog,
-                        &uniform! {
-                            iResolution: resolution,
-                            iTime: now.elapsed().as_secs_f32(),
-                            iVideo: &video_texture,
-                        },
-                    );
-
-                    for obj in objects {
-                        obj.draw(
-                            &mut target,
-                            obj_prog_handle.as_program().unwrap(),
-                            &uniform! {
-                                iResolution: resolution,
-                                iTime: now.elapsed().as_secs_f32(),
-                            },
+            renderer.poll_programs(&display);
+            status.poll_program(&display);
+        }
+        if let Some(config) = config_handle.as_mut().and_then(|h| h.poll()) {
+            match PipelineSettings::resolve(&options, Some(config)) {
+                Ok(new_settings) => {
+                    if new_settings.source != *source.spec() {
+                        source = RestartingSource::open(
+                            &new_settings.source,
+                            video_options.clone(),
+                            options.fps,
                         );
                     }
-                } else {
-                    main_panel.draw(
-                        &mut target,
-                        prog,
-                        &uniform! {
-                            iResolution: resolution,
-                            iTime: now.elapsed().as_secs_f32(),
-                            iVideo: &video_texture,
-                        },
-                    );
+                    renderer.update_settings(&display, new_settings);
+                    println!("Reloaded pipeline config");
+                }
+                Err(err) => println!("Invalid config, keeping the previous one: {}", err),
+            }
+        }
+        if let Some(frame) = source.poll_frame() {
+            renderer.submit(frame);
+        }
+        let source_error = source.error().map(|(err, wait)| restart_message(err, wait));
+        status.set(SOURCE_STATUS, source_error);
+        let processed = renderer.poll_processed();
+        // While the source is down keep redrawing so that the restart countdown moves
+        if processed.is_some() || source.error().is_some() {
+            let time = now.elapsed();
+            let target = display.draw();
+            if let Err(err) = window_capture.fit(&display, target.get_dimensions()) {
+                println!("Error resizing the window capture: {}", err);
+            }
+            // Frames are drawn offscreen so that recordings can read them back
+            {
+                let mut surface = window_capture.surface();
+                match processed {
+                    Some(processed) => {
+                        let result =
+                            renderer.render(&display, &mut surface, processed, time.as_secs_f32());
+                        status.report(FRAME_STATUS, result);
+                    }
+                    None => surface.clear_color(0.0, 0.0, 0.0, 1.0),
+                }
+                if let Err(err) = status.draw(&display, &mut surface) {
+                    println!("Error drawing the status overlay: {}", err);
+                }
+            }
+            window_capture.show(&target);
+            if let Err(err) = target.finish() {
+                println!("Error presenting frame: {:?}", err);
+            }
+            if let Some(recorder) = recorder.as_mut() {
+                if let Err(err) = window_capture.record(recorder, time) {
+                    println!("Error capturing frame: {:?}", err);
                 }
             }
-
-            target.finish().unwrap();
         }
 
         let work = now.elapsed().as_micros() - frame_start;
@@ -222,6 +330,10 @@ pub fn start() {
         match ev {
             glutin::event::Event::WindowEvent { event, .. } => match event {
                 glutin::event::WindowEvent::CloseRequested => {
+                    if let Some(recorder) = recorder.take() {
+                        // Make sure the file is complete before the process exits
+                        let _ = window_capture.stop_recording(recorder).join();
+                    }
                     *control_flow = glutin::event_loop::ControlFlow::Exit;
                     return;
                 }
@@ -229,22 +341,75 @@ pub fn start() {
                     if let ElementState::Pressed = input.state {
                         match input.virtual_keycode {
                             Some(VirtualKeyCode::F) => {
-                                if fullscreen {
-                                    display.gl_window().window().set_fullscreen(None);
-                                } else {
-                                    let monitor_handle = display
-                                        .gl_window()
-                                        .window()
-                                        .available_monitors()
-                                        .next()
-                                        .unwrap();
-                                    let fs = Fullscreen::Borderless(Some(monitor_handle));
-                                    display.gl_window().window().set_fullscreen(Some(fs));
-                                }
                                 fullscreen = !fullscreen;
+                                window::set_fullscreen(&display, fullscreen);
                             }
                             Some(VirtualKeyCode::S) => {
-                                split_screen = !split_screen;
+                                renderer.next_layout();
+                            }
+                            Some(VirtualKeyCode::Space) => {
+                                source.send_command(PlaybackCommand::TogglePause);
+                            }
+                            Some(VirtualKeyCode::Period) => {
+                                source.send_command(PlaybackCommand::Step);
+                            }
+                            Some(VirtualKeyCode::Left) => {
+                                source.send_command(PlaybackCommand::SeekBy(-SEEK_STEP_SECS));
+                            }
+                            Some(VirtualKeyCode::Right) => {
+                                source.send_command(PlaybackCommand::SeekBy(SEEK_STEP_SECS));
+                            }
+                            Some(VirtualKeyCode::Home) => {
+                                source.send_command(PlaybackCommand::Seek(Duration::from_secs(0)));
+                            }
+                            Some(VirtualKeyCode::LBracket) => {
+                                rate_index = rate_index.saturating_sub(1);
+                                let rate = video::PLAYBACK_RATES[rate_index];
+                                println!("Playback rate: {}x", rate);
+                                source.send_command(PlaybackCommand::SetRate(rate));
+                            }
+                            Some(VirtualKeyCode::RBracket) => {
+                                rate_index = (rate_index + 1).min(video::PLAYBACK_RATES.len() - 1);
+                                let rate = video::PLAYBACK_RATES[rate_index];
+                                println!("Playback rate: {}x", rate);
+                                source.send_command(PlaybackCommand::SetRate(rate));
+                            }
+                            Some(VirtualKeyCode::R) => {
+                                renderer.reset();
+                            }
+                            Some(VirtualKeyCode::O) => {
+                                renderer.object_style = renderer.object_style.next();
+                                println!("Object style: {:?}", renderer.object_style);
+                            }
+                            Some(VirtualKeyCode::P) => capture::save_screenshot(
+                                capture::timestamped_path(&options.capture_dir, "augment", "png"),
+                                window_capture.screenshot(),
+                            ),
+                            Some(VirtualKeyCode::V) => {
+                                recorder = match recorder.take() {
+                                    Some(recorder) => {
+                                        window_capture.stop_recording(recorder);
+                                        None
+                                    }
+                                    None => {
+                                        let (width, height) = display.get_framebuffer_dimensions();
+                                        Some(Recorder::start(
+                                            capture::timestamped_path(
+                                                &options.capture_dir,
+                                                "augment",
+                                                "mp4",
+                                            ),
+                                            width,
+                                            height,
+                                            options.fps,
+                                        ))
+                                    }
+                                };
+                            }
+                            Some(VirtualKeyCode::L) => {
+                                loop_mode = loop_mode.next();
+                                println!("Loop mode: {:?}", loop_mode);
+                                source.send_command(PlaybackCommand::SetLoopMode(loop_mode));
                             }
                             _ => {}
                         }
@@ -257,10 +422,770 @@ pub fn start() {
     });
 }
 
-fn make_square(
+/// What the status overlay says while the source is down
+fn restart_message(err: &AugmentError, wait: Duration) -> String {
+    match wait.as_secs_f32().ceil() as u32 {
+        0 => format!("{}. Opening the source again", err),
+        secs => format!("{}. Opening the source again in {}s", err, secs),
+    }
+}
+
+/// Renders the source to a video file as fast as possible, without opening a window
+fn render_to_file(
+    window_options: &WindowOptions,
+    options: &AugmentOptions,
+    settings: PipelineSettings,
+    output: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let video_options = video::VideoOptions {
+        size: options.video_size,
+        flags: options.scaler,
+        pool: FramePool::new(),
+    };
+    let endless = matches!(
+        settings.source,
+        SourceSpec::Image(_) | SourceSpec::TestPattern
+    );
+    if endless && options.duration.is_none() {
+        return Err("Rendering a still image or the test pattern needs --duration".into());
+    }
+    let duration = options.duration.map(Duration::from_secs_f64);
+    let pool = video_options.pool.clone();
+    let mut source = source::open_offline(&settings.source, video_options, options.fps)?;
+    let audio_source = match &settings.source {
+        SourceSpec::File(path) => Some(path.clone()),
+        _ => None,
+    };
+
+    // Chroma subsampling needs even dimensions
+    let (width, height) = (window_options.width / 2 * 2, window_options.height / 2 * 2);
+    let headless = window::create_headless(width, height)?;
+    let facade = &headless.renderer;
+    let texture = glium::texture::Texture2d::empty(facade, width, height)?;
+    let mut encoder =
+        VideoEncoder::create(output, width, height, options.fps, audio_source.as_deref())?;
+
+    let first = source.wait_frame()?.ok_or("The source has no frames")?;
+    let mut renderer = Renderer::new(facade, options, settings, pool)?;
+    let mut next = Some(first);
+    let mut frames = 0;
+    while let Some(frame) = next {
+        if duration.map_or(false, |duration| frame.timestamp >= duration) {
+            break;
+        }
+        let timestamp = frame.timestamp;
+        // Every frame is filtered before the next one is read, so none are skipped
+        renderer.submit(frame);
+        let processed = renderer
+            .wait_processed()
+            .ok_or("The filter thread stopped")?;
+        // iTime follows the video so that the output only depends on the input
+        let time = timestamp.as_secs_f32();
+        renderer.render(facade, &mut texture.as_surface(), processed, time)?;
+        let image: glium::texture::RawImage2d<u8> = texture.read();
+        encoder.push(&capture::image_to_frame(&image), timestamp)?;
+        frames += 1;
+        next = source.wait_frame()?;
+    }
+    encoder.finish()?;
+    println!("Wrote {} frames to {:?}", frames, output);
+    Ok(())
+}
+
+/// A source frame and what the filters made of it
+struct ProcessedFrame {
+    source: Arc<TimedFrame>,
+    filtered: FilteredFrame,
+}
+
+/// Turns each frame into a picture: runs the filters, tracks objects and draws the panels
+struct Renderer {
+    settings: PipelineSettings,
+    programs: HashMap<ProgramSource, shaders::ProgramHandle>,
+    obj_prog_handle: shaders::ProgramHandle,
+    /// Draws shader errors over the panels that use the shader
+    text_program: shaders::ProgramHandle,
+    /// One per panel of the current layout
+    error_text: Vec<TextBox>,
+    worker: FilterWorker,
+    /// Source frames that were sent to the filters and their sequence numbers, oldest first
+    pending: VecDeque<(u64, Arc<TimedFrame>)>,
+    next_sequence: u64,
+    pool: FramePool,
+    /// One per source: "raw", "objects" and each shown filter stage
+    textures: TextureCache,
+    tracker: Tracker,
+    /// Particles given off by the tracked objects
+    emitters: ObjectEmitters,
+    /// What the particles bounce off, from the mask stage's last output
+    mask: Option<Rc<CollisionMask>>,
+    /// The mask stage's latest output, read into here to check whether it changed
+    mask_pixels: Vec<u8>,
+    last_video_time: Option<f32>,
+    /// Rebuilt whenever the target size, video size or layout changes
+    layout: Option<Layout>,
+    layout_index: usize,
+    object_style: ObjectStyle,
+}
+
+impl Renderer {
+    fn new<F: Facade>(
+        facade: &F,
+        options: &AugmentOptions,
+        settings: PipelineSettings,
+        pool: FramePool,
+    ) -> Result<Renderer, AugmentError> {
+        let mut programs = HashMap::new();
+        load_panel_programs(facade, &settings.layouts, &mut programs);
+        let obj_prog_handle = shaders::ProgramHandle::new(
+            facade,
+            &options.object_vertex_shader,
+            &options.object_fragment_shader,
+        )?;
+        Ok(Renderer {
+            tracker: Tracker::new(settings.tracker.clone()),
+            emitters: ObjectEmitters::new(facade)?,
+            mask: None,
+            mask_pixels: vec![],
+            worker: FilterWorker::spawn(&settings.stages, pool.clone()),
+            settings,
+            programs,
+            obj_prog_handle,
+            text_program: status::load_text_program(facade)?,
+            error_text: vec![],
+            pending: VecDeque::new(),
+            next_sequence: 0,
+            pool,
+            textures: TextureCache::new(),
+            last_video_time: None,
+            layout: None,
+            layout_index: 0,
+            object_style: options.object_style,
+        })
+    }
+
+    fn poll_programs<F: Facade>(&mut self, facade: &F) {
+        for program_handle in self.programs.values_mut() {
+            program_handle.poll(facade);
+        }
+        self.obj_prog_handle.poll(facade);
+        self.text_program.poll(facade);
+        self.emitters.poll_programs(facade);
+    }
+
+    /// Switches to a reloaded config. Opening a new source is up to the caller.
+    fn update_settings<F: Facade>(&mut self, facade: &F, settings: PipelineSettings) {
+        if settings.stages != self.settings.stages {
+            self.worker.set_stages(&settings.stages);
+            self.textures.clear();
+            self.forget_objects();
+        }
+        if settings.tracker != *self.tracker.params() {
+            self.tracker.set_params(settings.tracker.clone());
+        }
+        load_panel_programs(facade, &settings.layouts, &mut self.programs);
+        if settings.layouts != self.settings.layouts {
+            // Stay on the same layout if it still exists
+            let name = &self.settings.layouts[self.layout_index].name;
+            self.layout_index = settings
+                .layouts
+                .iter()
+                .position(|layout| &layout.name == name)
+                .unwrap_or(0);
+            self.layout = None;
+        }
+        self.settings = settings;
+    }
+
+    fn next_layout(&mut self) {
+        self.layout_index = (self.layout_index + 1) % self.settings.layouts.len();
+        println!("Layout: {}", self.settings.layouts[self.layout_index].name);
+    }
+
+    /// Forgets the learned background models and the tracked objects
+    fn reset(&mut self) {
+        self.worker.reset();
+        self.forget_objects();
+    }
+
+    /// Drops the tracked objects. Their particles fade away as if they had left.
+    fn forget_objects(&mut self) {
+        report_track_events(self.tracker.clear());
+    }
+
+    /// Sends a source frame to the filters. It comes back out of `poll_processed` or
+    /// `wait_processed` once they are done with it.
+    fn submit(&mut self, frame: TimedFrame) {
+        let frame = Arc::new(frame);
+        if self.pending.len() >= MAX_PENDING_FRAMES {
+            let (_, oldest) = self.pending.pop_front().unwrap();
+            self.recycle_source(oldest);
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.pending.push_back((sequence, frame.clone()));
+        let stages = self.settings.shown_stages(self.layout_index);
+        self.worker.submit(frame, sequence, stages);
+    }
+
+    /// Returns the newest frame that the filters have finished, without blocking
+    fn poll_processed(&mut self) -> Option<ProcessedFrame> {
+        let mut newest = None;
+        while let Some(filtered) = self.worker.try_recv() {
+            if let Some(skipped) = newest.replace(filtered) {
+                self.recycle_filtered(skipped);
+            }
+        }
+        newest.and_then(|filtered| self.pair(filtered))
+    }
+
+    /// Blocks until the filters have finished the next frame
+    fn wait_processed(&mut self) -> Option<ProcessedFrame> {
+        loop {
+            let filtered = self.worker.recv()?;
+            if let Some(processed) = self.pair(filtered) {
+                return Some(processed);
+            }
+        }
+    }
+
+    /// Finds the source frame that was filtered. Frames submitted before it were skipped by the
+    /// filters and are dropped.
+    fn pair(&mut self, filtered: FilteredFrame) -> Option<ProcessedFrame> {
+        let found = self
+            .pending
+            .iter()
+            .position(|(sequence, _)| *sequence == filtered.sequence);
+        let index = match found {
+            Some(index) => index,
+            None => {
+                self.recycle_filtered(filtered);
+                return None;
+            }
+        };
+        for _ in 0..index {
+            let (_, skipped) = self.pending.pop_front().unwrap();
+            self.recycle_source(skipped);
+        }
+        let (_, source) = self.pending.pop_front().unwrap();
+        Some(ProcessedFrame { source, filtered })
+    }
+
+    fn recycle_source(&self, frame: Arc<TimedFrame>) {
+        // The filter thread may still be holding on to it, in which case it is just freed
+        if let Ok(frame) = Arc::try_unwrap(frame) {
+            self.pool.give(frame.frame);
+        }
+    }
+
+    fn recycle_filtered(&self, filtered: FilteredFrame) {
+        if let Ok(output) = filtered.output {
+            output.recycle(&self.pool);
+        }
+    }
+
+    /// Updates the tracked objects and returns the video time since the previous frame
+    fn track(&mut self, components: &[ConnectedComponent], video_time: f32) -> f32 {
+        let dt = match self.last_video_time {
+            Some(last) if video_time > last && video_time - last < MAX_TRACKING_GAP_SECS => {
+                video_time - last
+            }
+            Some(_) => {
+                self.forget_objects();
+                0.0
+            }
+            None => 0.0,
+        };
+        self.last_video_time = Some(video_time);
+        report_track_events(self.tracker.update(components, dt));
+        dt
+    }
+
+    /// Draws a frame to `target` and recycles it. `time` is passed to the shaders as iTime. If
+    /// the filters failed, the raw video is still drawn and their error is returned.
+    fn render<F: Facade, S: Surface>(
+        &mut self,
+        facade: &F,
+        target: &mut S,
+        processed: ProcessedFrame,
+        time: f32,
+    ) -> Result<(), AugmentError> {
+        let ProcessedFrame { source, filtered } = processed;
+        let (output, filter_error) = match filtered.output {
+            Ok(output) => (output, None),
+            Err(err) => (ChainOutput::default(), Some(err)),
+        };
+        let result = self.draw(facade, target, &source, &output, time);
+        output.recycle(&self.pool);
+        self.recycle_source(source);
+        match filter_error {
+            Some(err) => Err(err),
+            None => result,
+        }
+    }
+
+    fn draw<F: Facade, S: Surface>(
+        &mut self,
+        facade: &F,
+        target: &mut S,
+        source: &TimedFrame,
+        output: &ChainOutput,
+        time: f32,
+    ) -> Result<(), AugmentError> {
+        let frame = &source.frame;
+        let video_time = source.timestamp.as_secs_f32();
+        let video_size = (frame.width(), frame.height());
+        self.textures.upload(facade, RAW_SOURCE, frame)?;
+
+        for (name, stage_frame) in &output.stages {
+            self.textures.upload(facade, name, stage_frame)?;
+        }
+        let dt = self.track(&output.components, video_time);
+
+        let layout_settings = &self.settings.layouts[self.layout_index];
+        // Particles only move while they are shown
+        if layout_settings.panels.iter().any(|panel| panel.particles) {
+            // Nothing bounces off the mask unless the particles collide
+            let mask_frame = self
+                .settings
+                .mask_stage()
+                .filter(|_| self.settings.particles.collide)
+                .and_then(|name| output.stage(name));
+            match mask_frame {
+                Some(frame) => {
+                    filters::mask_pixels(frame, &mut self.mask_pixels)?;
+                    let (width, height) = (frame.width() as usize, frame.height() as usize);
+                    // An unchanged mask is kept, so that the GPU doesn't upload it again
+                    let changed = match &self.mask {
+                        Some(mask) => !mask.matches(width, height, &self.mask_pixels),
+                        None => true,
+                    };
+                    if changed {
+                        let pixels = std::mem::take(&mut self.mask_pixels);
+                        self.mask = Some(Rc::new(CollisionMask::new(width, height, pixels)?));
+                    }
+                }
+                None => self.mask = None,
+            }
+            self.emitters.update(
+                facade,
+                &self.settings.particles,
+                self.tracker.objects(),
+                self.mask.clone(),
+                video_size,
+                dt,
+            )?;
+        }
+        let shows_objects = layout_settings
+            .panels
+            .iter()
+            .any(|panel| panel.source == OBJECTS_SOURCE);
+        if shows_objects {
+            let objects: Vec<_> = self
+                .tracker
+                .objects()
+                .map(|object| (object.id, &object.component))
+                .collect();
+            let mut image = self.pool.take(Pixel::RGB24, video_size.0, video_size.1);
+            let mut result = filters::draw_objects(&mut image, &objects);
+            if result.is_ok() {
+                result = self.textures.upload(facade, OBJECTS_SOURCE, &image);
+            }
+            self.pool.give(image);
+            result?;
+        }
+
+        let window_size = target.get_dimensions();
+        let stale = match &self.layout {
+            Some(layout) => {
+                layout.window_size != window_size
+                    || layout.video_size != video_size
+                    || layout.index != self.layout_index
+            }
+            None => true,
+        };
+        if stale {
+            self.layout = Some(Layout::new(
+                facade,
+                layout_settings,
+                self.layout_index,
+                window_size,
+                video_size,
+            )?);
+        }
+        let layout = self.layout.as_ref().unwrap();
+
+        target.clear_color(0.0, 0.0, 0.0, 1.0);
+
+        let (width, height) = window_size;
+        let aspect_ratio = height as f32 / width as f32;
+        let resolution = [width as f32, height as f32, aspect_ratio];
+        self.error_text
+            .resize_with(layout.panels.len(), TextBox::new);
+        // Panels are drawn in order so that picture in picture insets end up on top
+        let panels = layout.panels.iter().zip(&layout_settings.panels);
+        for (index, (panel, panel_settings)) in panels.enumerate() {
+            // Shows the errors of the shaders this panel is drawn with instead of leaving it black
+            let mut errors = vec![];
+            let program = self
+                .programs
+                .get(&panel_settings.shaders)
+                .map(|handle| handle.as_program());
+            // A stage that was only just shown has no output until the filters catch up
+            let texture = self.textures.get(&panel_settings.source);
+            match (program, texture) {
+                (Some(Ok(prog)), Some(texture)) => panel.draw(
+                    target,
+                    prog,
+                    &uniform! {
+                        iResolution: resolution,
+                        iTime: time,
+                        iVideoTime: video_time,
+                        iVideo: texture,
+                    },
+                )?,
+                (Some(Err(err)), _) => errors.push(err.to_string()),
+                _ => {}
+            }
+
+            if panel_settings.objects {
+                match self.obj_prog_handle.as_program() {
+                    Ok(prog) => {
+                        for object in self.tracker.objects() {
+                            let (vbo, indices) =
+                                object_shape(object, self.object_style, panel, video_size, facade)?;
+                            draw_shape(
+                                target,
+                                &vbo,
+                                &indices,
+                                prog,
+                                &uniform! {
+                                    iResolution: resolution,
+                                    iTime: time,
+                                    iObjectId: object.id as i32,
+                                },
+                            )?;
+                        }
+                    }
+                    Err(err) => errors.push(err.to_string()),
+                }
+            }
+
+            if panel_settings.particles {
+                self.emitters
+                    .draw(target, panel.lower_left, panel.upper_right)?;
+            }
+
+            if errors.is_empty() {
+                continue;
+            }
+            if let Ok(text_prog) = self.text_program.as_program() {
+                let lines: Vec<String> = errors
+                    .iter()
+                    .flat_map(|error| error.lines())
+                    .map(str::to_string)
+                    .collect();
+                self.error_text[index].draw(
+                    facade,
+                    target,
+                    text_prog,
+                    &lines,
+                    panel.area(),
+                    Edge::Top,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A panel's video source and the shaders it is drawn with
+#[derive(Debug, Clone, PartialEq)]
+struct PanelSettings {
+    source: String,
+    shaders: ProgramSource,
+    /// Draw the tracked objects over this panel
+    objects: bool,
+    /// Draw the particles given off by the tracked objects over this panel
+    particles: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct LayoutSettings {
+    name: String,
+    arrangement: Arrangement,
+    panels: Vec<PanelSettings>,
+}
+
+/// The parts of the pipeline that can be changed by reloading the config file
+#[derive(Debug, Clone, PartialEq)]
+struct PipelineSettings {
+    source: SourceSpec,
+    stages: Vec<StageSpec>,
+    /// Never empty, and every layout has at least one panel
+    layouts: Vec<LayoutSettings>,
+    tracker: TrackerParams,
+    particles: EmitterParams,
+}
+
+impl PipelineSettings {
+    /// Combines the command line with the config file, which takes precedence
+    fn resolve(
+        options: &AugmentOptions,
+        config: Option<&PipelineConfig>,
+    ) -> Result<PipelineSettings, String> {
+        let config_source = config.and_then(|c| c.source.as_ref());
+        let source = match (config_source, &options.source) {
+            (Some(source), _) => source.parse()?,
+            (None, Some(source)) => source.clone(),
+            (None, None) => return Err("No video source given".to_string()),
+        };
+
+        let stages = match config {
+            Some(config) if !config.stages.is_empty() => {
+                config.stages.iter().map(|stage| stage.to_spec()).collect()
+            }
+            _ => options.stages.clone(),
+        };
+
+        let default_shaders = ProgramSource::new(&options.vertex_shader, &options.fragment_shader);
+        let panel_settings = |panel: &PanelConfig| -> Result<PanelSettings, String> {
+            let separate_shaders = panel.vertex_shader.is_some()
+                || panel.fragment_shader.is_some()
+                || panel.geometry_shader.is_some();
+            let shaders = match &panel.shader {
+                Some(_) if separate_shaders => {
+                    return Err(format!(
+                        "Panel '{}' sets shader as well as separate shaders, use one or the other",
+                        panel.source
+                    ));
+                }
+                Some(shader) => ProgramSource::single_file(shader),
+                None => {
+                    let shaders = ProgramSource::new(
+                        panel
+                            .vertex_shader
+                            .as_ref()
+                            .unwrap_or(&options.vertex_shader),
+                        panel
+                            .fragment_shader
+                            .as_ref()
+                            .unwrap_or(&options.fragment_shader),
+                    );
+                    match &panel.geometry_shader {
+                        Some(geometry_shader) => shaders
+                            .with_stage(Stage::Geometry, geometry_shader)
+                            .map_err(|err| err.to_string())?,
+                        None => shaders,
+                    }
+                }
+            };
+            Ok(PanelSettings {
+                source: panel.source.clone(),
+                shaders,
+                objects: panel.objects,
+                particles: panel.particles,
+            })
+        };
+        let layouts = match config {
+            Some(config) if !config.layouts.is_empty() => config
+                .layouts
+                .iter()
+                .map(|layout| {
+                    Ok(LayoutSettings {
+                        name: layout.name.clone(),
+                        arrangement: layout.arrangement,
+                        panels: layout
+                            .panels
+                            .iter()
+                            .map(panel_settings)
+                            .collect::<Result<_, _>>()?,
+                    })
+                })
+                .collect::<Result<_, String>>()?,
+            Some(config) if !config.panels.is_empty() => default_layouts(
+                config
+                    .panels
+                    .iter()
+                    .map(panel_settings)
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => default_layouts(
+                options
+                    .panels
+                    .iter()
+                    .map(|name| {
+                        let mut parts = name.split('+');
+                        let mut panel = PanelSettings {
+                            source: parts.next().unwrap_or_default().to_string(),
+                            shaders: default_shaders.clone(),
+                            objects: false,
+                            particles: false,
+                        };
+                        for overlay in parts {
+                            match overlay {
+                                "objects" => panel.objects = true,
+                                "particles" => panel.particles = true,
+                                _ => return Err(format!("Unknown panel overlay '{}'", overlay)),
+                            }
+                        }
+                        Ok(panel)
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+        };
+
+        if let Some(stage) = stages
+            .iter()
+            .find(|stage| stage.name == RAW_SOURCE || stage.name == OBJECTS_SOURCE)
+        {
+            return Err(format!("Stage name '{}' is reserved", stage.name));
+        }
+        // Panels find stage outputs by name
+        for (i, stage) in stages.iter().enumerate() {
+            if stages[..i].iter().any(|other| other.name == stage.name) {
+                return Err(format!(
+                    "Stage name '{}' is used more than once. Name the stages apart with \
+                     \"name=filter\" or a name in the config.",
+                    stage.name
+                ));
+            }
+        }
+        if layouts.is_empty() {
+            return Err("No panels given".to_string());
+        }
+        for layout in &layouts {
+            if layout.panels.is_empty() {
+                return Err(format!("Layout '{}' has no panels", layout.name));
+            }
+            for panel in &layout.panels {
+                let known = panel.source == RAW_SOURCE
+                    || panel.source == OBJECTS_SOURCE
+                    || stages.iter().any(|stage| stage.name == panel.source);
+                if !known {
+                    return Err(format!("Panel source '{}' is not a filter stage", panel.source));
+                }
+            }
+        }
+
+        let tracker = config
+            .and_then(|config| config.tracker.clone())
+            .unwrap_or_default();
+        let particles = config
+            .and_then(|config| config.particles.clone())
+            .unwrap_or_default();
+
+        Ok(PipelineSettings {
+            source,
+            stages,
+            layouts,
+            tracker,
+            particles,
+        })
+    }
+
+    /// The filter stages whose output is drawn by a layout, or that its particles bounce off
+    fn shown_stages(&self, layout: usize) -> Vec<String> {
+        let panels = &self.layouts[layout].panels;
+        let mut stages: Vec<String> = vec![];
+        for panel in panels {
+            let source = &panel.source;
+            if source != RAW_SOURCE && source != OBJECTS_SOURCE && !stages.contains(source) {
+                stages.push(source.clone());
+            }
+        }
+        if self.particles.collide && panels.iter().any(|panel| panel.particles) {
+            if let Some(mask) = self.mask_stage() {
+                if !stages.iter().any(|stage| stage == mask) {
+                    stages.push(mask.to_string());
+                }
+            }
+        }
+        stages
+    }
+
+    /// The last find_objects stage, whose output is the mask the tracked objects come from
+    fn mask_stage(&self) -> Option<&str> {
+        self.stages
+            .iter()
+            .rev()
+            .find(|stage| matches!(stage.kind, FilterKind::FindObjects(_)))
+            .map(|stage| stage.name.as_str())
+    }
+}
+
+/// The layouts used when the config doesn't list any: every panel in a grid, the first panel on
+/// its own, and the first two side by side and picture in picture
+fn default_layouts(panels: Vec<PanelSettings>) -> Vec<LayoutSettings> {
+    if panels.is_empty() {
+        return vec![];
+    }
+    let mut layouts = vec![
+        LayoutSettings {
+            name: "grid".to_string(),
+            arrangement: Arrangement::Grid {
+                columns: None,
+                rows: None,
+            },
+            panels: panels.clone(),
+        },
+        LayoutSettings {
+            name: "single".to_string(),
+            arrangement: Arrangement::Single,
+            panels: panels[..1].to_vec(),
+        },
+    ];
+    if panels.len() >= 2 {
+        layouts.push(LayoutSettings {
+            name: "side_by_side".to_string(),
+            arrangement: Arrangement::SideBySide,
+            panels: panels[..2].to_vec(),
+        });
+        layouts.push(LayoutSettings {
+            name: "picture_in_picture".to_string(),
+            arrangement: Arrangement::PictureInPicture {
+                corner: Corner::default(),
+                scale: layout::DEFAULT_INSET_SCALE,
+            },
+            panels: panels[..2].to_vec(),
+        });
+    }
+    layouts
+}
+
+/// Prints the objects that entered and exited
+fn report_track_events(events: Vec<TrackEvent>) {
+    for event in events {
+        match event {
+            TrackEvent::Enter(id) => println!("Object {} entered", id),
+            TrackEvent::Exit(id) => println!("Object {} exited", id),
+            TrackEvent::Move { .. } => {}
+        }
+    }
+}
+
+/// Makes sure there is a program for the shaders of every panel in any layout
+fn load_panel_programs<F: Facade>(
+    facade: &F,
+    layouts: &[LayoutSettings],
+    programs: &mut HashMap<ProgramSource, shaders::ProgramHandle>,
+) {
+    for panel in layouts.iter().flat_map(|layout| &layout.panels) {
+        if !programs.contains_key(&panel.shaders) {
+            match shaders::ProgramHandle::load(facade, panel.shaders.clone(), &[]) {
+                Ok(handle) => {
+                    programs.insert(panel.shaders.clone(), handle);
+                }
+                Err(err) => println!("Error loading {:?}: {}", panel.shaders, err),
+            }
+        }
+    }
+}
+
+fn make_square<F: Facade>(
     upper_left: [f32; 2],
     lower_right: [f32; 2],
-    display: &glium::Display,
+    facade: &F,
 ) -> Result<glium::VertexBuffer<Vertex>, glium::vertex::BufferCreationError> {
     let vertex1 = Vertex {
         position: upper_left,
@@ -279,84 +1204,160 @@ fn make_square(
         tex_coords: [1.0, 0.0],
     };
     let shape = vec![vertex1, vertex2, vertex3, vertex4, vertex1, vertex3];
-    glium::VertexBuffer::new(display, &shape)
+    glium::VertexBuffer::new(facade, &shape)
+}
+
+/// The panels of one layout, fitted to the window and the video aspect ratio
+struct Layout {
+    window_size: (u32, u32),
+    video_size: (u32, u32),
+    /// Which of the pipeline's layouts this was built for
+    index: usize,
+    panels: Vec<Panel>,
+}
+
+impl Layout {
+    fn new<F: Facade>(
+        facade: &F,
+        settings: &LayoutSettings,
+        index: usize,
+        window_size: (u32, u32),
+        video_size: (u32, u32),
+    ) -> Result<Layout, glium::vertex::BufferCreationError> {
+        let panels = settings
+            .arrangement
+            .cells(settings.panels.len())
+            .into_iter()
+            .map(|cell| {
+                let (lower_left, upper_right) = layout::letterbox(cell, window_size, video_size);
+                Panel::new(lower_left, upper_right, facade)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Layout {
+            window_size,
+            video_size,
+            index,
+            panels,
+        })
+    }
 }
 
 struct Panel {
     vbo: glium::VertexBuffer<Vertex>,
     indices: glium::index::NoIndices,
+    lower_left: [f32; 2],
+    upper_right: [f32; 2],
 }
 
 impl Panel {
-    fn draw<U>(&self, target: &mut glium::Frame, program: &glium::Program, uniforms: &U)
+    fn new<F: Facade>(
+        lower_left: [f32; 2],
+        upper_right: [f32; 2],
+        facade: &F,
+    ) -> Result<Panel, glium::vertex::BufferCreationError> {
+        Ok(Panel {
+            vbo: make_square(lower_left, upper_right, facade)?,
+            indices: glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+            lower_left,
+            upper_right,
+        })
+    }
+
+    fn area(&self) -> Area {
+        Area {
+            lower_left: self.lower_left,
+            upper_right: self.upper_right,
+        }
+    }
+
+    /// Maps a point in normalized video coordinates to GL coordinates inside this panel
+    fn to_screen(&self, point: [f32; 2]) -> [f32; 2] {
+        // The video is upside down because it goes from top to bottom and GL is from
+        // bottom to top
+        [
+            self.lower_left[0] + point[0] * (self.upper_right[0] - self.lower_left[0]),
+            self.upper_right[1] - point[1] * (self.upper_right[1] - self.lower_left[1]),
+        ]
+    }
+
+    fn draw<S, U>(
+        &self,
+        target: &mut S,
+        program: &glium::Program,
+        uniforms: &U,
+    ) -> Result<(), glium::DrawError>
     where
+        S: Surface,
         U: glium::uniforms::Uniforms,
     {
-        let result = target.draw(
-            &self.vbo,
-            &self.indices,
-            program,
-            uniforms,
-            &Default::default(),
-        );
-        if result.is_err() {
-            println!("Error drawing: {:?}", result.err());
-        }
+        draw_shape(target, &self.vbo, &self.indices, program, uniforms)
     }
 }
 
-struct ImageProcessor {
-    rgb2bgr_ctx: Context,
-    bgr2rgb_ctx: Context,
+fn draw_shape<S, U>(
+    target: &mut S,
+    vbo: &glium::VertexBuffer<Vertex>,
+    indices: &glium::index::NoIndices,
+    program: &glium::Program,
+    uniforms: &U,
+) -> Result<(), glium::DrawError>
+where
+    S: Surface,
+    U: glium::uniforms::Uniforms,
+{
+    target.draw(vbo, indices, program, uniforms, &Default::default())
 }
 
-impl ImageProcessor {
-    fn new(width: u32, height: u32) -> Result<Self, Box<dyn std::error::Error>> {
-        let rgb2bgr_ctx = Context::get(
-            Pixel::RGB24,
-            width,
-            height,
-            Pixel::BGR24,
-            width,
-            height,
-            Flags::BILINEAR,
-        )?;
-        let bgr2rgb_ctx = Context::get(
-            Pixel::BGR24,
-            width,
-            height,
-            Pixel::RGB24,
-            width,
-            height,
-            Flags::BILINEAR,
-        )?;
-        Ok(ImageProcessor {
-            rgb2bgr_ctx,
-            bgr2rgb_ctx,
-        })
-    }
-
-    fn find_components_with_intermediate_frame(
-        &mut self,
-        display: &glium::Display,
-        frame: &ffmpeg::frame::Video,
-    ) -> Result<
-        (glium::texture::Texture2d, Vec<ConnectedComponent>),
-        Box<dyn std::error::Error + 'static>,
-    > {
-        let mut bgr_frame = Video::empty();
-        self.rgb2bgr_ctx.run(&frame, &mut bgr_frame)?;
-
-        let mut components_frame =
-            Video::new(bgr_frame.format(), bgr_frame.width(), bgr_frame.height());
-        let components = find_objects(&bgr_frame, Some(&mut components_frame))?;
-
-        let mut final_frame = Video::empty();
-        self.bgr2rgb_ctx.run(&components_frame, &mut final_frame)?;
-        let image = glium::texture::RawImage2d::from_raw_rgb(
-            final_frame.data(0).to_vec(),
-            (frame.width(), frame.height()),
-        );
-        Ok((glium::texture::Texture2d::new(display, image)?, components))
-    }
+/// Builds the overlay for a tracked object in the given panel. Texture coordinates go from 0
+/// to 1 across the object's bounding box.
+fn object_shape<F: Facade>(
+    object: &TrackedObject,
+    style: ObjectStyle,
+    panel: &Panel,
+    video_size: (u32, u32),
+    facade: &F,
+) -> Result<
+    (glium::VertexBuffer<Vertex>, glium::index::NoIndices),
+    glium::vertex::BufferCreationError,
+> {
+    let [x, y, width, height] = object.bounds();
+    let (video_width, video_height) = (video_size.0 as f32, video_size.1 as f32);
+    let to_vertex = |point: [f32; 2]| Vertex {
+        position: panel.to_screen([point[0] / video_width, point[1] / video_height]),
+        tex_coords: [(point[0] - x) / width, (point[1] - y) / height],
+    };
+    let offset = object.offset();
+    let outline: Vec<[f32; 2]> = match style {
+        ObjectStyle::Box => {
+            let lower_left = panel.to_screen([x / video_width, (y + height) / video_height]);
+            let upper_right = panel.to_screen([(x + width) / video_width, y / video_height]);
+            return Ok((
+                make_square(lower_left, upper_right, facade)?,
+                glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+            ));
+        }
+        ObjectStyle::Contour | ObjectStyle::Hull => {
+            let points = if style == ObjectStyle::Contour {
+                &object.component.contour
+            } else {
+                &object.component.convex_hull
+            };
+            points
+                .iter()
+                .map(|p| [p[0] as f32 + offset.x, p[1] as f32 + offset.y])
+                .collect()
+        }
+        ObjectStyle::Rotated => object
+            .component
+            .min_area_rect
+            .corners()
+            .iter()
+            .map(|p| [p[0] + offset.x, p[1] + offset.y])
+            .collect(),
+    };
+    let vertices: Vec<Vertex> = outline.into_iter().map(to_vertex).collect();
+    Ok((
+        glium::VertexBuffer::new(facade, &vertices)?,
+        glium::index::NoIndices(glium::index::PrimitiveType::LineLoop),
+    ))
 }
@@ -0,0 +1,256 @@
+use ffmpeg::format::Pixel;
+use ffmpeg::util::frame::video::Video;
+use glium::backend::Facade;
+use glium::buffer::ReadError;
+use glium::texture::pixel_buffer::PixelBuffer;
+use glium::texture::{RawImage2d, Texture2d, TextureCreationError};
+use glium::uniforms::MagnifySamplerFilter;
+use glium::Surface;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::*;
+use std::thread::{self, JoinHandle};
+use std::time::*;
+
+use super::encode::{self, VideoEncoder};
+use super::video::TimedFrame;
+
+/// Frames waiting for the recording encoder. If it falls further behind than this, frames are
+/// dropped instead of stalling the render loop.
+const RECORD_QUEUE_FRAMES: usize = 8;
+
+/// A file name in `dir` such as "augment-20210314-153009-250.png", using UTC down to the
+/// millisecond
+pub fn timestamped_path(dir: &Path, prefix: &str, extension: &str) -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let time = secs % 86400;
+    dir.join(format!(
+        "{}-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}.{}",
+        prefix,
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        now.subsec_millis(),
+        extension
+    ))
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's algorithm, counting in 400 year eras that start on March 1st
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Saves an RGBA frame as a PNG without blocking the caller
+pub fn save_screenshot(path: PathBuf, frame: Video) {
+    thread::spawn(move || match encode::save_png(&path, &frame) {
+        Ok(()) => println!("Saved screenshot to {:?}", path),
+        Err(err) => println!("Error saving screenshot to {:?}: {}", path, err),
+    });
+}
+
+/// Copies RGBA pixels read back from GL into a frame, flipping them since GL rows go from
+/// bottom to top
+pub fn image_to_frame(image: &RawImage2d<u8>) -> Video {
+    let mut frame = Video::new(Pixel::RGBA, image.width, image.height);
+    let row_len = image.width as usize * 4;
+    let stride = frame.stride(0);
+    let data = frame.data_mut(0);
+    for (y, row) in image.data.chunks(row_len).rev().enumerate() {
+        data[y * stride..y * stride + row_len].copy_from_slice(row);
+    }
+    frame
+}
+
+/// An offscreen copy of the window. Frames are drawn here and then copied to the window, so
+/// that screenshots and recordings can read them back without touching the front buffer.
+pub struct WindowCapture {
+    texture: Texture2d,
+    /// The last recorded frame and its time, on its way from the texture to a pixel buffer
+    pending: Option<(PixelBuffer<(u8, u8, u8, u8)>, Duration)>,
+}
+
+impl WindowCapture {
+    pub fn new<F: Facade>(facade: &F, size: (u32, u32)) -> Result<Self, TextureCreationError> {
+        Ok(WindowCapture {
+            texture: Texture2d::empty(facade, size.0, size.1)?,
+            pending: None,
+        })
+    }
+
+    /// Makes the texture the size of the window again after it was resized
+    pub fn fit<F: Facade>(
+        &mut self,
+        facade: &F,
+        size: (u32, u32),
+    ) -> Result<(), TextureCreationError> {
+        if self.texture.dimensions() != size {
+            self.texture = Texture2d::empty(facade, size.0, size.1)?;
+        }
+        Ok(())
+    }
+
+    /// Where to draw the frame
+    pub fn surface(&self) -> glium::framebuffer::SimpleFrameBuffer<'_> {
+        self.texture.as_surface()
+    }
+
+    /// Copies the frame to the window
+    pub fn show<S: Surface>(&self, target: &S) {
+        self.texture
+            .as_surface()
+            .fill(target, MagnifySamplerFilter::Nearest);
+    }
+
+    /// Reads back the last frame drawn. This waits for the GPU, so it is for one-off
+    /// screenshots.
+    pub fn screenshot(&self) -> Video {
+        let image: RawImage2d<u8> = self.texture.read();
+        image_to_frame(&image)
+    }
+
+    /// Starts reading the frame just drawn into a pixel buffer, and hands the recorder the
+    /// previous one, which has had a whole frame to arrive without stalling the GPU. `time` is
+    /// when the frame was drawn.
+    pub fn record(&mut self, recorder: &mut Recorder, time: Duration) -> Result<(), ReadError> {
+        let buffer = self.texture.read_to_pixel_buffer();
+        match self.pending.replace((buffer, time)) {
+            Some((previous, time)) => Self::push(recorder, previous, time),
+            None => Ok(()),
+        }
+    }
+
+    /// Hands the recorder the frame that is still being read, then stops it
+    pub fn stop_recording(&mut self, mut recorder: Recorder) -> JoinHandle<()> {
+        if let Some((buffer, time)) = self.pending.take() {
+            if let Err(err) = Self::push(&mut recorder, buffer, time) {
+                println!("Error capturing frame: {:?}", err);
+            }
+        }
+        recorder.stop()
+    }
+
+    fn push(
+        recorder: &mut Recorder,
+        buffer: PixelBuffer<(u8, u8, u8, u8)>,
+        time: Duration,
+    ) -> Result<(), ReadError> {
+        let image: RawImage2d<u8> = buffer.read_as_texture_2d()?;
+        recorder.push(image_to_frame(&image), time);
+        Ok(())
+    }
+}
+
+/// Encodes frames to a video file on a background thread
+pub struct Recorder {
+    path: PathBuf,
+    frames: SyncSender<TimedFrame>,
+    thread: JoinHandle<()>,
+    /// Time of the first frame, which the recording starts from
+    start: Option<Duration>,
+    dropped: u32,
+}
+
+impl Recorder {
+    pub fn start(path: PathBuf, width: u32, height: u32, frame_rate: f64) -> Recorder {
+        let (tx, rx) = sync_channel(RECORD_QUEUE_FRAMES);
+        let thread_path = path.clone();
+        let thread = thread::spawn(move || {
+            // Chroma subsampling needs even dimensions
+            let result = record(&thread_path, width / 2 * 2, height / 2 * 2, frame_rate, rx);
+            match result {
+                Ok(()) => println!("Saved recording to {:?}", thread_path),
+                Err(err) => println!("Error recording to {:?}: {}", thread_path, err),
+            }
+        });
+        println!("Recording to {:?}", path);
+        Recorder {
+            path,
+            frames: tx,
+            thread,
+            start: None,
+            dropped: 0,
+        }
+    }
+
+    /// Queues an RGBA frame drawn at `time`. The recording is timed from its first frame, so
+    /// that it plays back at the pace the frames were drawn.
+    pub fn push(&mut self, frame: Video, time: Duration) {
+        let start = *self.start.get_or_insert(time);
+        let timed = TimedFrame {
+            frame,
+            timestamp: time.saturating_sub(start),
+        };
+        // If the encoder thread is gone it has already printed why
+        if let Err(TrySendError::Full(_)) = self.frames.try_send(timed) {
+            self.dropped += 1;
+        }
+    }
+
+    /// Lets the encoder finish the file in the background. Join the handle to wait for it.
+    pub fn stop(self) -> JoinHandle<()> {
+        if self.dropped > 0 {
+            println!(
+                "Dropped {} frames recording to {:?} because the encoder could not keep up",
+                self.dropped, self.path
+            );
+        }
+        drop(self.frames);
+        self.thread
+    }
+}
+
+fn record(
+    path: &Path,
+    width: u32,
+    height: u32,
+    frame_rate: f64,
+    frames: Receiver<TimedFrame>,
+) -> Result<(), Box<dyn Error>> {
+    let mut encoder = VideoEncoder::create(path, width, height, frame_rate, None)?;
+    for frame in frames {
+        encoder.push(&frame.frame, frame.timestamp)?;
+    }
+    encoder.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_from_days_counts_from_the_epoch() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(18_700), (2021, 3, 14));
+        assert_eq!(civil_from_days(20_088), (2024, 12, 31));
+    }
+
+    #[test]
+    fn civil_from_days_handles_leap_years() {
+        // Divisible by 400, so 2000 has a leap day
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        // Divisible by 100 but not 400, so 2100 and 1900 don't
+        assert_eq!(civil_from_days(47_540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+        assert_eq!(civil_from_days(-25_508), (1900, 3, 1));
+    }
+}
@@ -0,0 +1,70 @@
+use std::time::{Duration, Instant};
+
+/// What the decoder should do with a frame given the current playback position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// The frame is early and should be presented after sleeping this long
+    Wait(Duration),
+    /// The frame should have been presented this long ago, in stream time
+    Late(Duration),
+}
+
+/// Maps stream presentation timestamps onto the wall clock
+#[derive(Debug)]
+pub struct PlaybackClock {
+    /// Wall clock time at which `origin` was presented
+    start: Instant,
+    origin: Option<Duration>,
+    rate: f64,
+}
+
+impl PlaybackClock {
+    pub fn new() -> PlaybackClock {
+        PlaybackClock {
+            start: Instant::now(),
+            origin: None,
+            rate: 1.0,
+        }
+    }
+
+    /// Restart the clock so that `pts` is presented right now
+    pub fn reset(&mut self, pts: Duration) {
+        self.start = Instant::now();
+        self.origin = Some(pts);
+    }
+
+    /// Forget the current position. The next scheduled frame will be presented immediately.
+    pub fn clear(&mut self) {
+        self.origin = None;
+    }
+
+    pub fn set_rate(&mut self, rate: f64) {
+        // Re-anchor so that the position doesn't jump when the rate changes
+        if let Some(position) = self.position() {
+            self.reset(position);
+        }
+        self.rate = rate;
+    }
+
+    /// The stream time that should currently be on screen
+    pub fn position(&self) -> Option<Duration> {
+        self.origin
+            .map(|origin| origin + self.start.elapsed().mul_f64(self.rate))
+    }
+
+    pub fn schedule(&mut self, pts: Duration) -> Schedule {
+        let position = match self.position() {
+            Some(position) => position,
+            None => {
+                // The first frame defines the start of playback
+                self.reset(pts);
+                return Schedule::Wait(Duration::from_secs(0));
+            }
+        };
+        if pts >= position {
+            Schedule::Wait((pts - position).div_f64(self.rate))
+        } else {
+            Schedule::Late(position - pts)
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    time::Duration,
+};
+
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+
+use super::emitters::EmitterParams;
+use super::filters::{FilterKind, StageSpec};
+use super::layout::Arrangement;
+use super::tracker::TrackerParams;
+
+/// The augment pipeline as described by a TOML file
+///
+/// ```toml
+/// source = "Bliss Dance - Nicky Evers.mp4"
+///
+/// [[stages]]
+/// name = "mask"
+/// filter = "find_objects"
+/// threshold = 230.0
+///
+/// [[panels]]
+/// source = "raw"
+///
+/// [[panels]]
+/// source = "mask"
+/// fragment_shader = "shaders/video.frag"
+///
+/// [[layouts]]
+/// name = "compare"
+/// arrangement = "picture_in_picture"
+/// corner = "upper_right"
+/// panels = [{ source = "raw", objects = true }, { source = "mask" }]
+///
+/// [tracker]
+/// matching = "centroid"
+/// despawn_frames = 15
+///
+/// [particles]
+/// shape = "box"
+/// scale_by = "speed"
+///
+/// [[particles.forces]]
+/// force = "vortex"
+/// center = [0.0, 0.0]
+/// strength = 0.2
+/// radius = 0.1
+/// ```
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Same format as the source on the command line
+    pub source: Option<String>,
+    #[serde(default)]
+    pub stages: Vec<StageConfig>,
+    /// Shown in a grid, on their own, side by side and picture in picture when there are no
+    /// layouts
+    #[serde(default)]
+    pub panels: Vec<PanelConfig>,
+    /// Cycled through with S, starting with the first
+    #[serde(default)]
+    pub layouts: Vec<LayoutConfig>,
+    /// How detected objects are followed from frame to frame
+    pub tracker: Option<TrackerParams>,
+    /// The particles tracked objects give off, on panels that show them
+    pub particles: Option<EmitterParams>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StageConfig {
+    /// Defaults to the name of the filter
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub filter: FilterKind,
+}
+
+impl StageConfig {
+    pub fn to_spec(&self) -> StageSpec {
+        StageSpec {
+            name: self
+                .name
+                .clone()
+                .unwrap_or_else(|| self.filter.name().to_string()),
+            kind: self.filter.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PanelConfig {
+    /// "raw", "objects" or the name of a filter stage
+    pub source: String,
+    /// Defaults to the shaders given on the command line
+    pub vertex_shader: Option<PathBuf>,
+    pub fragment_shader: Option<PathBuf>,
+    pub geometry_shader: Option<PathBuf>,
+    /// A single file with a `#pragma stage` line before each stage, used instead of the
+    /// separate shaders
+    pub shader: Option<PathBuf>,
+    /// Draw the tracked objects over this panel
+    #[serde(default)]
+    pub objects: bool,
+    /// Draw the particles given off by the tracked objects over this panel
+    #[serde(default)]
+    pub particles: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LayoutConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub arrangement: Arrangement,
+    pub panels: Vec<PanelConfig>,
+}
+
+pub fn load_config(path: &Path) -> Result<PipelineConfig, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(toml::from_str(&text)?)
+}
+
+/// Keeps a pipeline config up to date with the file it was loaded from
+pub struct ConfigHandle {
+    path: PathBuf,
+    watcher: RecommendedWatcher,
+    listener: Receiver<DebouncedEvent>,
+    config: PipelineConfig,
+}
+
+impl std::fmt::Debug for ConfigHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ConfigHandle({:?})", self.path)
+    }
+}
+
+impl ConfigHandle {
+    pub fn new(path: &Path) -> Result<ConfigHandle, Box<dyn Error>> {
+        let config = load_config(path)?;
+        let (tx, rx) = channel();
+        let mut watcher = watcher(tx, Duration::from_millis(50))?;
+        watcher.watch(path, RecursiveMode::NonRecursive)?;
+        Ok(ConfigHandle {
+            path: path.to_path_buf(),
+            watcher,
+            listener: rx,
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &PipelineConfig {
+        &self.config
+    }
+
+    /// Returns the new config if the file changed. If the file can't be parsed the error is
+    /// printed and the previous config stays in place.
+    pub fn poll(&mut self) -> Option<&PipelineConfig> {
+        if self.listener.try_iter().count() == 0 {
+            return None;
+        }
+        // Editors that save by replacing the file drop the watch
+        let _ = self.watcher.watch(&self.path, RecursiveMode::NonRecursive);
+        match load_config(&self.path) {
+            Ok(config) if config != self.config => {
+                self.config = config;
+                Some(&self.config)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                println!(
+                    "Error loading {:?}, keeping the previous config: {}",
+                    self.path, err
+                );
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use std::rc::Rc;
+
+use cgmath::{InnerSpace, Vector2};
+use glium::backend::Facade;
+use glium::Surface;
+use serde::Deserialize;
+
+use super::error::AugmentError;
+use super::tracker::TrackedObject;
+use crate::particles::{
+    self, Backend, CollisionMask, Contour, Curve, EmitterShape, Force, Interpolation,
+    ParticlePrograms, ParticleSystem, ParticleSystemRunner,
+};
+
+/// Where on a tracked object particles appear
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    /// The object's center
+    Point,
+    /// Anywhere in its bounding box
+    Box,
+    /// Anywhere on its outline
+    Contour,
+}
+
+/// What an object's emission rate is proportional to
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateScale {
+    /// Nothing, every object emits `rate` particles per second
+    Constant,
+    /// The object's area, at `rate` per 1000 square pixels
+    Area,
+    /// The object's speed, at `rate` per 100 pixels per second
+    Speed,
+}
+
+/// A force field that moves the particles, with the same fields as `particles::Force`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "force", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ForceConfig {
+    Attractor {
+        position: [f32; 2],
+        /// Negative to push particles away
+        strength: f32,
+        radius: f32,
+    },
+    Vortex {
+        center: [f32; 2],
+        strength: f32,
+        radius: f32,
+    },
+    Turbulence {
+        strength: f32,
+        scale: f32,
+        speed: f32,
+        /// Picks the flow, so the same seed always moves particles the same way
+        #[serde(default)]
+        seed: u32,
+    },
+    Wind {
+        velocity: [f32; 2],
+        strength: f32,
+    },
+}
+
+impl ForceConfig {
+    fn to_force(&self) -> Force {
+        match *self {
+            ForceConfig::Attractor {
+                position,
+                strength,
+                radius,
+            } => Force::Attractor {
+                position: position.into(),
+                strength,
+                radius,
+            },
+            ForceConfig::Vortex {
+                center,
+                strength,
+                radius,
+            } => Force::Vortex {
+                center: center.into(),
+                strength,
+                radius,
+            },
+            ForceConfig::Turbulence {
+                strength,
+                scale,
+                speed,
+                seed,
+            } => Force::Turbulence {
+                strength,
+                scale,
+                speed,
+                seed,
+            },
+            ForceConfig::Wind { velocity, strength } => Force::Wind {
+                velocity: velocity.into(),
+                strength,
+            },
+        }
+    }
+}
+
+/// The particles given off by every tracked object. Positions and velocities are in panel
+/// coordinates, which go from -1 to 1 across the video both ways, and sizes are in pixels.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmitterParams {
+    pub shape: Shape,
+    pub scale_by: RateScale,
+    pub rate: f32,
+    /// Particles alive at once for each object, past which new ones are dropped
+    pub max_particles: usize,
+    /// Seconds each particle lives
+    pub lifetime: f32,
+    pub velocity: [f32; 2],
+    /// New particles leave up to this many radians either side of `velocity`
+    pub spread: f32,
+    /// Fraction of the object's own velocity that new particles get on top of `velocity`
+    pub inherit_velocity: f32,
+    pub gravity: [f32; 2],
+    /// Fraction of its velocity a particle loses every second
+    pub drag: f32,
+    /// Diameter when a particle is emitted and when it dies
+    pub size: [f32; 2],
+    /// RGBA when a particle is emitted and when it dies
+    pub color: [[f32; 4]; 2],
+    /// Seconds an object's particles take to fade away once it is no longer tracked
+    pub fade_out: f32,
+    pub forces: Vec<ForceConfig>,
+    /// Bounce off the mask of the last find_objects stage
+    pub collide: bool,
+    /// Fraction of a particle's speed into the mask that it bounces back with
+    pub bounce: f32,
+    /// Fraction of a particle's speed along the mask that it loses when it hits
+    pub friction: f32,
+}
+
+impl Default for EmitterParams {
+    fn default() -> Self {
+        EmitterParams {
+            shape: Shape::Contour,
+            scale_by: RateScale::Area,
+            rate: 20.0,
+            max_particles: 1000,
+            lifetime: 1.5,
+            velocity: [0.0, 0.1],
+            spread: std::f32::consts::PI,
+            inherit_velocity: 0.5,
+            gravity: [0.0, 0.2],
+            drag: 0.5,
+            size: [6.0, 2.0],
+            color: [[1.0, 0.8, 0.3, 1.0], [1.0, 0.2, 0.1, 0.0]],
+            fade_out: 1.0,
+            forces: vec![],
+            collide: false,
+            bounce: 0.3,
+            friction: 0.2,
+        }
+    }
+}
+
+struct ObjectEmitter {
+    /// The tracked object's id
+    id: u64,
+    runner: ParticleSystemRunner,
+    /// 1 while the object is tracked, then down to 0 as it fades away
+    fade: f32,
+}
+
+/// A particle system for each tracked object, which follows it around and fades away after it
+/// is gone
+pub struct ObjectEmitters {
+    emitters: Vec<ObjectEmitter>,
+    /// Shared by every emitter
+    programs: ParticlePrograms,
+}
+
+impl ObjectEmitters {
+    pub fn new<F: Facade>(facade: &F) -> Result<ObjectEmitters, AugmentError> {
+        Ok(ObjectEmitters {
+            emitters: vec![],
+            programs: ParticlePrograms::new(facade, Backend::Cpu)?,
+        })
+    }
+
+    pub fn poll_programs<F: Facade>(&mut self, facade: &F) {
+        self.programs.poll(facade);
+    }
+
+    /// Moves the emitters to where the objects are now and advances their particles by `delta`
+    /// seconds. Objects without an emitter get one. Particles bounce off `mask` when the params
+    /// ask for it.
+    pub fn update<'a, F, I>(
+        &mut self,
+        facade: &F,
+        params: &EmitterParams,
+        objects: I,
+        mask: Option<Rc<CollisionMask>>,
+        video_size: (u32, u32),
+        delta: f32,
+    ) -> Result<(), AugmentError>
+    where
+        F: Facade,
+        I: IntoIterator<Item = &'a TrackedObject>,
+    {
+        let mask = mask.filter(|_| params.collide);
+        let mut seen = vec![];
+        for object in objects {
+            seen.push(object.id);
+            let index = match self.emitters.iter().position(|e| e.id == object.id) {
+                Some(index) => index,
+                None => {
+                    let mut system = ParticleSystem::new();
+                    system
+                        .set_num_particles(params.max_particles)
+                        // Each object gets different particles, but the same ones every run.
+                        // A system also uses the seeds just above its own, so consecutive ids
+                        // are scattered rather than used as they are.
+                        .set_seed(particles::hash(object.id as u32));
+                    self.emitters.push(ObjectEmitter {
+                        id: object.id,
+                        runner: ParticleSystemRunner::new(facade, system, Backend::Cpu)?,
+                        fade: 1.0,
+                    });
+                    self.emitters.len() - 1
+                }
+            };
+            let emitter = &mut self.emitters[index];
+            emitter.fade = 1.0;
+            let system = emitter.runner.system_mut();
+            configure(system, params, &mask);
+            follow(system, params, object, video_size);
+        }
+
+        let fade_step = if params.fade_out > 0.0 {
+            delta / params.fade_out
+        } else {
+            1.0
+        };
+        for emitter in self.emitters.iter_mut() {
+            if seen.contains(&emitter.id) {
+                continue;
+            }
+            emitter.fade -= fade_step;
+            let system = emitter.runner.system_mut();
+            configure(system, params, &mask);
+            system
+                .set_emission_rate(0.0)
+                .set_fade(emitter.fade.max(0.0));
+        }
+        self.emitters.retain(|emitter| emitter.fade > 0.0);
+
+        for emitter in self.emitters.iter_mut() {
+            if emitter.runner.num_particles() != params.max_particles {
+                emitter
+                    .runner
+                    .set_num_particles(facade, params.max_particles)?;
+            }
+            emitter.runner.update(facade, &self.programs, delta)?;
+        }
+        Ok(())
+    }
+
+    /// Draws every object's particles into a panel, given by its corners in GL coordinates
+    pub fn draw<S: Surface>(
+        &mut self,
+        target: &mut S,
+        lower_left: [f32; 2],
+        upper_right: [f32; 2],
+    ) -> Result<(), AugmentError> {
+        for emitter in self.emitters.iter_mut() {
+            emitter
+                .runner
+                .draw_in(&self.programs, target, lower_left, upper_right)?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies the parts of the params that are the same for every object, so that a reloaded
+/// config takes effect on the emitters that already exist
+fn configure(
+    system: &mut ParticleSystem,
+    params: &EmitterParams,
+    mask: &Option<Rc<CollisionMask>>,
+) {
+    let [start_color, end_color] = params.color;
+    system
+        .set_lifetime(params.lifetime)
+        .set_spread(params.spread)
+        .set_gravity(params.gravity.into())
+        .set_drag(params.drag)
+        .set_size(Curve::linear(params.size[0], params.size[1]))
+        .set_color(Curve::constant(start_color).add(1.0, end_color, Interpolation::Linear))
+        .set_forces(params.forces.iter().map(ForceConfig::to_force).collect())
+        .set_mask(mask.clone())
+        .set_bounce(params.bounce)
+        .set_friction(params.friction);
+}
+
+/// Moves an emitter onto its object and sets how much it emits
+fn follow(
+    system: &mut ParticleSystem,
+    params: &EmitterParams,
+    object: &TrackedObject,
+    video_size: (u32, u32),
+) {
+    // Pixels go from the top left and panel coordinates from -1 to 1 upwards
+    let (width, height) = (video_size.0 as f32, video_size.1 as f32);
+    let to_panel = |v: Vector2<f32>| Vector2::new(v.x * 2.0 / width, -v.y * 2.0 / height);
+    let position = object.position();
+    let velocity = object.velocity();
+    let component = &object.component;
+
+    let shape = match params.shape {
+        Shape::Point => EmitterShape::Point,
+        Shape::Box => EmitterShape::Box(Vector2::new(
+            component.width as f32 / width,
+            component.height as f32 / height,
+        )),
+        Shape::Contour => {
+            let [cx, cy] = component.centroid;
+            EmitterShape::Contour(Contour::new(
+                component
+                    .contour
+                    .iter()
+                    .map(|p| to_panel(Vector2::new(p[0] as f32 - cx, p[1] as f32 - cy)))
+                    .collect(),
+            ))
+        }
+    };
+    let rate = match params.scale_by {
+        RateScale::Constant => params.rate,
+        RateScale::Area => params.rate * component.area as f32 / 1000.0,
+        RateScale::Speed => params.rate * velocity.magnitude() / 100.0,
+    };
+    let launch = Vector2::from(params.velocity) + to_panel(velocity) * params.inherit_velocity;
+    system
+        .set_position(Vector2::new(
+            position.x * 2.0 / width - 1.0,
+            1.0 - position.y * 2.0 / height,
+        ))
+        .set_shape(shape)
+        .set_velocity(launch)
+        .set_emission_rate(rate)
+        .set_fade(1.0);
+}
@@ -0,0 +1,358 @@
+use ffmpeg::format::{self, context, Pixel};
+use ffmpeg::media::Type;
+use ffmpeg::software::scaling::{context::Context, flag::Flags};
+use ffmpeg::util::frame::video::Video;
+use ffmpeg::{codec, encoder, Dictionary, Packet, Rational, Rescale};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::time::*;
+
+/// Frames are stamped in milliseconds so that variable frame rates survive encoding
+const TIME_BASE: Rational = Rational(1, 1000);
+
+/// Why a video file couldn't be started
+#[derive(Debug)]
+pub enum EncodeError {
+    Ffmpeg(ffmpeg::Error),
+    /// The source's audio can't go in the container without re-encoding it
+    UnsupportedAudio {
+        codec: codec::Id,
+        extension: String,
+    },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Ffmpeg(err) => write!(f, "{}", err),
+            EncodeError::UnsupportedAudio { codec, extension } => write!(
+                f,
+                "Can't copy {:?} audio into a .{} file. Write an .mp4 or .mkv file instead.",
+                codec, extension
+            ),
+        }
+    }
+}
+
+impl Error for EncodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EncodeError::Ffmpeg(err) => Some(err),
+            EncodeError::UnsupportedAudio { .. } => None,
+        }
+    }
+}
+
+impl From<ffmpeg::Error> for EncodeError {
+    fn from(err: ffmpeg::Error) -> Self {
+        EncodeError::Ffmpeg(err)
+    }
+}
+
+/// Encodes RGBA frames to a video file, copying over the audio of the source if there is one
+pub struct VideoEncoder {
+    octx: context::Output,
+    encoder: encoder::video::Encoder,
+    scaler: Context,
+    /// Size of the frames the scaler expects, which can change when the window is resized
+    input_size: (u32, u32),
+    size: (u32, u32),
+    stream_index: usize,
+    stream_time_base: Rational,
+    audio: Option<AudioCopy>,
+    last_pts: Option<i64>,
+}
+
+impl VideoEncoder {
+    /// The container comes from the extension of `path`. WebM files are encoded with VP9 and
+    /// everything else with H.264. The width and height have to be even. Fails if the audio of
+    /// `audio_source` can't go in the container as it is.
+    pub fn create(
+        path: &Path,
+        width: u32,
+        height: u32,
+        frame_rate: f64,
+        audio_source: Option<&Path>,
+    ) -> Result<VideoEncoder, EncodeError> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .unwrap_or_default();
+        let codec_id = if extension == "webm" {
+            codec::Id::VP9
+        } else {
+            codec::Id::H264
+        };
+        let codec = encoder::find(codec_id).ok_or(ffmpeg::Error::EncoderNotFound)?;
+
+        let mut octx = format::output(&path)?;
+        let global_header = octx
+            .format()
+            .flags()
+            .contains(format::flag::Flags::GLOBAL_HEADER);
+
+        let (stream_index, encoder) = {
+            let mut ost = octx.add_stream(codec)?;
+            let mut video = ost.codec().encoder().video()?;
+            video.set_width(width);
+            video.set_height(height);
+            video.set_format(Pixel::YUV420P);
+            video.set_time_base(TIME_BASE);
+            video.set_frame_rate(Some(Rational::from(frame_rate)));
+            if global_header {
+                video.set_flags(codec::flag::Flags::GLOBAL_HEADER);
+            }
+            let mut options = Dictionary::new();
+            if codec_id == codec::Id::VP9 {
+                // Constant quality, the default bitrate for VP9 is very low
+                options.set("crf", "32");
+                options.set("b", "0");
+            } else {
+                options.set("preset", "medium");
+            }
+            let encoder = video.open_as_with(codec, options)?;
+            ost.set_parameters(&encoder);
+            (ost.index(), encoder)
+        };
+
+        let mut audio = match audio_source {
+            Some(source) => AudioCopy::open(source, &mut octx, &extension)?,
+            None => None,
+        };
+
+        octx.write_header()?;
+        // The muxer is free to pick its own time bases when writing the header
+        let stream_time_base = octx.stream(stream_index).unwrap().time_base();
+        if let Some(audio) = audio.as_mut() {
+            audio.out_time_base = octx.stream(audio.out_index).unwrap().time_base();
+        }
+
+        let scaler = Context::get(
+            Pixel::RGBA,
+            width,
+            height,
+            Pixel::YUV420P,
+            width,
+            height,
+            Flags::BILINEAR,
+        )?;
+
+        Ok(VideoEncoder {
+            octx,
+            encoder,
+            scaler,
+            input_size: (width, height),
+            size: (width, height),
+            stream_index,
+            stream_time_base,
+            audio,
+            last_pts: None,
+        })
+    }
+
+    /// Adds an RGBA frame, which is scaled to the size of the video if needed. Frames that
+    /// don't come after the previous one are skipped.
+    pub fn push(&mut self, frame: &Video, timestamp: Duration) -> Result<(), ffmpeg::Error> {
+        let pts = timestamp.as_millis() as i64;
+        if self.last_pts.map_or(false, |last| pts <= last) {
+            return Ok(());
+        }
+        self.last_pts = Some(pts);
+
+        if self.input_size != (frame.width(), frame.height()) {
+            self.input_size = (frame.width(), frame.height());
+            self.scaler = Context::get(
+                Pixel::RGBA,
+                frame.width(),
+                frame.height(),
+                Pixel::YUV420P,
+                self.size.0,
+                self.size.1,
+                Flags::BILINEAR,
+            )?;
+        }
+
+        let mut yuv = Video::empty();
+        self.scaler.run(frame, &mut yuv)?;
+        yuv.set_pts(Some(pts));
+        self.encoder.send_frame(&yuv)?;
+        self.write_packets()
+    }
+
+    /// Flushes the encoder and finishes the file
+    pub fn finish(mut self) -> Result<(), ffmpeg::Error> {
+        self.encoder.send_eof()?;
+        self.write_packets()?;
+        if let (Some(audio), Some(pts)) = (self.audio.as_mut(), self.last_pts) {
+            audio.write_until(&mut self.octx, Duration::from_millis(pts as u64))?;
+        }
+        self.octx.write_trailer()
+    }
+
+    fn write_packets(&mut self) -> Result<(), ffmpeg::Error> {
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            // Keep the audio just ahead of the video so the muxer doesn't have to buffer much
+            if let (Some(audio), Some(pts)) = (self.audio.as_mut(), packet.pts()) {
+                audio.write_until(&mut self.octx, Duration::from_millis(pts.max(0) as u64))?;
+            }
+            packet.set_stream(self.stream_index);
+            packet.rescale_ts(TIME_BASE, self.stream_time_base);
+            packet.write_interleaved(&mut self.octx)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a single RGBA frame as a PNG image
+pub fn save_png(path: &Path, frame: &Video) -> Result<(), ffmpeg::Error> {
+    let codec = encoder::find(codec::Id::PNG).ok_or(ffmpeg::Error::EncoderNotFound)?;
+    // Picks the image2 muxer from the extension
+    let mut octx = format::output(&path)?;
+    let (stream_index, mut encoder) = {
+        let mut ost = octx.add_stream(codec)?;
+        let mut image = ost.codec().encoder().video()?;
+        image.set_width(frame.width());
+        image.set_height(frame.height());
+        image.set_format(Pixel::RGBA);
+        image.set_time_base(TIME_BASE);
+        let encoder = image.open_as(codec)?;
+        ost.set_parameters(&encoder);
+        (ost.index(), encoder)
+    };
+    octx.write_header()?;
+
+    let mut frame = frame.clone();
+    frame.set_pts(Some(0));
+    encoder.send_frame(&frame)?;
+    encoder.send_eof()?;
+    let mut packet = Packet::empty();
+    while encoder.receive_packet(&mut packet).is_ok() {
+        packet.set_stream(stream_index);
+        packet.write_interleaved(&mut octx)?;
+    }
+    octx.write_trailer()
+}
+
+/// Remuxes the audio of the source without re-encoding it
+struct AudioCopy {
+    ictx: context::Input,
+    in_index: usize,
+    out_index: usize,
+    in_time_base: Rational,
+    out_time_base: Rational,
+    /// Audio timestamp of the first video frame, since video timestamps start from zero
+    start: i64,
+    /// Read past the point that has been written so far
+    pending: Option<Packet>,
+    eof: bool,
+}
+
+impl AudioCopy {
+    /// Returns None if the source has no audio
+    fn open(
+        path: &Path,
+        octx: &mut context::Output,
+        extension: &str,
+    ) -> Result<Option<AudioCopy>, EncodeError> {
+        let ictx = format::input(&path)?;
+        let (in_index, in_time_base, parameters) = match ictx.streams().best(Type::Audio) {
+            Some(stream) => (stream.index(), stream.time_base(), stream.parameters()),
+            None => return Ok(None),
+        };
+        if !accepts_audio(extension, parameters.id()) {
+            return Err(EncodeError::UnsupportedAudio {
+                codec: parameters.id(),
+                extension: extension.to_string(),
+            });
+        }
+        let start = match ictx.streams().best(Type::Video) {
+            Some(video) if video.start_time() != ffmpeg::ffi::AV_NOPTS_VALUE => {
+                video.start_time().rescale(video.time_base(), in_time_base)
+            }
+            _ => 0,
+        };
+
+        let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
+        ost.set_parameters(parameters);
+        // The tag is specific to the source container
+        unsafe {
+            (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        let out_index = ost.index();
+
+        Ok(Some(AudioCopy {
+            ictx,
+            in_index,
+            out_index,
+            in_time_base,
+            out_time_base: in_time_base,
+            start,
+            pending: None,
+            eof: false,
+        }))
+    }
+
+    /// Writes all audio up to `until`, measured like the video timestamps
+    fn write_until(
+        &mut self,
+        octx: &mut context::Output,
+        until: Duration,
+    ) -> Result<(), ffmpeg::Error> {
+        let until = (until.as_micros() as i64).rescale(Rational(1, 1_000_000), self.in_time_base)
+            + self.start;
+        loop {
+            if self.pending.is_none() {
+                if self.eof {
+                    return Ok(());
+                }
+                let mut packet = Packet::empty();
+                match packet.read(&mut self.ictx) {
+                    Ok(()) if packet.stream() == self.in_index => self.pending = Some(packet),
+                    Ok(()) => continue,
+                    Err(ffmpeg::Error::Eof) => {
+                        self.eof = true;
+                        return Ok(());
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+
+            let packet = self.pending.as_ref().unwrap();
+            let ts = packet.pts().or_else(|| packet.dts()).unwrap_or(self.start);
+            if ts > until {
+                return Ok(());
+            }
+            let mut packet = self.pending.take().unwrap();
+            // Audio from before the first video frame is dropped
+            if ts < self.start {
+                continue;
+            }
+            packet.set_pts(packet.pts().map(|pts| pts - self.start));
+            packet.set_dts(packet.dts().map(|dts| dts - self.start));
+            packet.rescale_ts(self.in_time_base, self.out_time_base);
+            packet.set_position(-1);
+            packet.set_stream(self.out_index);
+            packet.write_interleaved(octx)?;
+        }
+    }
+}
+
+/// Audio codecs that each container can hold
+fn accepts_audio(extension: &str, codec: codec::Id) -> bool {
+    match extension {
+        "webm" => matches!(codec, codec::Id::OPUS | codec::Id::VORBIS),
+        "mp4" | "m4v" | "mov" => matches!(
+            codec,
+            codec::Id::AAC
+                | codec::Id::MP3
+                | codec::Id::AC3
+                | codec::Id::OPUS
+                | codec::Id::FLAC
+                | codec::Id::ALAC
+        ),
+        _ => true,
+    }
+}
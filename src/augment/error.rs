@@ -0,0 +1,100 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use super::shaders::ShaderMessage;
+
+/// Everything that can go wrong while decoding, filtering and drawing video
+#[derive(Debug)]
+pub enum AugmentError {
+    /// Opening, reading or decoding the source
+    Decode(ffmpeg::Error),
+    /// Converting decoded frames to RGB at the output size
+    Scale(ffmpeg::Error),
+    /// A filter stage failed
+    OpenCv(opencv::Error),
+    /// A shader couldn't be read, compiled or linked. Never empty.
+    Shader(Vec<ShaderMessage>),
+    /// Creating a buffer or texture, or drawing with it
+    Gl(String),
+    Io(io::Error),
+}
+
+impl AugmentError {
+    /// A nicer message than ffmpeg's when a file or device isn't there at all
+    pub fn not_found(path: &Path) -> AugmentError {
+        AugmentError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", path.display()),
+        ))
+    }
+}
+
+impl fmt::Display for AugmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AugmentError::Decode(err) => write!(f, "Error decoding video: {}", err),
+            AugmentError::Scale(err) => write!(f, "Error scaling video: {}", err),
+            AugmentError::OpenCv(err) => write!(f, "Error filtering video: {}", err),
+            AugmentError::Shader(messages) => {
+                write!(f, "Shader error:")?;
+                for message in messages {
+                    write!(f, "\n{}", message)?;
+                }
+                Ok(())
+            }
+            AugmentError::Gl(message) => write!(f, "GL error: {}", message),
+            AugmentError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for AugmentError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AugmentError::Decode(err) | AugmentError::Scale(err) => Some(err),
+            AugmentError::OpenCv(err) => Some(err),
+            AugmentError::Io(err) => Some(err),
+            AugmentError::Shader(_) | AugmentError::Gl(_) => None,
+        }
+    }
+}
+
+/// ffmpeg errors are decoding errors unless they came from the scaler, which converts them
+/// explicitly
+impl From<ffmpeg::Error> for AugmentError {
+    fn from(err: ffmpeg::Error) -> Self {
+        AugmentError::Decode(err)
+    }
+}
+
+impl From<opencv::Error> for AugmentError {
+    fn from(err: opencv::Error) -> Self {
+        AugmentError::OpenCv(err)
+    }
+}
+
+impl From<io::Error> for AugmentError {
+    fn from(err: io::Error) -> Self {
+        AugmentError::Io(err)
+    }
+}
+
+impl From<glium::vertex::BufferCreationError> for AugmentError {
+    fn from(err: glium::vertex::BufferCreationError) -> Self {
+        AugmentError::Gl(err.to_string())
+    }
+}
+
+impl From<glium::texture::TextureCreationError> for AugmentError {
+    fn from(err: glium::texture::TextureCreationError) -> Self {
+        AugmentError::Gl(err.to_string())
+    }
+}
+
+impl From<glium::DrawError> for AugmentError {
+    fn from(err: glium::DrawError) -> Self {
+        AugmentError::Gl(err.to_string())
+    }
+}
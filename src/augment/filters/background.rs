@@ -0,0 +1,167 @@
+use opencv::{
+    core::{Mat, Ptr},
+    video::{self, BackgroundSubtractor as _, BackgroundSubtractorKNN, BackgroundSubtractorMOG2},
+};
+use serde::Deserialize;
+
+use crate::augment::error::AugmentError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Mog2,
+    Knn,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackgroundSubtractorParams {
+    pub backend: Backend,
+    /// Number of frames that make up the background model
+    pub history: i32,
+    /// MOG2's varThreshold or KNN's dist2Threshold. Defaults to OpenCV's default for the
+    /// backend, as the two are on very different scales.
+    pub var_threshold: Option<f64>,
+    /// Mark shadows with 127 in the foreground mask instead of treating them as foreground
+    pub detect_shadows: bool,
+    /// How fast the model adapts, between 0 and 1. Negative lets OpenCV pick it from the history.
+    pub learning_rate: f64,
+}
+
+impl BackgroundSubtractorParams {
+    pub fn new(backend: Backend) -> BackgroundSubtractorParams {
+        BackgroundSubtractorParams {
+            backend,
+            history: 500,
+            var_threshold: None,
+            detect_shadows: false,
+            learning_rate: -1.0,
+        }
+    }
+
+    pub fn var_threshold(&self) -> f64 {
+        self.var_threshold.unwrap_or(match self.backend {
+            Backend::Mog2 => 16.0,
+            Backend::Knn => 400.0,
+        })
+    }
+}
+
+impl Default for BackgroundSubtractorParams {
+    fn default() -> Self {
+        BackgroundSubtractorParams::new(Backend::Mog2)
+    }
+}
+
+enum Model {
+    Mog2(Ptr<dyn BackgroundSubtractorMOG2>),
+    Knn(Ptr<dyn BackgroundSubtractorKNN>),
+}
+
+/// A background model that learns from every frame it is given
+pub struct BackgroundSubtractor {
+    params: BackgroundSubtractorParams,
+    model: Model,
+}
+
+// The models are plain OpenCV objects that are only used by one thread at a time. They are
+// created on the filter thread, but the stage specs they live in are sent there.
+unsafe impl Send for BackgroundSubtractor {}
+
+impl BackgroundSubtractor {
+    pub fn new(params: &BackgroundSubtractorParams) -> Result<Self, AugmentError> {
+        let model = match params.backend {
+            Backend::Mog2 => Model::Mog2(video::create_background_subtractor_mog2(
+                params.history,
+                params.var_threshold(),
+                params.detect_shadows,
+            )?),
+            Backend::Knn => Model::Knn(video::create_background_subtractor_knn(
+                params.history,
+                params.var_threshold(),
+                params.detect_shadows,
+            )?),
+        };
+        Ok(BackgroundSubtractor {
+            params: params.clone(),
+            model,
+        })
+    }
+
+    pub fn params(&self) -> &BackgroundSubtractorParams {
+        &self.params
+    }
+
+    /// Throws away everything learned so far
+    pub fn reset(&mut self) -> Result<(), AugmentError> {
+        *self = BackgroundSubtractor::new(&self.params)?;
+        Ok(())
+    }
+
+    /// Updates the model with an RGB frame and returns the single channel foreground mask
+    pub fn apply(&mut self, src: &Mat) -> Result<Mat, AugmentError> {
+        let mut fg_mask = Mat::default()?;
+        match &mut self.model {
+            Model::Mog2(model) => {
+                BackgroundSubtractorMOG2::apply(model, src, &mut fg_mask, self.params.learning_rate)?
+            }
+            Model::Knn(model) => model.apply(src, &mut fg_mask, self.params.learning_rate)?,
+        }
+        Ok(fg_mask)
+    }
+
+    /// The RGB image of the background learned so far
+    pub fn background_image(&self) -> Result<Mat, AugmentError> {
+        let mut background = Mat::default()?;
+        match &self.model {
+            Model::Mog2(model) => model.get_background_image(&mut background)?,
+            Model::Knn(model) => model.get_background_image(&mut background)?,
+        }
+        Ok(background)
+    }
+}
+
+/// Holds a filter's background model. Cloning or comparing filters ignores what was learned.
+#[derive(Default)]
+pub struct SubtractorState(Option<BackgroundSubtractor>);
+
+impl SubtractorState {
+    pub fn get_or_create(
+        &mut self,
+        params: &BackgroundSubtractorParams,
+    ) -> Result<&mut BackgroundSubtractor, AugmentError> {
+        let stale = match &self.0 {
+            Some(subtractor) => subtractor.params() != params,
+            None => true,
+        };
+        if stale {
+            self.0 = Some(BackgroundSubtractor::new(params)?);
+        }
+        Ok(self.0.as_mut().unwrap())
+    }
+
+    pub fn reset(&mut self) -> Result<(), AugmentError> {
+        match &mut self.0 {
+            Some(subtractor) => subtractor.reset(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Clone for SubtractorState {
+    fn clone(&self) -> Self {
+        SubtractorState(None)
+    }
+}
+
+impl PartialEq for SubtractorState {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl std::fmt::Debug for SubtractorState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SubtractorState({:?})", self.0.as_ref().map(|s| s.params()))
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use ffmpeg::frame::Video;
+use serde::Deserialize;
+
+use super::background::{Backend, BackgroundSubtractorParams, SubtractorState};
+use super::ConnectedComponent;
+use crate::augment::error::AugmentError;
+use crate::augment::video::FramePool;
+
+/// A single image processing step. Filters take and produce RGB24 frames. They run on the
+/// filter thread.
+pub trait Filter: Send {
+    /// `dst` has the same format and size as `src`. It is reused from earlier frames, so every
+    /// pixel has to be written.
+    fn apply(&mut self, src: &Video, dst: &mut Video) -> Result<(), AugmentError>;
+
+    /// Objects found by the last call to `apply`, for filters that detect them
+    fn take_components(&mut self) -> Option<Vec<ConnectedComponent>> {
+        None
+    }
+
+    /// Forget any state learned from previous frames
+    fn reset(&mut self) -> Result<(), AugmentError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Blur {
+    pub k: i32,
+}
+
+impl Default for Blur {
+    fn default() -> Self {
+        Blur { k: 11 }
+    }
+}
+
+impl Filter for Blur {
+    fn apply(&mut self, src: &Video, dst: &mut Video) -> Result<(), AugmentError> {
+        super::blur(src, dst, self.k)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Edges {
+    pub t1: f64,
+    pub t2: f64,
+}
+
+impl Default for Edges {
+    fn default() -> Self {
+        Edges {
+            t1: 100.0,
+            t2: 200.0,
+        }
+    }
+}
+
+impl Filter for Edges {
+    fn apply(&mut self, src: &Video, dst: &mut Video) -> Result<(), AugmentError> {
+        super::edges(src, dst, self.t1, self.t2)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Denoise {
+    pub h: f32,
+    pub h_color: f32,
+    pub template_window: i32,
+    pub search_window: i32,
+}
+
+impl Default for Denoise {
+    fn default() -> Self {
+        Denoise {
+            h: 3.0,
+            h_color: 3.0,
+            template_window: 7,
+            search_window: 3,
+        }
+    }
+}
+
+impl Filter for Denoise {
+    fn apply(&mut self, src: &Video, dst: &mut Video) -> Result<(), AugmentError> {
+        super::denoise(src, dst, self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pixelate {
+    pub k: i32,
+}
+
+impl Default for Pixelate {
+    fn default() -> Self {
+        Pixelate { k: 32 }
+    }
+}
+
+impl Filter for Pixelate {
+    fn apply(&mut self, src: &Video, dst: &mut Video) -> Result<(), AugmentError> {
+        super::pixelate(src, dst, self.k)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BgSubOutput {
+    /// The parts of the frame that differ from the background
+    Foreground,
+    /// The foreground mask itself
+    Mask,
+    /// The background learned so far
+    Background,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BgSub {
+    pub output: BgSubOutput,
+    pub model: BackgroundSubtractorParams,
+    #[serde(skip)]
+    subtractor: SubtractorState,
+}
+
+impl Default for BgSub {
+    fn default() -> Self {
+        BgSub {
+            output: BgSubOutput::Foreground,
+            model: BackgroundSubtractorParams::default(),
+            subtractor: SubtractorState::default(),
+        }
+    }
+}
+
+impl Filter for BgSub {
+    fn apply(&mut self, src: &Video, dst: &mut Video) -> Result<(), AugmentError> {
+        let subtractor = self.subtractor.get_or_create(&self.model)?;
+        super::bgsub(src, dst, subtractor, self.output)
+    }
+
+    fn reset(&mut self) -> Result<(), AugmentError> {
+        self.subtractor.reset()
+    }
+}
+
+/// Outputs the binary mask that objects are detected in
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FindObjects {
+    /// Size of the box blur applied before thresholding
+    pub blur: i32,
+    /// Brightness above which a pixel belongs to an object
+    pub threshold: f64,
+    pub erode_iterations: i32,
+    pub dilate_iterations: i32,
+    /// Find objects that differ from a learned background instead of bright regions
+    pub background: Option<BackgroundSubtractorParams>,
+    /// Also cut out a binary mask for every object
+    pub masks: bool,
+    #[serde(skip)]
+    pub(super) subtractor: SubtractorState,
+    #[serde(skip)]
+    components: Option<Vec<ConnectedComponent>>,
+}
+
+impl Default for FindObjects {
+    fn default() -> Self {
+        FindObjects {
+            blur: 11,
+            threshold: 230.0,
+            erode_iterations: 2,
+            dilate_iterations: 4,
+            background: None,
+            masks: false,
+            subtractor: SubtractorState::default(),
+            components: None,
+        }
+    }
+}
+
+impl Filter for FindObjects {
+    fn apply(&mut self, src: &Video, dst: &mut Video) -> Result<(), AugmentError> {
+        let components = super::find_objects(src, self, Some(dst))?;
+        self.components = Some(components);
+        Ok(())
+    }
+
+    fn take_components(&mut self) -> Option<Vec<ConnectedComponent>> {
+        self.components.take()
+    }
+
+    fn reset(&mut self) -> Result<(), AugmentError> {
+        self.subtractor.reset()
+    }
+}
+
+/// The filter for one stage along with its parameters
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "filter", rename_all = "snake_case")]
+pub enum FilterKind {
+    Blur(Blur),
+    Edges(Edges),
+    Denoise(Denoise),
+    Pixelate(Pixelate),
+    #[serde(rename = "bgsub")]
+    BgSub(BgSub),
+    FindObjects(FindObjects),
+}
+
+impl FilterKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterKind::Blur(_) => "blur",
+            FilterKind::Edges(_) => "edges",
+            FilterKind::Denoise(_) => "denoise",
+            FilterKind::Pixelate(_) => "pixelate",
+            FilterKind::BgSub(_) => "bgsub",
+            FilterKind::FindObjects(_) => "find_objects",
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn Filter> {
+        match self {
+            FilterKind::Blur(f) => Box::new(f.clone()),
+            FilterKind::Edges(f) => Box::new(f.clone()),
+            FilterKind::Denoise(f) => Box::new(f.clone()),
+            FilterKind::Pixelate(f) => Box::new(f.clone()),
+            FilterKind::BgSub(f) => Box::new(f.clone()),
+            FilterKind::FindObjects(f) => Box::new(f.clone()),
+        }
+    }
+}
+
+/// Typed access to "key=value" stage parameters
+struct Params<'a> {
+    values: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Params<'a> {
+    fn parse(s: &'a str) -> Result<Params<'a>, String> {
+        let mut values = HashMap::new();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = parts
+                .next()
+                .ok_or_else(|| format!("Expected key=value, got '{}'", pair))?;
+            values.insert(key, value.trim());
+        }
+        Ok(Params { values })
+    }
+
+    fn get<T: FromStr>(&mut self, key: &str, default: T) -> Result<T, String> {
+        Ok(self.get_optional(key)?.unwrap_or(default))
+    }
+
+    fn get_optional<T: FromStr>(&mut self, key: &str) -> Result<Option<T>, String> {
+        match self.values.remove(key) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("Invalid value '{}' for parameter '{}'", value, key)),
+            None => Ok(None),
+        }
+    }
+
+    /// The background model parameters that bgsub and find_objects both take
+    fn background(&mut self, backend: Backend) -> Result<BackgroundSubtractorParams, String> {
+        let defaults = BackgroundSubtractorParams::new(backend);
+        Ok(BackgroundSubtractorParams {
+            history: self.get("history", defaults.history)?,
+            var_threshold: self.get_optional("var_threshold")?,
+            detect_shadows: self.get("detect_shadows", defaults.detect_shadows)?,
+            learning_rate: self.get("learning_rate", defaults.learning_rate)?,
+            ..defaults
+        })
+    }
+
+    /// Fails if any parameters were not consumed
+    fn finish(self) -> Result<(), String> {
+        match self.values.keys().next() {
+            Some(key) => Err(format!("Unknown parameter '{}'", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A named stage of a `FilterChain`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageSpec {
+    pub name: String,
+    pub kind: FilterKind,
+}
+
+impl FromStr for StageSpec {
+    type Err = String;
+
+    /// Parses "[name=]filter[:key=value,...]", e.g. "soft=blur:k=5" or "edges:t1=50,t2=150".
+    /// The stage is named after the filter if no name is given.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let head = parts.next().unwrap();
+        let mut params = Params::parse(parts.next().unwrap_or(""))?;
+        let (name, filter) = match head.find('=') {
+            Some(i) => (&head[..i], &head[i + 1..]),
+            None => (head, head),
+        };
+        let kind = match filter {
+            "blur" => FilterKind::Blur(Blur {
+                k: params.get("k", Blur::default().k)?,
+            }),
+            "edges" => {
+                let defaults = Edges::default();
+                FilterKind::Edges(Edges {
+                    t1: params.get("t1", defaults.t1)?,
+                    t2: params.get("t2", defaults.t2)?,
+                })
+            }
+            "denoise" => {
+                let defaults = Denoise::default();
+                FilterKind::Denoise(Denoise {
+                    h: params.get("h", defaults.h)?,
+                    h_color: params.get("h_color", defaults.h_color)?,
+                    template_window: params.get("template_window", defaults.template_window)?,
+                    search_window: params.get("search_window", defaults.search_window)?,
+                })
+            }
+            "pixelate" => FilterKind::Pixelate(Pixelate {
+                k: params.get("k", Pixelate::default().k)?,
+            }),
+            "bgsub" => {
+                let mut defaults = BgSub::default();
+                let backend = match params.get("backend", "mog2".to_string())?.as_str() {
+                    "mog2" => Backend::Mog2,
+                    "knn" => Backend::Knn,
+                    other => return Err(format!("Unknown background backend '{}'", other)),
+                };
+                defaults.output = match params.get("output", "foreground".to_string())?.as_str() {
+                    "foreground" => BgSubOutput::Foreground,
+                    "mask" => BgSubOutput::Mask,
+                    "background" => BgSubOutput::Background,
+                    other => return Err(format!("Unknown bgsub output '{}'", other)),
+                };
+                defaults.model = params.background(backend)?;
+                FilterKind::BgSub(defaults)
+            }
+            "find_objects" => {
+                let defaults = FindObjects::default();
+                FilterKind::FindObjects(FindObjects {
+                    blur: params.get("blur", defaults.blur)?,
+                    threshold: params.get("threshold", defaults.threshold)?,
+                    erode_iterations: params.get("erode_iterations", defaults.erode_iterations)?,
+                    dilate_iterations: params.get("dilate_iterations", defaults.dilate_iterations)?,
+                    background: match params.get("background", "none".to_string())?.as_str() {
+                        "none" => None,
+                        "mog2" => Some(params.background(Backend::Mog2)?),
+                        "knn" => Some(params.background(Backend::Knn)?),
+                        other => return Err(format!("Unknown background backend '{}'", other)),
+                    },
+                    masks: params.get("masks", defaults.masks)?,
+                    ..defaults
+                })
+            }
+            _ => return Err(format!("Unknown filter '{}'", filter)),
+        };
+        params.finish()?;
+        Ok(StageSpec {
+            name: name.to_string(),
+            kind,
+        })
+    }
+}
+
+struct Stage {
+    name: String,
+    filter: Box<dyn Filter>,
+}
+
+#[derive(Default)]
+pub struct ChainOutput {
+    /// The output of every stage, in order
+    pub stages: Vec<(String, Video)>,
+    /// Objects reported by the last stage that detects them
+    pub components: Vec<ConnectedComponent>,
+}
+
+impl ChainOutput {
+    pub fn stage(&self, name: &str) -> Option<&Video> {
+        self.stages
+            .iter()
+            .find(|(stage, _)| stage == name)
+            .map(|(_, frame)| frame)
+    }
+
+    /// Hands the stage frames back to the pool they came from and keeps the objects
+    pub fn recycle(self, pool: &FramePool) -> Vec<ConnectedComponent> {
+        for (_, frame) in self.stages {
+            pool.give(frame);
+        }
+        self.components
+    }
+}
+
+/// Runs a list of filters in order, each one on the output of the previous
+pub struct FilterChain {
+    stages: Vec<Stage>,
+}
+
+impl FilterChain {
+    pub fn new(specs: &[StageSpec]) -> FilterChain {
+        FilterChain {
+            stages: specs
+                .iter()
+                .map(|spec| Stage {
+                    name: spec.name.clone(),
+                    filter: spec.kind.build(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Stage outputs are taken from `pool`. Call `ChainOutput::recycle` to give them back.
+    pub fn run(&mut self, src: &Video, pool: &FramePool) -> Result<ChainOutput, AugmentError> {
+        let mut output = ChainOutput {
+            stages: Vec::with_capacity(self.stages.len()),
+            components: vec![],
+        };
+        for stage in self.stages.iter_mut() {
+            let frame = {
+                let input = output.stages.last().map_or(src, |(_, frame)| frame);
+                let mut frame = pool.take(input.format(), input.width(), input.height());
+                stage.filter.apply(input, &mut frame)?;
+                frame
+            };
+            if let Some(components) = stage.filter.take_components() {
+                output.components = components;
+            }
+            output.stages.push((stage.name.clone(), frame));
+        }
+        Ok(output)
+    }
+
+    pub fn reset(&mut self) -> Result<(), AugmentError> {
+        for stage in self.stages.iter_mut() {
+            stage.filter.reset()?;
+        }
+        Ok(())
+    }
+}
@@ -1,79 +1,151 @@
-use std::error::Error;
-
+use ffmpeg::format::Pixel;
 use ffmpeg::frame::Video;
 use opencv::{
     core::CV_8UC3,
     imgproc::{
-        self, InterpolationFlags, MorphShapes, COLOR_BGR2GRAY, COLOR_GRAY2BGR, THRESH_BINARY,
+        self, InterpolationFlags, MorphShapes, COLOR_BGR2RGB, COLOR_GRAY2RGB, COLOR_RGB2BGR,
+        COLOR_RGB2GRAY, THRESH_BINARY,
     },
     prelude::*,
 };
 use opencv::{
-    core::{BorderTypes, Mat, Point, Size, CV_8UC1},
+    core::{self, BorderTypes, Mat, Point, Rect, Scalar, Size, CV_8UC1},
     photo,
+    types::{VectorOfPoint, VectorOfVectorOfPoint},
 };
 
+use crate::augment::error::AugmentError;
+
+mod background;
+mod chain;
 mod utils;
 
-#[derive(Debug)]
+pub use self::chain::{ChainOutput, FilterChain, FilterKind, StageSpec};
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ConnectedComponent {
     pub left: i32,
     pub top: i32,
     pub width: i32,
     pub height: i32,
     pub area: i32,
+    /// Center of mass in pixels
+    pub centroid: [f32; 2],
+    /// Outer boundary in pixels, without holes
+    pub contour: Vec<[i32; 2]>,
+    pub convex_hull: Vec<[i32; 2]>,
+    pub min_area_rect: OrientedBox,
+    /// Only filled in when the find_objects stage has masks enabled
+    pub mask: Option<ComponentMask>,
+}
+
+/// The smallest rotated rectangle around a component
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrientedBox {
+    pub center: [f32; 2],
+    pub width: f32,
+    pub height: f32,
+    /// Rotation of the width axis in degrees, clockwise since y points down
+    pub angle: f32,
+}
+
+impl OrientedBox {
+    /// The four corners in pixels, in order around the box
+    pub fn corners(&self) -> [[f32; 2]; 4] {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let w = [cos * self.width / 2.0, sin * self.width / 2.0];
+        let h = [-sin * self.height / 2.0, cos * self.height / 2.0];
+        let [x, y] = self.center;
+        [
+            [x - w[0] - h[0], y - w[1] - h[1]],
+            [x + w[0] - h[0], y + w[1] - h[1]],
+            [x + w[0] + h[0], y + w[1] + h[1]],
+            [x - w[0] + h[0], y - w[1] + h[1]],
+        ]
+    }
+}
+
+/// A component's pixels cropped to its bounding box, 255 inside and 0 outside
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentMask {
+    pub width: i32,
+    pub height: i32,
+    pub data: Vec<u8>,
 }
 
-#[allow(dead_code)]
-pub fn blur(src_frame: &Video, k: i32) -> Result<Video, Box<dyn Error>> {
-    let src = utils::frame_to_mat(&src_frame);
-    let mut out = Video::new(src_frame.format(), src_frame.width(), src_frame.height());
-    let mut dst = utils::frame_to_mat(&mut out);
+impl ComponentMask {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0
+            && y >= 0
+            && x < self.width
+            && y < self.height
+            && self.data[(y * self.width + x) as usize] != 0
+    }
+}
+
+pub fn blur(src_frame: &Video, dst_frame: &mut Video, k: i32) -> Result<(), AugmentError> {
+    let src = utils::frame_to_mat(src_frame)?;
+    let mut dst = utils::frame_to_mat_mut(dst_frame)?;
 
     imgproc::blur(
-        &src,
-        &mut dst,
+        &*src,
+        &mut *dst,
         Size::new(k, k),
         Point::new(-1, -1),
         BorderTypes::BORDER_CONSTANT as i32,
     )?;
 
-    Ok(out)
+    Ok(())
 }
 
-#[allow(dead_code)]
-pub fn edges(src_frame: &Video, t1: f64, t2: f64) -> Result<Video, Box<dyn Error>> {
-    let src = utils::frame_to_mat(src_frame);
-    let mut out = Video::new(src_frame.format(), src_frame.width(), src_frame.height());
-    let mut dst = utils::frame_to_mat(&mut out);
+pub fn edges(
+    src_frame: &Video,
+    dst_frame: &mut Video,
+    t1: f64,
+    t2: f64,
+) -> Result<(), AugmentError> {
+    let src = utils::frame_to_mat(src_frame)?;
+    let mut dst = utils::frame_to_mat_mut(dst_frame)?;
     let out_size = Size {
         width: src_frame.width() as i32,
         height: src_frame.height() as i32,
     };
     let mut edges = unsafe { Mat::new_size(out_size, CV_8UC1)? };
 
-    imgproc::canny(&src, &mut edges, t1, t2, 3, false)?;
-    imgproc::cvt_color(&edges, &mut dst, imgproc::COLOR_GRAY2BGR, 3)?;
+    imgproc::canny(&*src, &mut edges, t1, t2, 3, false)?;
+    imgproc::cvt_color(&edges, &mut *dst, imgproc::COLOR_GRAY2RGB, 3)?;
 
-    Ok(out)
+    Ok(())
 }
 
-#[allow(dead_code)]
-pub fn denoise(src_frame: &Video) -> Result<Video, Box<dyn Error>> {
-    let src = utils::frame_to_mat(src_frame);
-    let mut out = Video::new(src_frame.format(), src_frame.width(), src_frame.height());
-    let mut dst = utils::frame_to_mat(&mut out);
+pub fn denoise(
+    src_frame: &Video,
+    dst_frame: &mut Video,
+    params: &chain::Denoise,
+) -> Result<(), AugmentError> {
+    let src = utils::frame_to_mat(src_frame)?;
+    let mut dst = utils::frame_to_mat_mut(dst_frame)?;
+    // The color denoiser converts to Lab, which OpenCV expects to start from BGR
+    let mut bgr = Mat::default()?;
+    let mut denoised = Mat::default()?;
+    imgproc::cvt_color(&*src, &mut bgr, COLOR_RGB2BGR, 0)?;
 
-    photo::fast_nl_means_denoising_colored(&src, &mut dst, 3.0, 3.0, 7, 3)?;
+    photo::fast_nl_means_denoising_colored(
+        &bgr,
+        &mut denoised,
+        params.h,
+        params.h_color,
+        params.template_window,
+        params.search_window,
+    )?;
+    imgproc::cvt_color(&denoised, &mut *dst, COLOR_BGR2RGB, 0)?;
 
-    Ok(out)
+    Ok(())
 }
 
-#[allow(dead_code)]
-pub fn pixelate(src_frame: &Video, k: i32) -> Result<Video, Box<dyn Error>> {
-    let src = utils::frame_to_mat(src_frame);
-    let mut out = Video::new(src_frame.format(), src_frame.width(), src_frame.height());
-    let mut dst = utils::frame_to_mat(&mut out);
+pub fn pixelate(src_frame: &Video, dst_frame: &mut Video, k: i32) -> Result<(), AugmentError> {
+    let src = utils::frame_to_mat(src_frame)?;
+    let mut dst = utils::frame_to_mat_mut(dst_frame)?;
     let mut tmp = unsafe { Mat::new_size(Size::new(k, k), CV_8UC3)? };
     let tmp_size = Size {
         width: k,
@@ -81,7 +153,7 @@ pub fn pixelate(src_frame: &Video, k: i32) -> Result<Video, Box<dyn Error>> {
     };
 
     imgproc::resize(
-        &src,
+        &*src,
         &mut tmp,
         tmp_size,
         0.0,
@@ -95,59 +167,75 @@ pub fn pixelate(src_frame: &Video, k: i32) -> Result<Video, Box<dyn Error>> {
     };
     imgproc::resize(
         &tmp,
-        &mut dst,
+        &mut *dst,
         out_size,
         0.0,
         0.0,
         InterpolationFlags::INTER_NEAREST as i32,
     )?;
 
-    Ok(out)
+    Ok(())
 }
 
-#[allow(dead_code)]
-pub fn bgsub(src_frame: &Video) -> Result<Video, Box<dyn Error>> {
-    let src = utils::frame_to_mat(src_frame);
-    let mut out = Video::new(src_frame.format(), src_frame.width(), src_frame.height());
-    let mut dst = utils::frame_to_mat(&mut out);
+pub fn bgsub(
+    src_frame: &Video,
+    dst_frame: &mut Video,
+    subtractor: &mut background::BackgroundSubtractor,
+    output: chain::BgSubOutput,
+) -> Result<(), AugmentError> {
+    let src = utils::frame_to_mat(src_frame)?;
+    let mut dst = utils::frame_to_mat_mut(dst_frame)?;
     dst.set_to(
         &opencv::core::Scalar::all(0.0),
         &opencv::core::no_array().unwrap(),
     )?;
 
-    let out_size = Size {
-        width: src_frame.width() as i32,
-        height: src_frame.height() as i32,
-    };
-    let mut subtractor = opencv::video::create_background_subtractor_mog2(500, 16.0, false)?;
-    let mut fg_mask = unsafe { Mat::new_size(out_size, CV_8UC3)? };
+    let fg_mask = subtractor.apply(&*src)?;
 
-    BackgroundSubtractorMOG2::apply(&mut subtractor, &src, &mut fg_mask, -1.0)?;
-
-    opencv::core::copy_to(&src, &mut dst, &fg_mask)?;
+    match output {
+        chain::BgSubOutput::Foreground => opencv::core::copy_to(&*src, &mut *dst, &fg_mask)?,
+        chain::BgSubOutput::Mask => imgproc::cvt_color(&fg_mask, &mut *dst, COLOR_GRAY2RGB, 0)?,
+        chain::BgSubOutput::Background => {
+            let background = subtractor.background_image()?;
+            background.copy_to(&mut *dst)?;
+        }
+    }
 
-    Ok(out)
+    Ok(())
 }
 
-#[allow(dead_code)]
 pub fn find_objects(
     src_frame: &Video,
+    params: &mut chain::FindObjects,
     intermediate_frame: Option<&mut Video>,
-) -> Result<Vec<ConnectedComponent>, Box<dyn Error>> {
-    let src = utils::frame_to_mat(src_frame);
+) -> Result<Vec<ConnectedComponent>, AugmentError> {
+    let src = utils::frame_to_mat(src_frame)?;
     let mut gray_mat = Mat::default()?;
-    imgproc::cvt_color(&src, &mut gray_mat, COLOR_BGR2GRAY, 0)?;
-
     let mut gray2_mat = Mat::default()?;
-    imgproc::blur(
-        &gray_mat,
-        &mut gray2_mat,
-        Size::new(11, 11),
-        Point::new(-1, -1),
-        BorderTypes::BORDER_CONSTANT as i32,
-    )?;
 
-    imgproc::threshold(&gray2_mat, &mut gray_mat, 230.0, 255.0, THRESH_BINARY)?;
+    if let Some(background) = &params.background {
+        let fg_mask = params.subtractor.get_or_create(background)?.apply(&*src)?;
+        // Shadows are marked as 127, so this only keeps the confident foreground
+        imgproc::threshold(&fg_mask, &mut gray_mat, 200.0, 255.0, THRESH_BINARY)?;
+    } else {
+        imgproc::cvt_color(&*src, &mut gray_mat, COLOR_RGB2GRAY, 0)?;
+
+        imgproc::blur(
+            &gray_mat,
+            &mut gray2_mat,
+            Size::new(params.blur, params.blur),
+            Point::new(-1, -1),
+            BorderTypes::BORDER_CONSTANT as i32,
+        )?;
+
+        imgproc::threshold(
+            &gray2_mat,
+            &mut gray_mat,
+            params.threshold,
+            255.0,
+            THRESH_BINARY,
+        )?;
+    }
 
     imgproc::erode(
         &gray_mat,
@@ -158,7 +246,7 @@ pub fn find_objects(
             Point::new(-1, -1),
         )?,
         Point::new(-1, -1),
-        2,
+        params.erode_iterations,
         BorderTypes::BORDER_CONSTANT as i32,
         imgproc::morphology_default_border_value()?,
     )?;
@@ -172,28 +260,28 @@ pub fn find_objects(
             Point::new(-1, -1),
         )?,
         Point::new(-1, -1),
-        4,
+        params.dilate_iterations,
         BorderTypes::BORDER_CONSTANT as i32,
         imgproc::morphology_default_border_value()?,
     )?;
 
-    if let Some(mut output_frame) = intermediate_frame {
+    if let Some(output_frame) = intermediate_frame {
         if src_frame.format() != output_frame.format()
             || src_frame.width() != output_frame.width()
             || src_frame.height() != output_frame.height()
         {
-            return Err(
-                "Cannot output intermediate frame. Format or size does not match input".into(),
-            );
+            return Err(AugmentError::OpenCv(opencv::Error::new(
+                core::StsUnmatchedSizes,
+                "Cannot output intermediate frame. Format or size does not match input".to_string(),
+            )));
         }
-        let mut intermediate_mat = utils::frame_to_mat(&mut output_frame);
-        imgproc::cvt_color(&gray_mat, &mut intermediate_mat, COLOR_GRAY2BGR, 0)?;
+        let mut intermediate_mat = utils::frame_to_mat_mut(output_frame)?;
+        imgproc::cvt_color(&gray_mat, &mut *intermediate_mat, COLOR_GRAY2RGB, 0)?;
     }
 
     let mut labels = Mat::default()?;
     let mut stats = Mat::default()?;
     let mut centroids = Mat::default()?;
-    // TODO: how do we actually use the components?
     let num_labels = imgproc::connected_components_with_stats(
         &gray_mat,
         &mut labels,
@@ -205,14 +293,207 @@ pub fn find_objects(
 
     let mut components = vec![];
     for label in 1..num_labels {
-        components.push(ConnectedComponent {
-            left: *stats.at_2d::<i32>(label, 0)?,
-            top: *stats.at_2d::<i32>(label, 1)?,
-            width: *stats.at_2d::<i32>(label, 2)?,
-            height: *stats.at_2d::<i32>(label, 3)?,
-            area: *stats.at_2d::<i32>(label, 4)?,
-        })
+        let bounds = Rect::new(
+            *stats.at_2d::<i32>(label, 0)?,
+            *stats.at_2d::<i32>(label, 1)?,
+            *stats.at_2d::<i32>(label, 2)?,
+            *stats.at_2d::<i32>(label, 3)?,
+        );
+        components.push(describe_component(
+            &labels,
+            label,
+            bounds,
+            *stats.at_2d::<i32>(label, 4)?,
+            [
+                *centroids.at_2d::<f64>(label, 0)? as f32,
+                *centroids.at_2d::<f64>(label, 1)? as f32,
+            ],
+            params.masks,
+        )?);
     }
 
     Ok(components)
 }
+
+/// Replaces `pixels` with the first channel of every pixel, row by row from the top. The output
+/// of find_objects is gray, so this is its mask.
+pub fn mask_pixels(frame: &Video, pixels: &mut Vec<u8>) -> Result<(), AugmentError> {
+    let channels = match frame.format() {
+        Pixel::GRAY8 => 1,
+        Pixel::BGR24 | Pixel::RGB24 => 3,
+        Pixel::RGBA => 4,
+        format => return Err(utils::FrameError::UnsupportedFormat(format).into()),
+    };
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    pixels.clear();
+    for row in frame.data(0).chunks(frame.stride(0)).take(height) {
+        pixels.extend(row[..width * channels].iter().step_by(channels));
+    }
+    Ok(())
+}
+
+/// Draws each component filled in with a color picked from its object ID, on black. `dst_frame`
+/// has to be RGB24.
+pub fn draw_objects(
+    dst_frame: &mut Video,
+    objects: &[(u64, &ConnectedComponent)],
+) -> Result<(), AugmentError> {
+    for byte in dst_frame.data_mut(0) {
+        *byte = 0;
+    }
+    let mut dst = utils::frame_to_mat_mut(dst_frame)?;
+
+    for (id, component) in objects {
+        let contour: Vec<Point> = component
+            .contour
+            .iter()
+            .map(|point| Point::new(point[0], point[1]))
+            .collect();
+        let mut contours = VectorOfVectorOfPoint::new();
+        contours.push(VectorOfPoint::from(contour));
+        let [r, g, b] = object_color(*id);
+        imgproc::fill_poly(
+            &mut *dst,
+            &contours,
+            Scalar::new(r, g, b, 0.0),
+            imgproc::LINE_8,
+            0,
+            Point::new(0, 0),
+        )?;
+    }
+
+    Ok(())
+}
+
+const TEXT_FONT: i32 = imgproc::FONT_HERSHEY_SIMPLEX;
+const TEXT_SCALE: f64 = 0.6;
+const TEXT_THICKNESS: i32 = 1;
+/// Space around each line of text, in pixels
+const TEXT_MARGIN: i32 = 6;
+
+/// Height in pixels of each line written by `draw_text`
+pub fn text_line_height() -> Result<u32, AugmentError> {
+    let (ascent, descent) = text_extent()?;
+    Ok((ascent + descent + TEXT_MARGIN) as u32)
+}
+
+/// How far text reaches above and below its baseline
+fn text_extent() -> Result<(i32, i32), AugmentError> {
+    let mut baseline = 0;
+    let size = imgproc::get_text_size("Ag", TEXT_FONT, TEXT_SCALE, TEXT_THICKNESS, &mut baseline)?;
+    Ok((size.height, baseline))
+}
+
+/// Writes each line in white on `background`, `text_line_height` apart. Lines that are too long
+/// are cut off. `dst_frame` has to be RGB24.
+pub fn draw_text(
+    dst_frame: &mut Video,
+    lines: &[String],
+    background: [u8; 3],
+) -> Result<(), AugmentError> {
+    let line_height = text_line_height()? as i32;
+    let (ascent, _) = text_extent()?;
+    let (width, height) = (dst_frame.width() as i32, dst_frame.height() as i32);
+    let mut dst = utils::frame_to_mat_mut(dst_frame)?;
+    let [r, g, b] = background;
+    imgproc::rectangle(
+        &mut *dst,
+        Rect::new(0, 0, width, height),
+        Scalar::new(r as f64, g as f64, b as f64, 0.0),
+        imgproc::FILLED,
+        imgproc::LINE_8,
+        0,
+    )?;
+    for (i, line) in lines.iter().enumerate() {
+        // put_text places the baseline rather than the top of the text
+        let baseline_y = i as i32 * line_height + TEXT_MARGIN / 2 + ascent;
+        imgproc::put_text(
+            &mut *dst,
+            line,
+            Point::new(TEXT_MARGIN, baseline_y),
+            TEXT_FONT,
+            TEXT_SCALE,
+            Scalar::all(255.0),
+            TEXT_THICKNESS,
+            imgproc::LINE_AA,
+            false,
+        )?;
+    }
+    Ok(())
+}
+
+/// Same colors as shaders/obj.frag: the hue steps by the golden ratio for each ID
+fn object_color(id: u64) -> [f64; 3] {
+    let hue = (id as f64 * 0.618_034).fract() * 6.0;
+    let channel = |offset: f64| {
+        let value = ((hue + offset) % 6.0 - 3.0).abs() - 1.0;
+        value.max(0.0).min(1.0) * 255.0
+    };
+    [channel(0.0), channel(4.0), channel(2.0)]
+}
+
+/// Traces the shape of one label, looking only inside its bounding box
+fn describe_component(
+    labels: &Mat,
+    label: i32,
+    bounds: Rect,
+    area: i32,
+    centroid: [f32; 2],
+    with_mask: bool,
+) -> Result<ConnectedComponent, AugmentError> {
+    let roi = Mat::roi(labels, bounds)?;
+    let mut mask = Mat::default()?;
+    core::compare(&roi, &Scalar::all(label as f64), &mut mask, core::CMP_EQ)?;
+
+    let mut contours = VectorOfVectorOfPoint::new();
+    imgproc::find_contours(
+        &mask,
+        &mut contours,
+        imgproc::RETR_EXTERNAL,
+        imgproc::CHAIN_APPROX_SIMPLE,
+        Point::new(bounds.x, bounds.y),
+    )?;
+    // 4-connected labels can still trace as several 8-connected pieces, keep the biggest
+    let mut contour = VectorOfPoint::new();
+    let mut largest = -1.0;
+    for candidate in contours.iter() {
+        let candidate_area = imgproc::contour_area(&candidate, false)?;
+        if candidate_area > largest {
+            largest = candidate_area;
+            contour = candidate;
+        }
+    }
+
+    let mut hull = VectorOfPoint::new();
+    imgproc::convex_hull(&contour, &mut hull, false, true)?;
+    let rect = imgproc::min_area_rect(&contour)?;
+
+    let to_points = |points: &VectorOfPoint| points.iter().map(|p| [p.x, p.y]).collect::<Vec<_>>();
+    let mask = if with_mask {
+        Some(ComponentMask {
+            width: bounds.width,
+            height: bounds.height,
+            data: mask.data_typed::<u8>()?.to_vec(),
+        })
+    } else {
+        None
+    };
+
+    Ok(ConnectedComponent {
+        left: bounds.x,
+        top: bounds.y,
+        width: bounds.width,
+        height: bounds.height,
+        area,
+        centroid,
+        contour: to_points(&contour),
+        convex_hull: to_points(&hull),
+        min_area_rect: OrientedBox {
+            center: [rect.center.x, rect.center.y],
+            width: rect.size.width,
+            height: rect.size.height,
+            angle: rect.angle,
+        },
+        mask,
+    })
+}
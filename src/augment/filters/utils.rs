@@ -1,15 +1,329 @@
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use ffmpeg::format::Pixel;
 use ffmpeg::frame::Video;
-use opencv::core::{Mat, CV_8UC3};
-
-pub fn frame_to_mat(frame: &Video) -> Mat {
-    unsafe {
-        Mat::new_rows_cols_with_data(
-            frame.height() as i32,
-            frame.width() as i32,
-            CV_8UC3,
-            (*frame.as_ptr()).data[0] as *mut std::ffi::c_void,
-            frame.stride(0),
-        )
-        .unwrap()
+use opencv::core::{self, Mat, CV_8UC1, CV_8UC3, CV_8UC4};
+
+use crate::augment::error::AugmentError;
+
+/// Why a frame can't be looked at as a Mat
+#[derive(Debug)]
+pub enum FrameError {
+    UnsupportedFormat(Pixel),
+    /// Planar frames have to be wrapped one plane at a time
+    Planar(Pixel),
+    NoSuchPlane(usize),
+    /// Negative strides (bottom-up frames) or rows that are shorter than the image
+    BadStride {
+        plane: usize,
+        stride: usize,
+    },
+    OpenCv(opencv::Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::UnsupportedFormat(format) => {
+                write!(f, "{:?} frames can't be used with OpenCV", format)
+            }
+            FrameError::Planar(format) => {
+                write!(f, "{:?} frames have to be used one plane at a time", format)
+            }
+            FrameError::NoSuchPlane(plane) => write!(f, "The frame has no plane {}", plane),
+            FrameError::BadStride { plane, stride } => {
+                write!(f, "Plane {} has an unusable stride of {}", plane, stride)
+            }
+            FrameError::OpenCv(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+impl From<opencv::Error> for FrameError {
+    fn from(err: opencv::Error) -> Self {
+        FrameError::OpenCv(err)
+    }
+}
+
+impl From<FrameError> for AugmentError {
+    fn from(err: FrameError) -> Self {
+        match err {
+            FrameError::OpenCv(err) => AugmentError::OpenCv(err),
+            err => AugmentError::OpenCv(opencv::Error::new(
+                core::StsUnsupportedFormat,
+                err.to_string(),
+            )),
+        }
+    }
+}
+
+/// The Mat type of each plane, and whether the format has more than one plane
+fn plane_type(format: Pixel) -> Result<(i32, bool), FrameError> {
+    match format {
+        Pixel::BGR24 | Pixel::RGB24 => Ok((CV_8UC3, false)),
+        Pixel::GRAY8 => Ok((CV_8UC1, false)),
+        Pixel::RGBA => Ok((CV_8UC4, false)),
+        Pixel::YUV420P => Ok((CV_8UC1, true)),
+        _ => Err(FrameError::UnsupportedFormat(format)),
+    }
+}
+
+/// A Mat that reads a frame's pixels in place. It can't outlive the frame.
+pub struct MatRef<'a> {
+    mat: Mat,
+    _frame: PhantomData<&'a Video>,
+}
+
+impl Deref for MatRef<'_> {
+    type Target = Mat;
+
+    fn deref(&self) -> &Mat {
+        &self.mat
+    }
+}
+
+/// A Mat that writes to a frame's pixels in place. The frame can't be used while it exists.
+pub struct MatMut<'a> {
+    mat: Mat,
+    _frame: PhantomData<&'a mut Video>,
+}
+
+impl Deref for MatMut<'_> {
+    type Target = Mat;
+
+    fn deref(&self) -> &Mat {
+        &self.mat
+    }
+}
+
+impl DerefMut for MatMut<'_> {
+    fn deref_mut(&mut self) -> &mut Mat {
+        &mut self.mat
+    }
+}
+
+/// Wraps a packed BGR24, RGB24, GRAY8 or RGBA frame
+pub fn frame_to_mat(frame: &Video) -> Result<MatRef<'_>, FrameError> {
+    packed(frame)?;
+    plane_to_mat(frame, 0)
+}
+
+/// Wraps a packed BGR24, RGB24, GRAY8 or RGBA frame for writing
+pub fn frame_to_mat_mut(frame: &mut Video) -> Result<MatMut<'_>, FrameError> {
+    packed(frame)?;
+    plane_to_mat_mut(frame, 0)
+}
+
+/// Wraps one plane of a frame. The chroma planes of YUV420P are half the size of the frame.
+pub fn plane_to_mat(frame: &Video, plane: usize) -> Result<MatRef<'_>, FrameError> {
+    let (rows, cols, typ, stride) = plane_layout(frame, plane)?;
+    let data = frame.data(plane).as_ptr() as *mut std::ffi::c_void;
+    // The Mat is only handed out behind a shared reference, so it is never written through
+    let mat = unsafe { Mat::new_rows_cols_with_data(rows, cols, typ, data, stride)? };
+    Ok(MatRef {
+        mat,
+        _frame: PhantomData,
+    })
+}
+
+pub fn plane_to_mat_mut(frame: &mut Video, plane: usize) -> Result<MatMut<'_>, FrameError> {
+    let (rows, cols, typ, stride) = plane_layout(frame, plane)?;
+    let data = frame.data_mut(plane).as_mut_ptr() as *mut std::ffi::c_void;
+    let mat = unsafe { Mat::new_rows_cols_with_data(rows, cols, typ, data, stride)? };
+    Ok(MatMut {
+        mat,
+        _frame: PhantomData,
+    })
+}
+
+fn packed(frame: &Video) -> Result<(), FrameError> {
+    match plane_type(frame.format())? {
+        (_, true) => Err(FrameError::Planar(frame.format())),
+        (_, false) => Ok(()),
+    }
+}
+
+/// Rows, columns, Mat type and stride of a plane, after checking that the frame's buffer
+/// really holds that many bytes
+fn plane_layout(frame: &Video, plane: usize) -> Result<(i32, i32, i32, usize), FrameError> {
+    let (typ, planar) = plane_type(frame.format())?;
+    let planes = if planar { 3 } else { 1 };
+    // Also catches frames that were never allocated, which have no planes at all
+    if plane >= planes || plane >= frame.planes() {
+        return Err(FrameError::NoSuchPlane(plane));
+    }
+    let channels = match typ {
+        CV_8UC3 => 3,
+        CV_8UC4 => 4,
+        _ => 1,
+    };
+    let (width, height) = (frame.plane_width(plane), frame.plane_height(plane));
+    // ffmpeg's strides are signed, so a negative one shows up as a huge number here
+    let stride = frame.stride(plane);
+    if stride > i32::MAX as usize || stride < width as usize * channels {
+        return Err(FrameError::BadStride { plane, stride });
+    }
+    Ok((height as i32, width as i32, typ, stride))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::prelude::*;
+
+    /// A frame with a different value in every byte of the image, leaving the padding at the
+    /// end of each row alone
+    fn pattern_frame(format: Pixel, width: u32, height: u32) -> Video {
+        let mut frame = Video::new(format, width, height);
+        for plane in 0..frame.planes() {
+            let (_, _, _, stride) = plane_layout(&frame, plane).unwrap();
+            let row_len = row_bytes(&frame, plane);
+            let rows = frame.plane_height(plane) as usize;
+            let data = frame.data_mut(plane);
+            for row in 0..rows {
+                for col in 0..row_len {
+                    data[row * stride + col] = pattern(plane, row, col);
+                }
+            }
+        }
+        frame
+    }
+
+    fn pattern(plane: usize, row: usize, col: usize) -> u8 {
+        (plane * 70 + row * 31 + col * 7) as u8
+    }
+
+    fn row_bytes(frame: &Video, plane: usize) -> usize {
+        let channels = match frame.format() {
+            Pixel::BGR24 | Pixel::RGB24 => 3,
+            Pixel::RGBA => 4,
+            _ => 1,
+        };
+        frame.plane_width(plane) as usize * channels
+    }
+
+    /// Checks that a Mat has the shape of a plane and reads its pixels in place
+    fn assert_wraps(mat: &Mat, frame: &Video, plane: usize, typ: i32) -> Result<(), FrameError> {
+        let stride = frame.stride(plane);
+        assert_eq!(mat.rows(), frame.plane_height(plane) as i32);
+        assert_eq!(mat.cols(), frame.plane_width(plane) as i32);
+        assert_eq!(mat.typ()?, typ);
+        assert_eq!(mat.step1(0)?, stride);
+        for row in 0..mat.rows() {
+            let pixels = mat.ptr(row)?;
+            assert_eq!(pixels, frame.data(plane)[row as usize * stride..].as_ptr());
+            for col in 0..row_bytes(frame, plane) {
+                let value = unsafe { *pixels.add(col) };
+                assert_eq!(value, pattern(plane, row as usize, col));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn packed_frames_are_wrapped_with_their_padded_stride() -> Result<(), FrameError> {
+        let formats = [
+            (Pixel::BGR24, CV_8UC3),
+            (Pixel::RGB24, CV_8UC3),
+            (Pixel::GRAY8, CV_8UC1),
+            (Pixel::RGBA, CV_8UC4),
+        ];
+        for &(format, typ) in formats.iter() {
+            // Rows this short get padded out to ffmpeg's alignment
+            let frame = pattern_frame(format, 5, 3);
+            assert!(frame.stride(0) > row_bytes(&frame, 0));
+            assert_eq!(plane_layout(&frame, 0)?, (3, 5, typ, frame.stride(0)));
+            assert_wraps(&frame_to_mat(&frame)?, &frame, 0, typ)?;
+            assert_wraps(&plane_to_mat(&frame, 0)?, &frame, 0, typ)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn writes_through_a_mat_land_in_the_frame() -> Result<(), FrameError> {
+        let mut frame = pattern_frame(Pixel::RGB24, 5, 3);
+        let stride = frame.stride(0);
+        {
+            let mut mat = frame_to_mat_mut(&mut frame)?;
+            assert_eq!((mat.rows(), mat.cols()), (3, 5));
+            assert_eq!(mat.step1(0)?, stride);
+            unsafe { *mat.ptr_mut(2)?.add(4 * 3 + 1) = 200 };
+        }
+        assert_eq!(frame.data(0)[2 * stride + 4 * 3 + 1], 200);
+        assert_eq!(frame.data(0)[2 * stride + 4 * 3], pattern(0, 2, 12));
+        Ok(())
+    }
+
+    #[test]
+    fn yuv420p_is_wrapped_one_plane_at_a_time() -> Result<(), FrameError> {
+        let frame = pattern_frame(Pixel::YUV420P, 6, 4);
+        assert_eq!(plane_layout(&frame, 0)?, (4, 6, CV_8UC1, frame.stride(0)));
+        assert_wraps(&plane_to_mat(&frame, 0)?, &frame, 0, CV_8UC1)?;
+        // The chroma planes are half the size both ways
+        for plane in 1..3 {
+            assert_eq!(
+                plane_layout(&frame, plane)?,
+                (2, 3, CV_8UC1, frame.stride(plane))
+            );
+            let mat = plane_to_mat(&frame, plane)?;
+            assert_wraps(&mat, &frame, plane, CV_8UC1)?;
+            assert_eq!(*mat.at_2d::<u8>(1, 2)?, pattern(plane, 1, 2));
+        }
+        assert!(matches!(
+            frame_to_mat(&frame),
+            Err(FrameError::Planar(Pixel::YUV420P))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_formats_are_rejected() {
+        let mut frame = Video::new(Pixel::NV12, 4, 4);
+        assert!(matches!(
+            frame_to_mat(&frame),
+            Err(FrameError::UnsupportedFormat(Pixel::NV12))
+        ));
+        assert!(matches!(
+            plane_to_mat(&frame, 0),
+            Err(FrameError::UnsupportedFormat(Pixel::NV12))
+        ));
+        assert!(matches!(
+            plane_layout(&frame, 0),
+            Err(FrameError::UnsupportedFormat(Pixel::NV12))
+        ));
+        assert!(matches!(
+            frame_to_mat_mut(&mut frame),
+            Err(FrameError::UnsupportedFormat(Pixel::NV12))
+        ));
+    }
+
+    #[test]
+    fn planes_past_the_last_are_rejected() {
+        let mut rgb = Video::new(Pixel::RGB24, 4, 4);
+        assert!(matches!(
+            plane_to_mat(&rgb, 1),
+            Err(FrameError::NoSuchPlane(1))
+        ));
+        assert!(matches!(
+            plane_to_mat_mut(&mut rgb, 1),
+            Err(FrameError::NoSuchPlane(1))
+        ));
+        let yuv = Video::new(Pixel::YUV420P, 4, 4);
+        assert!(matches!(
+            plane_layout(&yuv, 3),
+            Err(FrameError::NoSuchPlane(3))
+        ));
+        // A frame that was never allocated has no planes
+        let mut empty = Video::empty();
+        empty.set_format(Pixel::GRAY8);
+        assert!(matches!(
+            plane_to_mat(&empty, 0),
+            Err(FrameError::NoSuchPlane(0))
+        ));
     }
 }
@@ -0,0 +1,152 @@
+use serde::Deserialize;
+
+/// Window area in GL coordinates, as (lower left, upper right)
+pub type Cell = ([f32; 2], [f32; 2]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    UpperLeft,
+    UpperRight,
+    LowerLeft,
+    LowerRight,
+}
+
+impl Default for Corner {
+    fn default() -> Self {
+        Corner::LowerRight
+    }
+}
+
+/// Size of picture in picture insets relative to the window
+pub const DEFAULT_INSET_SCALE: f32 = 0.3;
+
+fn default_inset_scale() -> f32 {
+    DEFAULT_INSET_SCALE
+}
+
+/// How the panels of a layout are placed in the window
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "arrangement", rename_all = "snake_case")]
+pub enum Arrangement {
+    /// Only the first panel, using the whole window
+    Single,
+    /// Rows of panels from the upper left. Leave out the columns or rows to get a grid that
+    /// is about square.
+    Grid {
+        columns: Option<u32>,
+        rows: Option<u32>,
+    },
+    /// All panels next to each other in one row
+    SideBySide,
+    /// The first panel uses the whole window and the others are inset along one corner
+    PictureInPicture {
+        #[serde(default)]
+        corner: Corner,
+        /// Size of the insets relative to the window
+        #[serde(default = "default_inset_scale")]
+        scale: f32,
+    },
+}
+
+impl Arrangement {
+    /// The area of each panel, in panel order. Panels that don't fit are left out.
+    pub fn cells(&self, panel_count: usize) -> Vec<Cell> {
+        let count = panel_count as u32;
+        match *self {
+            Arrangement::Single if count == 0 => vec![],
+            Arrangement::Single => vec![([-1.0, -1.0], [1.0, 1.0])],
+            Arrangement::Grid { columns, rows } => {
+                let (columns, rows) = grid_size(count, columns, rows);
+                grid_cells(columns, rows, count)
+            }
+            Arrangement::SideBySide => grid_cells(count.max(1), 1, count),
+            Arrangement::PictureInPicture { corner, scale } => {
+                let mut cells = vec![];
+                if count == 0 {
+                    return cells;
+                }
+                cells.push(([-1.0, -1.0], [1.0, 1.0]));
+                let size = 2.0 * scale;
+                let margin = 0.05;
+                for i in 0..count - 1 {
+                    // Insets are lined up along the bottom or top edge, away from the corner
+                    let offset = margin + i as f32 * (size + margin);
+                    let x = match corner {
+                        Corner::UpperLeft | Corner::LowerLeft => -1.0 + offset,
+                        Corner::UpperRight | Corner::LowerRight => 1.0 - offset - size,
+                    };
+                    let y = match corner {
+                        Corner::LowerLeft | Corner::LowerRight => -1.0 + margin,
+                        Corner::UpperLeft | Corner::UpperRight => 1.0 - margin - size,
+                    };
+                    if x < -1.0 || x + size > 1.0 {
+                        break;
+                    }
+                    cells.push(([x, y], [x + size, y + size]));
+                }
+                cells
+            }
+        }
+    }
+}
+
+/// Fills in whichever of the grid dimensions is missing
+fn grid_size(count: u32, columns: Option<u32>, rows: Option<u32>) -> (u32, u32) {
+    let count = count.max(1);
+    match (columns, rows) {
+        (Some(columns), Some(rows)) => (columns.max(1), rows.max(1)),
+        (Some(columns), None) => {
+            let columns = columns.max(1);
+            (columns, (count + columns - 1) / columns)
+        }
+        (None, Some(rows)) => {
+            let rows = rows.max(1);
+            ((count + rows - 1) / rows, rows)
+        }
+        (None, None) => {
+            let columns = (count as f32).sqrt().ceil() as u32;
+            (columns, (count + columns - 1) / columns)
+        }
+    }
+}
+
+fn grid_cells(columns: u32, rows: u32, count: u32) -> Vec<Cell> {
+    let width = 2.0 / columns as f32;
+    let height = 2.0 / rows as f32;
+    (0..count.min(columns * rows))
+        .map(|i| {
+            let column = (i % columns) as f32;
+            let row = (i / columns) as f32;
+            let left = -1.0 + column * width;
+            let top = 1.0 - row * height;
+            ([left, top - height], [left + width, top])
+        })
+        .collect()
+}
+
+/// Shrinks a cell so that it has the aspect ratio of the video
+pub fn letterbox(cell: Cell, window_size: (u32, u32), video_size: (u32, u32)) -> Cell {
+    let (lower_left, upper_right) = cell;
+    // Cell size in pixels
+    let width = (upper_right[0] - lower_left[0]) / 2.0 * window_size.0 as f32;
+    let height = (upper_right[1] - lower_left[1]) / 2.0 * window_size.1 as f32;
+    let video_aspect = video_size.0 as f32 / video_size.1 as f32;
+    let (scale_x, scale_y) = if width / height > video_aspect {
+        (video_aspect * height / width, 1.0)
+    } else {
+        (1.0, width / (video_aspect * height))
+    };
+    let center = [
+        (lower_left[0] + upper_right[0]) / 2.0,
+        (lower_left[1] + upper_right[1]) / 2.0,
+    ];
+    let half = [
+        (upper_right[0] - lower_left[0]) / 2.0 * scale_x,
+        (upper_right[1] - lower_left[1]) / 2.0 * scale_y,
+    ];
+    (
+        [center[0] - half[0], center[1] - half[1]],
+        [center[0] + half[0], center[1] + half[1]],
+    )
+}
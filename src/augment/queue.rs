@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A queue with room for a fixed number of items that makes space for new ones by dropping
+/// the oldest, so a slow consumer only ever sees recent frames and the producer never blocks.
+/// Either side finds out when the other one is dropped.
+pub fn bounded<T>(capacity: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            closed: false,
+        }),
+        ready: Condvar::new(),
+    });
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    /// Set when either end is dropped
+    closed: bool,
+}
+
+impl<T> Shared<T> {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueSender<T> {
+    /// Returns the item that was dropped to make room, if any, so that it can be recycled.
+    /// Fails with the item if the receiver is gone.
+    pub fn send(&self, item: T) -> Result<Option<T>, T> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Err(item);
+        }
+        let dropped = if state.items.len() >= state.capacity {
+            state.items.pop_front()
+        } else {
+            None
+        };
+        state.items.push_back(item);
+        self.shared.ready.notify_one();
+        Ok(dropped)
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueReceiver<T> {
+    /// Returns the oldest item without blocking
+    pub fn try_recv(&self) -> Option<T> {
+        self.shared.state.lock().unwrap().items.pop_front()
+    }
+
+    /// Blocks until there is an item. Returns None once the sender is gone and the queue is
+    /// empty.
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.shared.ready.wait(state).unwrap();
+        }
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// One problem with a shader, pointing at the file and line it is about when that is known
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderMessage {
+    pub file: Option<PathBuf>,
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl ShaderMessage {
+    pub fn new(file: Option<&Path>, message: String) -> ShaderMessage {
+        ShaderMessage {
+            file: file.map(Path::to_path_buf),
+            line: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ShaderMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: {}", file.display(), line, self.message),
+            (Some(file), None) => write!(f, "{}: {}", file.display(), self.message),
+            (None, Some(line)) => write!(f, "line {}: {}", line, self.message),
+            (None, None) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Numbers the files that make up a program. The numbers are used as the source string in
+/// `#line` directives, so the compiler's messages say which file they are about.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<PathBuf>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    /// The number of a file, which is the same every time it is added
+    pub fn add(&mut self, path: &Path) -> u32 {
+        match self.files.iter().position(|file| file == path) {
+            Some(index) => index as u32,
+            None => {
+                self.files.push(path.to_path_buf());
+                self.files.len() as u32 - 1
+            }
+        }
+    }
+
+    pub fn file(&self, index: u32) -> Option<&Path> {
+        self.files.get(index as usize).map(PathBuf::as_path)
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+}
+
+/// The directive that makes the line after it count as `line` of file number `file`. Before
+/// GLSL 3.30 the directive named the line before, and a few drivers still follow the version.
+pub fn line_directive(glsl_version: u32, line: u32, file: u32) -> String {
+    let line = if glsl_version < 330 {
+        line.saturating_sub(1)
+    } else {
+        line
+    };
+    format!("#line {} {}\n", line, file)
+}
+
+/// Splits a compile or link log into messages, mapping the source string numbers back to files.
+/// Lines in a format we don't know are kept as they are.
+pub fn parse_log(log: &str, sources: &SourceMap) -> Vec<ShaderMessage> {
+    log.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match parse_line(line) {
+            Some((file, line, message)) => ShaderMessage {
+                file: sources.file(file).map(Path::to_path_buf),
+                line: Some(line),
+                message,
+            },
+            None => ShaderMessage::new(None, line.to_string()),
+        })
+        .collect()
+}
+
+/// Understands the usual driver formats:
+///
+/// - Mesa: `0:12(5): error: message`
+/// - NVIDIA: `0(12) : error C0000: message`
+/// - AMD, Intel and Apple: `ERROR: 0:12: message`
+fn parse_line(line: &str) -> Option<(u32, u32, String)> {
+    let (severity, rest) = match line.find(": ") {
+        Some(i) if line[..i].chars().all(|c| c.is_ascii_alphabetic()) => {
+            (Some(&line[..i]), &line[i + 2..])
+        }
+        _ => (None, line),
+    };
+    let (file, rest) = split_number(rest)?;
+    let (line_number, rest) = if let Some(rest) = rest.strip_prefix(':') {
+        split_number(rest)?
+    } else {
+        let (line_number, rest) = split_number(rest.strip_prefix('(')?)?;
+        (line_number, rest.strip_prefix(')')?)
+    };
+    // Mesa adds the column
+    let rest = rest
+        .strip_prefix('(')
+        .and_then(split_number)
+        .and_then(|(_, rest)| rest.strip_prefix(')'))
+        .unwrap_or(rest);
+    let message = rest.trim_start_matches(|c| c == ':' || c == ' ').trim_end();
+    let message = match severity {
+        Some(severity) => format!("{}: {}", severity.to_lowercase(), message),
+        None => message.to_string(),
+    };
+    Some((file, line_number, message))
+}
+
+fn split_number(s: &str) -> Option<(u32, &str)> {
+    let end = s
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mesa_lines() {
+        assert_eq!(
+            parse_line("0:12(5): error: `foo' undeclared"),
+            Some((0, 12, "error: `foo' undeclared".to_string()))
+        );
+    }
+
+    #[test]
+    fn parses_nvidia_lines() {
+        assert_eq!(
+            parse_line("1(34) : error C0000: syntax error"),
+            Some((1, 34, "error C0000: syntax error".to_string()))
+        );
+    }
+
+    #[test]
+    fn parses_amd_lines() {
+        assert_eq!(
+            parse_line("ERROR: 2:7: 'x' : undeclared identifier"),
+            Some((2, 7, "error: 'x' : undeclared identifier".to_string()))
+        );
+    }
+
+    #[test]
+    fn leaves_other_lines_alone() {
+        assert_eq!(parse_line("Linking failed: missing main"), None);
+
+        let mut sources = SourceMap::new();
+        sources.add(Path::new("shaders/video.frag"));
+        let messages = parse_log("Linking failed: missing main\n", &sources);
+        assert_eq!(
+            messages,
+            vec![ShaderMessage::new(
+                None,
+                "Linking failed: missing main".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn maps_source_strings_to_files() {
+        let mut sources = SourceMap::new();
+        sources.add(Path::new("shaders/video.frag"));
+        sources.add(Path::new("shaders/common.glsl"));
+        let messages = parse_log(
+            "\n0:3(1): error: first\n  1:9(2): warning: second\n",
+            &sources,
+        );
+        assert_eq!(
+            messages,
+            vec![
+                ShaderMessage {
+                    file: Some(PathBuf::from("shaders/video.frag")),
+                    line: Some(3),
+                    message: "error: first".to_string(),
+                },
+                ShaderMessage {
+                    file: Some(PathBuf::from("shaders/common.glsl")),
+                    line: Some(9),
+                    message: "warning: second".to_string(),
+                },
+            ]
+        );
+    }
+}
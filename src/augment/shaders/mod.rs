@@ -0,0 +1,212 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    time::Duration,
+};
+
+use glium::backend::Facade;
+use glium::program::ProgramCreationInput;
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::error::AugmentError;
+
+mod diagnostics;
+mod preprocess;
+mod stages;
+
+pub use self::diagnostics::ShaderMessage;
+use self::diagnostics::SourceMap;
+pub use self::stages::{ProgramSource, Stage, Stages};
+
+/// A program that a `ProgramHandle` can build from its stages
+pub trait ShaderProgram: Sized {
+    fn build<F: Facade + ?Sized>(
+        facade: &F,
+        stages: &Stages,
+        sources: &SourceMap,
+    ) -> Result<Self, AugmentError>;
+}
+
+impl ShaderProgram for glium::Program {
+    fn build<F: Facade + ?Sized>(
+        facade: &F,
+        stages: &Stages,
+        sources: &SourceMap,
+    ) -> Result<Self, AugmentError> {
+        stages.check(
+            &[Stage::Vertex, Stage::Fragment],
+            &[
+                Stage::TessellationControl,
+                Stage::TessellationEvaluation,
+                Stage::Geometry,
+            ],
+        )?;
+        // Without this glium turns GL_PROGRAM_POINT_SIZE off and gl_PointSize does nothing
+        let uses_point_size = [
+            Stage::Vertex,
+            Stage::TessellationEvaluation,
+            Stage::Geometry,
+        ]
+        .iter()
+        .filter_map(|stage| stages.get(*stage))
+        .any(|source| source.contains("gl_PointSize"));
+        let code = ProgramCreationInput::SourceCode {
+            vertex_shader: stages.get(Stage::Vertex).unwrap_or_default(),
+            tessellation_control_shader: stages.get(Stage::TessellationControl),
+            tessellation_evaluation_shader: stages.get(Stage::TessellationEvaluation),
+            geometry_shader: stages.get(Stage::Geometry),
+            fragment_shader: stages.get(Stage::Fragment).unwrap_or_default(),
+            transform_feedback_varyings: None,
+            outputs_srgb: false,
+            uses_point_size,
+        };
+        glium::Program::new(facade, code).map_err(|err| program_error(err, sources))
+    }
+}
+
+/// A program that is loaded again whenever one of its files changes
+pub struct ProgramHandle<P: ShaderProgram = glium::Program> {
+    source: ProgramSource,
+    defines: Vec<(String, String)>,
+    /// Every file the program was last loaded from, including the ones it `#include`s
+    files: Vec<PathBuf>,
+    /// Whether `poll` reloads the program when one of its files changes
+    reload: bool,
+    watcher: RecommendedWatcher,
+    listener: Receiver<DebouncedEvent>,
+    program: Result<P, AugmentError>,
+}
+
+impl<P: ShaderProgram> std::fmt::Debug for ProgramHandle<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProgramHandle({:?})", self.source)
+    }
+}
+
+impl ProgramHandle {
+    pub fn new<F: Facade + ?Sized>(
+        facade: &F,
+        vertex_shader: &Path,
+        fragment_shader: &Path,
+    ) -> Result<ProgramHandle, AugmentError> {
+        ProgramHandle::load(
+            facade,
+            ProgramSource::new(vertex_shader, fragment_shader),
+            &[],
+        )
+    }
+}
+
+impl<P: ShaderProgram> ProgramHandle<P> {
+    pub fn poll<F: Facade + ?Sized>(&mut self, facade: &F) {
+        if self.reload && self.listener.try_recv().is_ok() {
+            let (program, files) = load(facade, &self.source, &self.defines);
+            self.program = program;
+            self.watch(files);
+        }
+    }
+
+    pub fn as_program(&self) -> Result<&P, &AugmentError> {
+        let progref = self.program.as_ref();
+        let prog = progref?;
+        Ok(&prog)
+    }
+
+    /// Each `(name, value)` in `defines` is added to every stage as `#define name value`, right
+    /// after its `#version`
+    pub fn load<F: Facade + ?Sized>(
+        facade: &F,
+        source: ProgramSource,
+        defines: &[(String, String)],
+    ) -> Result<ProgramHandle<P>, AugmentError> {
+        let (tx, rx) = channel();
+        let watcher = watcher(tx, Duration::from_millis(50)).map_err(|err| {
+            AugmentError::Shader(vec![ShaderMessage::new(
+                None,
+                format!("Can't watch shaders for changes: {}", err),
+            )])
+        })?;
+
+        let (program, files) = load(facade, &source, defines);
+        let mut handle = ProgramHandle {
+            program,
+            files: vec![],
+            reload: cfg!(debug_assertions),
+            watcher,
+            listener: rx,
+            source,
+            defines: defines.to_vec(),
+        };
+        handle.watch(files);
+        Ok(handle)
+    }
+
+    /// Reloads the program when it changes in release builds too, not just in debug builds
+    pub fn always_reload(mut self) -> ProgramHandle<P> {
+        self.reload = true;
+        let files = std::mem::take(&mut self.files);
+        self.watch(files);
+        self
+    }
+
+    /// Watches the files the program was just loaded from, and stops watching the ones it no
+    /// longer includes
+    fn watch(&mut self, files: Vec<PathBuf>) {
+        if self.reload {
+            for file in &self.files {
+                if !files.contains(file) {
+                    let _ = self.watcher.unwatch(file);
+                }
+            }
+            // Editors that save by renaming replace the file, which ends the old watch
+            for file in &files {
+                watch(&mut self.watcher, file);
+            }
+        }
+        self.files = files;
+    }
+}
+
+/// A shader that is missing or can't be watched just isn't reloaded; loading it reports why
+fn watch(watcher: &mut RecommendedWatcher, path: &Path) {
+    if let Err(err) = watcher.watch(path, RecursiveMode::NonRecursive) {
+        println!("Not watching {:?} for changes: {}", path, err);
+    }
+}
+
+/// Loads a program and prints why it didn't load, once for every time it is loaded. Compile and
+/// link errors point at the file and line they are about, including in included files. Also
+/// returns the files to watch, which are all the files it was read from even if it didn't
+/// compile.
+fn load<F: Facade + ?Sized, P: ShaderProgram>(
+    facade: &F,
+    source: &ProgramSource,
+    defines: &[(String, String)],
+) -> (Result<P, AugmentError>, Vec<PathBuf>) {
+    let mut sources = SourceMap::new();
+    let program = source
+        .read(defines, &mut sources)
+        .and_then(|stages| P::build(facade, &stages, &sources));
+    if let Err(err) = &program {
+        println!("{}", err);
+    }
+    let mut files: Vec<PathBuf> = source.paths().into_iter().map(Path::to_path_buf).collect();
+    for file in sources.files() {
+        if !files.contains(file) {
+            files.push(file.clone());
+        }
+    }
+    (program, files)
+}
+
+fn program_error(err: glium::ProgramCreationError, sources: &SourceMap) -> AugmentError {
+    use glium::ProgramCreationError::*;
+    let mut messages = match &err {
+        CompilationError(log, ..) | LinkingError(log) => diagnostics::parse_log(log, sources),
+        _ => vec![],
+    };
+    if messages.is_empty() {
+        messages.push(ShaderMessage::new(None, err.to_string()));
+    }
+    AugmentError::Shader(messages)
+}
@@ -0,0 +1,310 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::diagnostics::{self, SourceMap};
+use super::ShaderMessage;
+use crate::augment::error::AugmentError;
+
+/// Reads a shader, adds `defines` after its `#version` and pastes in the files it `#include`s.
+/// `#line` directives tell the compiler which file and line each part came from.
+pub fn read_shader(
+    path: &Path,
+    defines: &[(String, String)],
+    sources: &mut SourceMap,
+) -> Result<String, AugmentError> {
+    let text = read_file(path)?;
+    preprocess(path, &text, defines, sources)
+}
+
+pub fn read_file(path: &Path) -> Result<String, AugmentError> {
+    std::fs::read_to_string(path)
+        .map_err(|err| AugmentError::Shader(vec![ShaderMessage::new(Some(path), err.to_string())]))
+}
+
+/// Like `read_shader`, for text that was already read from `path`
+pub fn preprocess(
+    path: &Path,
+    text: &str,
+    defines: &[(String, String)],
+    sources: &mut SourceMap,
+) -> Result<String, AugmentError> {
+    preprocess_with(path, text, defines, sources, &|path| {
+        std::fs::read_to_string(path)
+    })
+}
+
+/// Like `preprocess`, reading included files with `read`
+fn preprocess_with(
+    path: &Path,
+    text: &str,
+    defines: &[(String, String)],
+    sources: &mut SourceMap,
+    read: &dyn Fn(&Path) -> io::Result<String>,
+) -> Result<String, AugmentError> {
+    let version = text
+        .lines()
+        .enumerate()
+        .find_map(|(i, line)| glsl_version(line).map(|version| (i, version)));
+    let mut preprocessor = Preprocessor {
+        sources,
+        read,
+        included: vec![path.to_path_buf()],
+        // Shaders without a #version are GLSL 1.10
+        version: 110,
+        output: String::with_capacity(text.len() + 16),
+    };
+    let first_line = match version {
+        Some((version_line, version)) => {
+            for line in text.lines().take(version_line + 1) {
+                preprocessor.push_line(line);
+            }
+            preprocessor.version = version;
+            version_line + 1
+        }
+        None => 0,
+    };
+    for (name, value) in defines {
+        preprocessor.push_line(&format!("#define {} {}", name, value));
+    }
+    preprocessor.paste(path, text, first_line)?;
+    Ok(preprocessor.output)
+}
+
+/// The version number of a `#version` line
+fn glsl_version(line: &str) -> Option<u32> {
+    let version = line.trim_start().strip_prefix("#version")?;
+    version.split_whitespace().next()?.parse().ok()
+}
+
+struct Preprocessor<'a> {
+    sources: &'a mut SourceMap,
+    read: &'a dyn Fn(&Path) -> io::Result<String>,
+    /// Files that are already part of the shader. Each file is only pasted in once, so shared
+    /// chunks don't need include guards of their own and can include each other freely.
+    included: Vec<PathBuf>,
+    version: u32,
+    output: String,
+}
+
+impl Preprocessor<'_> {
+    fn push_line(&mut self, line: &str) {
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+
+    /// Copies `text` from `first_line` (counting from 0) on, replacing each `#include` with the
+    /// file it names
+    fn paste(&mut self, path: &Path, text: &str, first_line: usize) -> Result<(), AugmentError> {
+        let file = self.sources.add(path);
+        let version = self.version;
+        self.output.push_str(&diagnostics::line_directive(
+            version,
+            first_line as u32 + 1,
+            file,
+        ));
+        for (i, line) in text.lines().enumerate().skip(first_line) {
+            let line_number = i as u32 + 1;
+            if let Some(include) = include_path(line) {
+                let include = include.map_err(|message| error_at(path, line_number, message))?;
+                // Includes are relative to the file they are in
+                let include = path.parent().unwrap_or_else(|| Path::new("")).join(include);
+                if !self.included.contains(&include) {
+                    self.included.push(include.clone());
+                    let text = (self.read)(&include).map_err(|err| {
+                        let message = format!("Can't include {}: {}", include.display(), err);
+                        error_at(path, line_number, message)
+                    })?;
+                    self.paste(&include, &text, 0)?;
+                }
+                // Takes the place of the #include line, so the lines after it keep their numbers
+                self.output
+                    .push_str(&diagnostics::line_directive(version, line_number + 1, file));
+            } else if glsl_version(line).is_some() {
+                let message = "#version can only be at the top of the main shader file";
+                return Err(error_at(path, line_number, message.to_string()));
+            } else {
+                self.push_line(line);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The file named by an `#include "file.glsl"` line, or None for any other line
+fn include_path(line: &str) -> Option<Result<&str, String>> {
+    let rest = line.trim_start().strip_prefix('#')?;
+    let rest = rest.trim_start().strip_prefix("include")?;
+    let name = rest
+        .trim()
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|name| !name.is_empty() && !name.contains('"'));
+    Some(name.ok_or_else(|| format!("Expected #include \"file\", found {}", line.trim())))
+}
+
+pub fn error_at(path: &Path, line: u32, message: String) -> AugmentError {
+    AugmentError::Shader(vec![ShaderMessage {
+        file: Some(path.to_path_buf()),
+        line: Some(line),
+        message,
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Preprocesses `shaders/main.frag`, with `files` standing in for the files it includes
+    fn run(
+        text: &str,
+        defines: &[(&str, &str)],
+        files: &[(&str, &str)],
+    ) -> Result<String, AugmentError> {
+        let defines: Vec<(String, String)> = defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let read = |path: &Path| {
+            files
+                .iter()
+                .find(|(name, _)| Path::new(name) == path)
+                .map(|(_, text)| text.to_string())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not found"))
+        };
+        preprocess_with(
+            Path::new("shaders/main.frag"),
+            text,
+            &defines,
+            &mut SourceMap::new(),
+            &read,
+        )
+    }
+
+    fn lines(output: &str) -> Vec<&str> {
+        output.lines().collect()
+    }
+
+    /// The one message of a shader error
+    fn message(err: AugmentError) -> ShaderMessage {
+        match err {
+            AugmentError::Shader(mut messages) if messages.len() == 1 => messages.remove(0),
+            err => panic!("Expected one shader message, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn defines_go_after_the_version() {
+        let output = run(
+            "// Comment\n#version 330\nvoid main() {}\n",
+            &[("SAMPLES", "4"), ("DEBUG", "1")],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            lines(&output),
+            vec![
+                "// Comment",
+                "#version 330",
+                "#define SAMPLES 4",
+                "#define DEBUG 1",
+                "#line 3 0",
+                "void main() {}",
+            ]
+        );
+    }
+
+    #[test]
+    fn shaders_without_a_version_are_glsl_110() {
+        let output = run("void main() {}\n", &[("SAMPLES", "4")], &[]).unwrap();
+        // Before 3.30 #line names the line before the next one
+        assert_eq!(
+            lines(&output),
+            vec!["#define SAMPLES 4", "#line 0 0", "void main() {}"]
+        );
+    }
+
+    #[test]
+    fn includes_are_numbered_and_the_lines_after_them_keep_theirs() {
+        let output = run(
+            "#version 330\nuniform float a;\n#include \"common.glsl\"\nvoid main() {}\n",
+            &[],
+            &[("shaders/common.glsl", "float b;\nfloat c;\n")],
+        )
+        .unwrap();
+        assert_eq!(
+            lines(&output),
+            vec![
+                "#version 330",
+                "#line 2 0",
+                "uniform float a;",
+                "#line 1 1",
+                "float b;",
+                "float c;",
+                "#line 4 0",
+                "void main() {}",
+            ]
+        );
+    }
+
+    #[test]
+    fn each_file_is_included_once() {
+        let output = run(
+            "#version 330\n#include \"a.glsl\"\n#include \"b.glsl\"\n#include \"a.glsl\"\n",
+            &[],
+            &[
+                ("shaders/a.glsl", "#include \"b.glsl\"\nfloat a;\n"),
+                ("shaders/b.glsl", "#include \"a.glsl\"\nfloat b;\n"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            lines(&output),
+            vec![
+                "#version 330",
+                "#line 2 0",
+                "#line 1 1",
+                "#line 1 2",
+                "#line 2 2",
+                "float b;",
+                "#line 2 1",
+                "float a;",
+                "#line 3 0",
+                "#line 4 0",
+                "#line 5 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn version_is_only_allowed_at_the_top_of_the_main_file() {
+        let err = run(
+            "#version 330\n#include \"common.glsl\"\n",
+            &[],
+            &[("shaders/common.glsl", "float a;\n#version 330\n")],
+        )
+        .unwrap_err();
+        assert_eq!(
+            message(err),
+            ShaderMessage {
+                file: Some(PathBuf::from("shaders/common.glsl")),
+                line: Some(2),
+                message: "#version can only be at the top of the main shader file".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn bad_includes_point_at_the_include_line() {
+        let err = run("#version 330\n\n#include common.glsl\n", &[], &[]).unwrap_err();
+        let error = message(err);
+        assert_eq!(error.file, Some(PathBuf::from("shaders/main.frag")));
+        assert_eq!(error.line, Some(3));
+
+        let err = run("#version 330\n#include \"missing.glsl\"\n", &[], &[]).unwrap_err();
+        let error = message(err);
+        assert_eq!(error.line, Some(2));
+        assert!(error
+            .message
+            .starts_with("Can't include shaders/missing.glsl"));
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::diagnostics::SourceMap;
+use super::preprocess;
+use super::ShaderMessage;
+use crate::augment::error::AugmentError;
+
+/// One stage of the GL pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Vertex,
+    TessellationControl,
+    TessellationEvaluation,
+    Geometry,
+    Fragment,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Vertex,
+        Stage::TessellationControl,
+        Stage::TessellationEvaluation,
+        Stage::Geometry,
+        Stage::Fragment,
+    ];
+
+    /// The name used in `#pragma stage <name>`
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Vertex => "vertex",
+            Stage::TessellationControl => "tessellation_control",
+            Stage::TessellationEvaluation => "tessellation_evaluation",
+            Stage::Geometry => "geometry",
+            Stage::Fragment => "fragment",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Stage> {
+        Stage::ALL
+            .iter()
+            .copied()
+            .find(|stage| stage.name() == name)
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Where the shaders of a program come from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ProgramSource {
+    /// A file for each stage
+    Files(Vec<(Stage, PathBuf)>),
+    /// One file with a `#pragma stage <name>` line at the start of each stage. Lines before the
+    /// first of them, such as the `#version`, are part of every stage.
+    SingleFile(PathBuf),
+}
+
+impl ProgramSource {
+    pub fn new(vertex_shader: &Path, fragment_shader: &Path) -> ProgramSource {
+        ProgramSource::Files(vec![
+            (Stage::Vertex, vertex_shader.to_path_buf()),
+            (Stage::Fragment, fragment_shader.to_path_buf()),
+        ])
+    }
+
+    pub fn single_file(path: &Path) -> ProgramSource {
+        ProgramSource::SingleFile(path.to_path_buf())
+    }
+
+    /// Adds a geometry or tessellation shader to a program made of separate files. A single
+    /// file program has its stages in the file, so nothing can be added to it.
+    pub fn with_stage(self, stage: Stage, path: &Path) -> Result<ProgramSource, AugmentError> {
+        match self {
+            ProgramSource::Files(mut files) => {
+                files.retain(|(s, _)| *s != stage);
+                files.push((stage, path.to_path_buf()));
+                Ok(ProgramSource::Files(files))
+            }
+            ProgramSource::SingleFile(file) => Err(AugmentError::Shader(vec![ShaderMessage::new(
+                Some(&file),
+                format!(
+                    "Can't add the {} shader {:?}, put it in this file instead",
+                    stage, path
+                ),
+            )])),
+        }
+    }
+
+    /// The files named here, not counting the ones they include
+    pub fn paths(&self) -> Vec<&Path> {
+        match self {
+            ProgramSource::Files(files) => files.iter().map(|(_, path)| path.as_path()).collect(),
+            ProgramSource::SingleFile(path) => vec![path.as_path()],
+        }
+    }
+
+    /// Reads and preprocesses the source of every stage
+    pub fn read(
+        &self,
+        defines: &[(String, String)],
+        sources: &mut SourceMap,
+    ) -> Result<Stages, AugmentError> {
+        let mut stages = Stages(vec![]);
+        match self {
+            ProgramSource::Files(files) => {
+                for (stage, path) in files {
+                    let source = preprocess::read_shader(path, defines, sources)?;
+                    stages.0.push((*stage, source));
+                }
+            }
+            ProgramSource::SingleFile(path) => {
+                let text = preprocess::read_file(path)?;
+                for (stage, text) in split_stages(path, &text)? {
+                    let source = preprocess::preprocess(path, &text, defines, sources)?;
+                    stages.0.push((stage, source));
+                }
+            }
+        }
+        Ok(stages)
+    }
+}
+
+/// The preprocessed source of each stage of a program
+#[derive(Debug)]
+pub struct Stages(Vec<(Stage, String)>);
+
+impl Stages {
+    pub fn get(&self, stage: Stage) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(s, _)| *s == stage)
+            .map(|(_, source)| source.as_str())
+    }
+
+    /// Fails unless the program has all of `required` and nothing outside `allowed`
+    pub fn check(&self, required: &[Stage], allowed: &[Stage]) -> Result<(), AugmentError> {
+        let mut messages = vec![];
+        for stage in required {
+            if self.get(*stage).is_none() {
+                messages.push(format!("The program has no {} shader", stage));
+            }
+        }
+        for (stage, _) in &self.0 {
+            if !required.contains(stage) && !allowed.contains(stage) {
+                messages.push(format!("A {} shader can't be used here", stage));
+            }
+        }
+        if messages.is_empty() {
+            Ok(())
+        } else {
+            Err(AugmentError::Shader(
+                messages
+                    .into_iter()
+                    .map(|message| ShaderMessage::new(None, message))
+                    .collect(),
+            ))
+        }
+    }
+}
+
+/// Splits a single-file program at its `#pragma stage` lines. Every stage keeps the lines of the
+/// whole file, with the ones that belong to other stages left blank, so line numbers in errors
+/// are the same as in the file.
+fn split_stages(path: &Path, text: &str) -> Result<Vec<(Stage, String)>, AugmentError> {
+    let mut sections: Vec<(usize, Option<Stage>)> = vec![(0, None)];
+    for (i, line) in text.lines().enumerate() {
+        if let Some(name) = stage_pragma(line) {
+            let stage = Stage::from_name(name).ok_or_else(|| {
+                let message = format!("Unknown stage {:?} in #pragma stage", name);
+                preprocess::error_at(path, i as u32 + 1, message)
+            })?;
+            sections.push((i, Some(stage)));
+        }
+    }
+    let mut stages: Vec<Stage> = vec![];
+    for (_, stage) in &sections {
+        if let Some(stage) = stage {
+            if !stages.contains(stage) {
+                stages.push(*stage);
+            }
+        }
+    }
+    if stages.is_empty() {
+        let message = "A single-file program needs a #pragma stage line before each stage";
+        return Err(preprocess::error_at(path, 1, message.to_string()));
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    Ok(stages
+        .into_iter()
+        .map(|stage| {
+            let mut source = String::with_capacity(text.len());
+            for (n, (start, section)) in sections.iter().enumerate() {
+                let end = sections.get(n + 1).map_or(lines.len(), |(end, _)| *end);
+                // The pragma line itself is left blank too
+                let first = if section.is_some() { start + 1 } else { *start };
+                if section.is_some() {
+                    source.push('\n');
+                }
+                let keep = section.map_or(true, |s| s == stage);
+                for line in &lines[first..end] {
+                    if keep {
+                        source.push_str(line);
+                    }
+                    source.push('\n');
+                }
+            }
+            (stage, source)
+        })
+        .collect())
+}
+
+/// The stage name of a `#pragma stage <name>` line
+fn stage_pragma(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('#')?;
+    let rest = rest.trim_start().strip_prefix("pragma")?;
+    let mut words = rest.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("stage"), Some(name)) => Some(name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_stage_replaces_a_stage_of_separate_files() {
+        let source = ProgramSource::new(Path::new("a.vert"), Path::new("a.frag"))
+            .with_stage(Stage::Geometry, Path::new("a.geom"))
+            .unwrap()
+            .with_stage(Stage::Geometry, Path::new("b.geom"))
+            .unwrap();
+        assert_eq!(
+            source.paths(),
+            vec![
+                Path::new("a.vert"),
+                Path::new("a.frag"),
+                Path::new("b.geom")
+            ]
+        );
+    }
+
+    #[test]
+    fn with_stage_rejects_a_single_file() {
+        let source = ProgramSource::single_file(Path::new("a.glsl"));
+        let result = source.with_stage(Stage::Geometry, Path::new("a.geom"));
+        assert!(matches!(result, Err(AugmentError::Shader(_))));
+    }
+}
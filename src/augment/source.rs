@@ -0,0 +1,515 @@
+use ffmpeg::format::Pixel;
+use ffmpeg::util::frame::video::Video;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::*;
+use std::thread;
+use std::time::*;
+
+use super::error::AugmentError;
+use super::queue::{self, QueueReceiver};
+use super::video::{
+    self, FramePool, Input, PlaybackCommand, TimedFrame, VideoOptions, VideoReader,
+};
+
+/// Decoded frames waiting for the renderer. The decoder paces itself, so this only fills up
+/// when rendering falls behind, and then the oldest frames are dropped.
+const DECODE_QUEUE_FRAMES: usize = 3;
+
+/// How long a failed source waits before it is reopened. The wait doubles with every failure in
+/// a row, up to `MAX_RESTART_DELAY`.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+
+/// Anything that produces video frames for the augment pipeline
+pub trait FrameSource {
+    /// Returns the newest frame that is ready, without blocking. Once this fails the source has
+    /// stopped and has to be opened again.
+    fn poll_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError>;
+
+    /// Blocks until the next frame is ready. Returns None at the end of the source.
+    fn wait_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError>;
+
+    /// Sources that can't be controlled ignore playback commands
+    fn send_command(&mut self, _command: PlaybackCommand) {}
+}
+
+/// Which source to open, as given on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceSpec {
+    File(PathBuf),
+    Camera(PathBuf),
+    ImageSequence(String),
+    Image(PathBuf),
+    TestPattern,
+}
+
+impl FromStr for SourceSpec {
+    type Err = String;
+
+    /// Parses "test-pattern", "v4l2:/dev/videoN", a numbered image pattern containing '%', a
+    /// still image, or any file ffmpeg can open
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "test-pattern" {
+            return Ok(SourceSpec::TestPattern);
+        }
+        if let Some(device) = s.strip_prefix("v4l2:") {
+            return Ok(SourceSpec::Camera(PathBuf::from(device)));
+        }
+        if s.contains('%') {
+            return Ok(SourceSpec::ImageSequence(s.to_string()));
+        }
+        let path = PathBuf::from(s);
+        let is_image = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| {
+                let ext = ext.to_lowercase();
+                ext == "png" || ext == "jpg" || ext == "jpeg" || ext == "bmp"
+            })
+            .unwrap_or(false);
+        if is_image {
+            Ok(SourceSpec::Image(path))
+        } else {
+            Ok(SourceSpec::File(path))
+        }
+    }
+}
+
+/// Parses a frame rate, which has to be a positive number of frames per second
+pub fn parse_fps(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fps) if fps.is_finite() && fps > 0.0 => Ok(fps),
+        Ok(_) => Err(format!("The frame rate has to be above 0, not {}", s)),
+        Err(err) => Err(format!("Invalid frame rate '{}': {}", s, err)),
+    }
+}
+
+/// `frame_rate` is used by the sources that don't have a rate of their own
+pub fn open(
+    spec: &SourceSpec,
+    options: VideoOptions,
+    frame_rate: f64,
+) -> Result<Box<dyn FrameSource>, AugmentError> {
+    Ok(match spec {
+        SourceSpec::File(path) => Box::new(DecoderSource::spawn(Input::File(path.clone()), options)),
+        SourceSpec::Camera(device) => {
+            Box::new(DecoderSource::spawn(Input::Camera(device.clone()), options))
+        }
+        SourceSpec::ImageSequence(pattern) => Box::new(DecoderSource::spawn(
+            Input::ImageSequence {
+                pattern: pattern.clone(),
+                frame_rate,
+            },
+            options,
+        )),
+        SourceSpec::Image(path) => Box::new(StillImage::open(path, &options, frame_rate)?),
+        SourceSpec::TestPattern => Box::new(TestPattern::new(&options, frame_rate)),
+    })
+}
+
+/// Opens a source that hands out every frame as soon as it is asked for one instead of
+/// keeping to the frame rate, for rendering offline. Capture devices can't be read this way.
+pub fn open_offline(
+    spec: &SourceSpec,
+    options: VideoOptions,
+    frame_rate: f64,
+) -> Result<Box<dyn FrameSource>, AugmentError> {
+    Ok(match spec {
+        SourceSpec::File(path) => Box::new(ReaderSource(VideoReader::open(
+            &Input::File(path.clone()),
+            &options,
+        )?)),
+        SourceSpec::Camera(_) => {
+            return Err(AugmentError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Capture devices can't be rendered offline",
+            )))
+        }
+        SourceSpec::ImageSequence(pattern) => Box::new(ReaderSource(VideoReader::open(
+            &Input::ImageSequence {
+                pattern: pattern.clone(),
+                frame_rate,
+            },
+            &options,
+        )?)),
+        SourceSpec::Image(path) => {
+            let mut image = StillImage::open(path, &options, frame_rate)?;
+            image.timer.paced = false;
+            Box::new(image)
+        }
+        SourceSpec::TestPattern => {
+            let mut pattern = TestPattern::new(&options, frame_rate);
+            pattern.timer.paced = false;
+            Box::new(pattern)
+        }
+    })
+}
+
+/// Decodes a file or capture device with ffmpeg on a background thread
+pub struct DecoderSource {
+    frames: QueueReceiver<TimedFrame>,
+    commands: Sender<PlaybackCommand>,
+    /// Why the decoder thread stopped, if it failed
+    errors: Receiver<AugmentError>,
+    pool: FramePool,
+}
+
+impl DecoderSource {
+    pub fn spawn(input: Input, options: VideoOptions) -> DecoderSource {
+        let (tx, rx) = queue::bounded(DECODE_QUEUE_FRAMES);
+        let (command_tx, command_rx) = channel();
+        let (error_tx, error_rx) = channel();
+        let pool = options.pool.clone();
+        thread::spawn(move || {
+            if let Err(err) = video::load_video(&input, options, tx, command_rx) {
+                let _ = error_tx.send(err);
+            }
+        });
+        DecoderSource {
+            frames: rx,
+            commands: command_tx,
+            errors: error_rx,
+            pool,
+        }
+    }
+}
+
+impl FrameSource for DecoderSource {
+    fn poll_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError> {
+        // The decoder paces itself, so if we fell behind only the newest frame matters
+        let mut newest = match self.frames.try_recv() {
+            Some(frame) => frame,
+            None => return self.errors.try_recv().map_or(Ok(None), Err),
+        };
+        while let Some(frame) = self.frames.try_recv() {
+            self.pool.give(std::mem::replace(&mut newest, frame).frame);
+        }
+        Ok(Some(newest))
+    }
+
+    fn wait_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError> {
+        match self.frames.recv() {
+            Some(frame) => Ok(Some(frame)),
+            // The thread closes the queue just before it reports why it stopped
+            None => self.errors.recv().map_or(Ok(None), Err),
+        }
+    }
+
+    fn send_command(&mut self, command: PlaybackCommand) {
+        if self.commands.send(command).is_err() {
+            println!("Video decoder is not running");
+        }
+    }
+}
+
+/// Decodes on the calling thread, one frame per call
+struct ReaderSource(VideoReader);
+
+impl FrameSource for ReaderSource {
+    fn poll_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError> {
+        self.wait_frame()
+    }
+
+    fn wait_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError> {
+        self.0.next_frame()
+    }
+}
+
+/// Keeps a source going for the live view by opening it again a little while after it fails,
+/// e.g. when a camera is unplugged or a file is missing or being rewritten
+pub struct RestartingSource {
+    spec: SourceSpec,
+    options: VideoOptions,
+    frame_rate: f64,
+    source: Option<Box<dyn FrameSource>>,
+    /// Why the source stopped, until it produces a frame again
+    error: Option<AugmentError>,
+    restart_at: Instant,
+    restart_delay: Duration,
+    /// The last rate and loop mode, which a reopened source is told about again
+    settings: Vec<PlaybackCommand>,
+}
+
+impl RestartingSource {
+    /// Never fails; if the source can't be opened yet that is reported by `error`
+    pub fn open(spec: &SourceSpec, options: VideoOptions, frame_rate: f64) -> RestartingSource {
+        let mut source = RestartingSource {
+            spec: spec.clone(),
+            options,
+            frame_rate,
+            source: None,
+            error: None,
+            restart_at: Instant::now(),
+            restart_delay: MIN_RESTART_DELAY,
+            settings: vec![],
+        };
+        source.restart();
+        source
+    }
+
+    pub fn spec(&self) -> &SourceSpec {
+        &self.spec
+    }
+
+    /// Why the source isn't running, and how long until it is opened again
+    pub fn error(&self) -> Option<(&AugmentError, Duration)> {
+        let wait = self.restart_at.saturating_duration_since(Instant::now());
+        self.error.as_ref().map(|err| (err, wait))
+    }
+
+    /// Returns the newest frame that is ready, reopening the source if it is due
+    pub fn poll_frame(&mut self) -> Option<TimedFrame> {
+        if self.source.is_none() && Instant::now() >= self.restart_at {
+            self.restart();
+        }
+        let polled = self.source.as_mut()?.poll_frame();
+        match polled {
+            Ok(Some(frame)) => {
+                self.error = None;
+                self.restart_delay = MIN_RESTART_DELAY;
+                Some(frame)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                self.fail(err);
+                None
+            }
+        }
+    }
+
+    pub fn send_command(&mut self, command: PlaybackCommand) {
+        if let PlaybackCommand::SetRate(_) | PlaybackCommand::SetLoopMode(_) = command {
+            let kind = std::mem::discriminant(&command);
+            self.settings
+                .retain(|setting| std::mem::discriminant(setting) != kind);
+            self.settings.push(command);
+        }
+        match self.source.as_mut() {
+            Some(source) => source.send_command(command),
+            None => println!("The source is not running"),
+        }
+    }
+
+    fn restart(&mut self) {
+        match open(&self.spec, self.options.clone(), self.frame_rate) {
+            Ok(mut source) => {
+                for &setting in &self.settings {
+                    source.send_command(setting);
+                }
+                self.source = Some(source);
+            }
+            Err(err) => self.fail(err),
+        }
+    }
+
+    fn fail(&mut self, err: AugmentError) {
+        println!(
+            "{}. Opening {:?} again in {}s",
+            err,
+            self.spec,
+            self.restart_delay.as_secs()
+        );
+        self.source = None;
+        self.restart_at = Instant::now() + self.restart_delay;
+        self.restart_delay = (self.restart_delay * 2).min(MAX_RESTART_DELAY);
+        self.error = Some(err);
+    }
+}
+
+/// Shared pacing for the sources that generate frames on demand
+struct FrameTimer {
+    frame_duration: Duration,
+    start: Instant,
+    next_frame: u64,
+    paused: bool,
+    step: bool,
+    /// When false frames are produced as fast as they are asked for
+    paced: bool,
+}
+
+impl FrameTimer {
+    fn new(frame_rate: f64) -> FrameTimer {
+        FrameTimer {
+            frame_duration: Duration::from_secs_f64(1.0 / frame_rate),
+            start: Instant::now(),
+            next_frame: 0,
+            paused: false,
+            step: false,
+            paced: true,
+        }
+    }
+
+    fn timestamp(&self, index: u64) -> Duration {
+        self.frame_duration * index as u32
+    }
+
+    /// Returns the timestamp of the next frame if it is due
+    fn poll(&mut self) -> Option<Duration> {
+        if self.step {
+            self.step = false;
+        } else if self.paused
+            || (self.paced && self.start.elapsed() < self.timestamp(self.next_frame))
+        {
+            return None;
+        }
+        let timestamp = self.timestamp(self.next_frame);
+        self.next_frame += 1;
+        Some(timestamp)
+    }
+
+    fn wait(&mut self) -> Duration {
+        loop {
+            if let Some(timestamp) = self.poll() {
+                return timestamp;
+            }
+            thread::sleep(self.frame_duration / 4);
+        }
+    }
+
+    fn handle_command(&mut self, command: PlaybackCommand) {
+        match command {
+            PlaybackCommand::TogglePause => {
+                self.paused = !self.paused;
+                if !self.paused {
+                    self.start = Instant::now() - self.timestamp(self.next_frame);
+                }
+            }
+            PlaybackCommand::Step => {
+                self.paused = true;
+                self.step = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Repeats a single image at a fixed frame rate
+pub struct StillImage {
+    frame: Video,
+    pool: FramePool,
+    timer: FrameTimer,
+}
+
+impl StillImage {
+    pub fn open(
+        path: &Path,
+        options: &VideoOptions,
+        frame_rate: f64,
+    ) -> Result<StillImage, AugmentError> {
+        Ok(StillImage {
+            frame: video::load_image(path, options)?,
+            pool: options.pool.clone(),
+            timer: FrameTimer::new(frame_rate),
+        })
+    }
+
+    fn copy_frame(&self) -> Video {
+        let mut frame =
+            self.pool
+                .take(self.frame.format(), self.frame.width(), self.frame.height());
+        frame.clone_from(&self.frame);
+        frame
+    }
+}
+
+impl FrameSource for StillImage {
+    fn poll_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError> {
+        Ok(self.timer.poll().map(|timestamp| TimedFrame {
+            frame: self.copy_frame(),
+            timestamp,
+        }))
+    }
+
+    fn wait_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError> {
+        let timestamp = self.timer.wait();
+        Ok(Some(TimedFrame {
+            frame: self.copy_frame(),
+            timestamp,
+        }))
+    }
+
+    fn send_command(&mut self, command: PlaybackCommand) {
+        self.timer.handle_command(command);
+    }
+}
+
+/// Dim color bars with a bright disc moving across them, so that the object detection has
+/// something to find without any media files
+pub struct TestPattern {
+    width: u32,
+    height: u32,
+    pool: FramePool,
+    timer: FrameTimer,
+}
+
+const BARS: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+
+impl TestPattern {
+    pub fn new(options: &VideoOptions, frame_rate: f64) -> TestPattern {
+        let (width, height) = options.size.resolve(640, 480);
+        TestPattern {
+            width,
+            height,
+            pool: options.pool.clone(),
+            timer: FrameTimer::new(frame_rate),
+        }
+    }
+
+    /// Frames only depend on the timestamp so that runs are reproducible
+    pub fn render(&self, timestamp: Duration) -> Video {
+        let t = timestamp.as_secs_f64();
+        let (width, height) = (self.width as usize, self.height as usize);
+        let center_x = width as f64 * (0.5 + 0.35 * t.cos());
+        let center_y = height as f64 * (0.5 + 0.35 * (2.0 * t).sin());
+        let radius = height as f64 / 10.0;
+
+        let mut frame = self.pool.take(Pixel::RGB24, self.width, self.height);
+        let stride = frame.stride(0);
+        let data = frame.data_mut(0);
+        for y in 0..height {
+            let row = &mut data[y * stride..y * stride + width * 3];
+            for x in 0..width {
+                let dx = x as f64 - center_x;
+                let dy = y as f64 - center_y;
+                let color = if dx * dx + dy * dy < radius * radius {
+                    [255, 255, 255]
+                } else {
+                    BARS[x * BARS.len() / width]
+                };
+                row[x * 3..x * 3 + 3].copy_from_slice(&color);
+            }
+        }
+        frame
+    }
+}
+
+impl FrameSource for TestPattern {
+    fn poll_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError> {
+        Ok(self.timer.poll().map(|timestamp| TimedFrame {
+            frame: self.render(timestamp),
+            timestamp,
+        }))
+    }
+
+    fn wait_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError> {
+        let timestamp = self.timer.wait();
+        Ok(Some(TimedFrame {
+            frame: self.render(timestamp),
+            timestamp,
+        }))
+    }
+
+    fn send_command(&mut self, command: PlaybackCommand) {
+        self.timer.handle_command(command);
+    }
+}
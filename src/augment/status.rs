@@ -0,0 +1,214 @@
+use ffmpeg::format::Pixel;
+use ffmpeg::util::frame::video::Video;
+use glium::backend::Facade;
+use glium::texture::{MipmapsOption, Texture2d, UncompressedFloatFormat};
+use glium::Surface;
+use std::path::Path;
+
+use super::error::AugmentError;
+use super::filters;
+use super::shaders::ProgramHandle;
+use super::textures;
+use super::{draw_shape, make_square, Vertex};
+
+const VERTEX_SHADER: &str = "shaders/text.vert";
+const FRAGMENT_SHADER: &str = "shaders/text.frag";
+
+/// Dark red behind the text
+const BACKGROUND: [u8; 3] = [96, 16, 16];
+
+/// Long messages such as shader compile logs are cut off after this many lines
+const MAX_LINES: usize = 12;
+
+/// The program that `TextBox` draws with
+pub fn load_text_program<F: Facade>(facade: &F) -> Result<ProgramHandle, AugmentError> {
+    ProgramHandle::new(facade, Path::new(VERTEX_SHADER), Path::new(FRAGMENT_SHADER))
+}
+
+/// Part of the window in GL coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Area {
+    pub lower_left: [f32; 2],
+    pub upper_right: [f32; 2],
+}
+
+impl Area {
+    pub const WINDOW: Area = Area {
+        lower_left: [-1.0, -1.0],
+        upper_right: [1.0, 1.0],
+    };
+}
+
+/// Which side of its area a text box is drawn against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Top,
+    Bottom,
+}
+
+/// Lines of text on a solid background, as wide as their area and as tall as the text. The
+/// text is only drawn again when it or its area changes.
+#[derive(Default)]
+pub struct TextBox {
+    drawn: Option<DrawnText>,
+}
+
+struct DrawnText {
+    lines: Vec<String>,
+    area: Area,
+    edge: Edge,
+    window_size: (u32, u32),
+    vbo: glium::VertexBuffer<Vertex>,
+    texture: Texture2d,
+}
+
+impl TextBox {
+    pub fn new() -> TextBox {
+        TextBox::default()
+    }
+
+    pub fn draw<F: Facade, S: Surface>(
+        &mut self,
+        facade: &F,
+        target: &mut S,
+        program: &glium::Program,
+        lines: &[String],
+        area: Area,
+        edge: Edge,
+    ) -> Result<(), AugmentError> {
+        let window_size = target.get_dimensions();
+        let lines = &lines[..lines.len().min(MAX_LINES)];
+        let stale = match &self.drawn {
+            Some(drawn) => {
+                drawn.lines != lines
+                    || drawn.area != area
+                    || drawn.edge != edge
+                    || drawn.window_size != window_size
+            }
+            None => true,
+        };
+        if stale {
+            self.drawn = None;
+            // The text is drawn at the window's resolution so that it stays sharp
+            let to_pixels = |gl: f32, size: u32| (gl / 2.0 * size as f32) as u32;
+            let width = to_pixels(area.upper_right[0] - area.lower_left[0], window_size.0);
+            let max_height = to_pixels(area.upper_right[1] - area.lower_left[1], window_size.1);
+            let height = (filters::text_line_height()? * lines.len() as u32).min(max_height);
+            if width == 0 || height == 0 {
+                return Ok(());
+            }
+            let mut image = Video::new(Pixel::RGB24, width, height);
+            filters::draw_text(&mut image, lines, BACKGROUND)?;
+            let mut scratch = vec![];
+            let texture = Texture2d::with_format(
+                facade,
+                textures::rgb_image(&image, &mut scratch),
+                UncompressedFloatFormat::U8U8U8,
+                MipmapsOption::NoMipmap,
+            )?;
+            let gl_height = 2.0 * height as f32 / window_size.1 as f32;
+            let (bottom, top) = match edge {
+                Edge::Top => (area.upper_right[1] - gl_height, area.upper_right[1]),
+                Edge::Bottom => (area.lower_left[1], area.lower_left[1] + gl_height),
+            };
+            let vbo = make_square(
+                [area.lower_left[0], bottom],
+                [area.upper_right[0], top],
+                facade,
+            )?;
+            self.drawn = Some(DrawnText {
+                lines: lines.to_vec(),
+                area,
+                edge,
+                window_size,
+                vbo,
+                texture,
+            });
+        }
+
+        if let Some(drawn) = &self.drawn {
+            draw_shape(
+                target,
+                &drawn.vbo,
+                &glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                program,
+                &uniform! { iText: &drawn.texture },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Errors shown along the bottom of the window until whatever failed is working again
+pub struct StatusOverlay {
+    /// At most one message per part of the pipeline, in the order they first failed
+    messages: Vec<(&'static str, String)>,
+    program: ProgramHandle,
+    text: TextBox,
+}
+
+impl StatusOverlay {
+    pub fn new<F: Facade>(facade: &F) -> Result<StatusOverlay, AugmentError> {
+        Ok(StatusOverlay {
+            messages: vec![],
+            program: load_text_program(facade)?,
+            text: TextBox::new(),
+        })
+    }
+
+    pub fn poll_program<F: Facade>(&mut self, facade: &F) {
+        self.program.poll(facade);
+    }
+
+    /// Replaces the message shown for `origin`, or removes it. Returns true if that changed
+    /// anything.
+    pub fn set(&mut self, origin: &'static str, message: Option<String>) -> bool {
+        let index = self.messages.iter().position(|(o, _)| *o == origin);
+        match (index, message) {
+            (Some(i), Some(message)) if self.messages[i].1 != message => {
+                self.messages[i].1 = message
+            }
+            (None, Some(message)) => self.messages.push((origin, message)),
+            (Some(i), None) => {
+                self.messages.remove(i);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Shows the error from `origin`, or clears it if it succeeded. Errors are printed when they
+    /// first show up rather than on every frame.
+    pub fn report(&mut self, origin: &'static str, result: Result<(), AugmentError>) {
+        let message = result.err().map(|err| err.to_string());
+        if self.set(origin, message.clone()) {
+            if let Some(message) = message {
+                println!("{}", message);
+            }
+        }
+    }
+
+    /// Draws the messages over the bottom of `target`, if there are any
+    pub fn draw<F: Facade, S: Surface>(
+        &mut self,
+        facade: &F,
+        target: &mut S,
+    ) -> Result<(), AugmentError> {
+        let lines: Vec<String> = self
+            .messages
+            .iter()
+            .flat_map(|(_, message)| message.lines())
+            .map(str::to_string)
+            .collect();
+        // Shader errors were already printed when the program was loaded
+        let program = match self.program.as_program() {
+            Ok(program) => program,
+            Err(_) => return Ok(()),
+        };
+        if lines.is_empty() {
+            return Ok(());
+        }
+        self.text
+            .draw(facade, target, program, &lines, Area::WINDOW, Edge::Bottom)
+    }
+}
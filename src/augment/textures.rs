@@ -0,0 +1,88 @@
+use ffmpeg::util::frame::video::Video;
+use glium::backend::Facade;
+use glium::texture::{ClientFormat, MipmapsOption, RawImage2d, Texture2d, UncompressedFloatFormat};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use super::error::AugmentError;
+
+/// Video textures that are allocated once and written to on every frame. They are only
+/// reallocated when the video size changes.
+#[derive(Default)]
+pub struct TextureCache {
+    textures: HashMap<String, Texture2d>,
+    /// Holds the pixels of frames whose rows are padded, since GL wants them packed
+    scratch: Vec<u8>,
+}
+
+impl TextureCache {
+    pub fn new() -> TextureCache {
+        TextureCache::default()
+    }
+
+    /// Copies an RGB24 frame into the texture called `name`
+    pub fn upload<F: Facade + ?Sized>(
+        &mut self,
+        facade: &F,
+        name: &str,
+        frame: &Video,
+    ) -> Result<(), AugmentError> {
+        let size = (frame.width(), frame.height());
+        let reuse =
+            matches!(self.textures.get(name), Some(texture) if texture.dimensions() == size);
+        if !reuse {
+            let texture = Texture2d::empty_with_format(
+                facade,
+                UncompressedFloatFormat::U8U8U8,
+                MipmapsOption::NoMipmap,
+                size.0,
+                size.1,
+            )?;
+            self.textures.insert(name.to_string(), texture);
+        }
+        let image = rgb_image(frame, &mut self.scratch);
+        self.textures[name].write(
+            glium::Rect {
+                left: 0,
+                bottom: 0,
+                width: size.0,
+                height: size.1,
+            },
+            image,
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Texture2d> {
+        self.textures.get(name)
+    }
+
+    /// Frees every texture, e.g. when the sources they were used for are gone
+    pub fn clear(&mut self) {
+        self.textures.clear();
+    }
+}
+
+/// Borrows the pixels of an RGB24 frame when its rows are packed, and packs them into
+/// `scratch` when ffmpeg padded the rows for alignment
+pub fn rgb_image<'a>(frame: &'a Video, scratch: &'a mut Vec<u8>) -> RawImage2d<'a, u8> {
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let row_len = width * 3;
+    let stride = frame.stride(0);
+    let data = frame.data(0);
+    let pixels = if stride == row_len {
+        &data[..row_len * height]
+    } else {
+        scratch.clear();
+        for row in data.chunks(stride).take(height) {
+            scratch.extend_from_slice(&row[..row_len]);
+        }
+        &scratch[..]
+    };
+    RawImage2d {
+        data: Cow::Borrowed(pixels),
+        width: frame.width(),
+        height: frame.height(),
+        format: ClientFormat::U8U8U8,
+    }
+}
@@ -0,0 +1,319 @@
+use cgmath::{InnerSpace, Vector2};
+use serde::Deserialize;
+
+use super::filters::ConnectedComponent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Matching {
+    /// Match boxes by how much they overlap
+    Iou,
+    /// Match by the distance between centers
+    Centroid,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackerParams {
+    pub matching: Matching,
+    /// Smallest overlap that counts as the same object when matching by IoU
+    pub min_iou: f32,
+    /// Largest distance in pixels that counts as the same object when matching by centroid
+    pub max_distance: f32,
+    /// Frames an object has to be seen before it is reported
+    pub spawn_frames: u32,
+    /// Frames an object can go missing before it is dropped
+    pub despawn_frames: u32,
+    /// How much the velocity is allowed to change between frames. Higher is more responsive,
+    /// lower is smoother.
+    pub process_noise: f32,
+    /// How much the detected positions jitter, in pixels
+    pub measurement_noise: f32,
+}
+
+impl Default for TrackerParams {
+    fn default() -> Self {
+        TrackerParams {
+            matching: Matching::Iou,
+            min_iou: 0.1,
+            max_distance: 50.0,
+            spawn_frames: 3,
+            despawn_frames: 10,
+            process_noise: 200.0,
+            measurement_noise: 4.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackEvent {
+    /// An object has been seen for long enough to be reported
+    Enter(u64),
+    /// An object has been missing for longer than the grace period
+    Exit(u64),
+    Move {
+        id: u64,
+        position: Vector2<f32>,
+        velocity: Vector2<f32>,
+    },
+}
+
+/// Constant velocity Kalman filter for a single axis
+#[derive(Debug, Clone)]
+struct Kalman {
+    position: f32,
+    velocity: f32,
+    covariance: [[f32; 2]; 2],
+}
+
+impl Kalman {
+    fn new(position: f32, measurement_noise: f32) -> Kalman {
+        let r = measurement_noise * measurement_noise;
+        Kalman {
+            position,
+            velocity: 0.0,
+            // We know nothing about the velocity yet
+            covariance: [[r, 0.0], [0.0, 1e4]],
+        }
+    }
+
+    fn predict(&mut self, dt: f32, process_noise: f32) {
+        self.position += self.velocity * dt;
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        // Velocity is perturbed by white noise acceleration
+        let q = process_noise * process_noise;
+        let dt2 = dt * dt;
+        self.covariance = [
+            [
+                p00 + dt * (p10 + p01) + dt2 * p11 + q * dt2 * dt2 / 4.0,
+                p01 + dt * p11 + q * dt2 * dt / 2.0,
+            ],
+            [p10 + dt * p11 + q * dt2 * dt / 2.0, p11 + q * dt2],
+        ];
+    }
+
+    fn update(&mut self, measurement: f32, measurement_noise: f32) {
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let s = p00 + measurement_noise * measurement_noise;
+        let k0 = p00 / s;
+        let k1 = p10 / s;
+        let residual = measurement - self.position;
+        self.position += k0 * residual;
+        self.velocity += k1 * residual;
+        self.covariance = [
+            [(1.0 - k0) * p00, (1.0 - k0) * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackedObject {
+    pub id: u64,
+    /// The most recent detection of this object
+    pub component: ConnectedComponent,
+    /// Frames since the object was first seen
+    pub age: u32,
+    /// Frames since the object was last detected
+    pub missed: u32,
+    confirmed: bool,
+    x: Kalman,
+    y: Kalman,
+}
+
+impl TrackedObject {
+    /// Smoothed center in pixels
+    pub fn position(&self) -> Vector2<f32> {
+        Vector2::new(self.x.position, self.y.position)
+    }
+
+    /// Smoothed velocity in pixels per second
+    pub fn velocity(&self) -> Vector2<f32> {
+        Vector2::new(self.x.velocity, self.y.velocity)
+    }
+
+    /// How far the smoothed position is from the latest detection. Add this to anything taken
+    /// from `component` to make it follow the smoothed motion.
+    pub fn offset(&self) -> Vector2<f32> {
+        self.position() - center(&self.component)
+    }
+
+    /// The latest bounding box moved to the smoothed position, as (left, top, width, height)
+    pub fn bounds(&self) -> [f32; 4] {
+        let offset = self.offset();
+        [
+            self.component.left as f32 + offset.x,
+            self.component.top as f32 + offset.y,
+            self.component.width as f32,
+            self.component.height as f32,
+        ]
+    }
+}
+
+fn center(component: &ConnectedComponent) -> Vector2<f32> {
+    Vector2::new(component.centroid[0], component.centroid[1])
+}
+
+fn iou(a: [f32; 4], b: [f32; 4]) -> f32 {
+    let overlap_x = (a[0] + a[2]).min(b[0] + b[2]) - a[0].max(b[0]);
+    let overlap_y = (a[1] + a[3]).min(b[1] + b[3]) - a[1].max(b[1]);
+    if overlap_x <= 0.0 || overlap_y <= 0.0 {
+        return 0.0;
+    }
+    let intersection = overlap_x * overlap_y;
+    intersection / (a[2] * a[3] + b[2] * b[3] - intersection)
+}
+
+/// Gives the components found in each frame identities that persist across frames
+#[derive(Debug)]
+pub struct Tracker {
+    params: TrackerParams,
+    next_id: u64,
+    objects: Vec<TrackedObject>,
+}
+
+impl Tracker {
+    pub fn new(params: TrackerParams) -> Tracker {
+        Tracker {
+            params,
+            next_id: 1,
+            objects: vec![],
+        }
+    }
+
+    pub fn params(&self) -> &TrackerParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: TrackerParams) {
+        self.params = params;
+    }
+
+    /// Objects that have been seen for at least `spawn_frames`
+    pub fn objects(&self) -> impl Iterator<Item = &TrackedObject> {
+        self.objects.iter().filter(|object| object.confirmed)
+    }
+
+    /// Forget all objects, reporting that the ones that had entered exited
+    pub fn clear(&mut self) -> Vec<TrackEvent> {
+        self.objects
+            .drain(..)
+            .filter(|object| object.confirmed)
+            .map(|object| TrackEvent::Exit(object.id))
+            .collect()
+    }
+
+    /// Matches this frame's components against the known objects. `dt` is the time since the
+    /// previous frame in seconds.
+    pub fn update(&mut self, components: &[ConnectedComponent], dt: f32) -> Vec<TrackEvent> {
+        let params = &self.params;
+        for object in self.objects.iter_mut() {
+            object.x.predict(dt, params.process_noise);
+            object.y.predict(dt, params.process_noise);
+        }
+
+        // Score every plausible pair and greedily take the best ones
+        let mut candidates = vec![];
+        for (i, object) in self.objects.iter().enumerate() {
+            for (j, component) in components.iter().enumerate() {
+                let score = match params.matching {
+                    Matching::Iou => {
+                        let bounds = [
+                            component.left as f32,
+                            component.top as f32,
+                            component.width as f32,
+                            component.height as f32,
+                        ];
+                        let overlap = iou(object.bounds(), bounds);
+                        if overlap < params.min_iou {
+                            continue;
+                        }
+                        overlap
+                    }
+                    Matching::Centroid => {
+                        let distance = (object.position() - center(component)).magnitude();
+                        if distance > params.max_distance {
+                            continue;
+                        }
+                        -distance
+                    }
+                };
+                candidates.push((score, i, j));
+            }
+        }
+        candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+
+        let mut object_matched = vec![false; self.objects.len()];
+        let mut component_matched = vec![false; components.len()];
+        let mut events = vec![];
+        for (_, i, j) in candidates {
+            if object_matched[i] || component_matched[j] {
+                continue;
+            }
+            object_matched[i] = true;
+            component_matched[j] = true;
+            let object = &mut self.objects[i];
+            let measured = center(&components[j]);
+            object.x.update(measured.x, params.measurement_noise);
+            object.y.update(measured.y, params.measurement_noise);
+            object.component = components[j].clone();
+            object.missed = 0;
+            object.age += 1;
+            if !object.confirmed && object.age >= params.spawn_frames {
+                object.confirmed = true;
+                events.push(TrackEvent::Enter(object.id));
+            }
+            if object.confirmed {
+                events.push(TrackEvent::Move {
+                    id: object.id,
+                    position: object.position(),
+                    velocity: object.velocity(),
+                });
+            }
+        }
+
+        for (object, matched) in self.objects.iter_mut().zip(object_matched) {
+            if !matched {
+                object.missed += 1;
+                object.age += 1;
+            }
+        }
+        let despawn_frames = params.despawn_frames;
+        self.objects.retain(|object| {
+            // Unconfirmed objects are noise, so they get no grace period
+            let keep = if object.confirmed {
+                object.missed <= despawn_frames
+            } else {
+                object.missed == 0
+            };
+            if !keep && object.confirmed {
+                events.push(TrackEvent::Exit(object.id));
+            }
+            keep
+        });
+
+        for (component, matched) in components.iter().zip(component_matched) {
+            if matched {
+                continue;
+            }
+            let position = center(component);
+            let mut object = TrackedObject {
+                id: self.next_id,
+                component: component.clone(),
+                age: 1,
+                missed: 0,
+                confirmed: false,
+                x: Kalman::new(position.x, self.params.measurement_noise),
+                y: Kalman::new(position.y, self.params.measurement_noise),
+            };
+            self.next_id += 1;
+            if self.params.spawn_frames <= 1 {
+                object.confirmed = true;
+                events.push(TrackEvent::Enter(object.id));
+            }
+            self.objects.push(object);
+        }
+
+        events
+    }
+}
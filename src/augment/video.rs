@@ -1,63 +1,683 @@
-use ffmpeg::format::{input, Pixel};
+use ffmpeg::format::{context, input, Pixel};
 use ffmpeg::media::Type;
 use ffmpeg::software::scaling::{context::Context, flag::Flags};
 use ffmpeg::util::frame::video::Video;
-use std::path::Path;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::mpsc::*;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::*;
 
-pub fn load_video(filename: &Path, tx: Sender<Video>) -> Result<(), ffmpeg::Error> {
-    loop {
-        load_video_once(filename, &tx)?;
-    }
-}
-
-fn load_video_once(filename: &Path, tx: &Sender<Video>) -> Result<(), ffmpeg::Error> {
-    let mut ictx = input(&filename)?;
-    let input = ictx
-        .streams()
-        .best(Type::Video)
-        .ok_or(ffmpeg::Error::StreamNotFound)?;
-    let video_stream_index = input.index();
-
-    let mut decoder = input.codec().decoder().video()?;
-
-    let mut scaler = Context::get(
-        decoder.format(),
-        decoder.width(),
-        decoder.height(),
-        Pixel::RGB24,
-        // TODO FIXME make this configurable
-        // decoder.width(),
-        // decoder.height(),
-        640,
-        480,
-        Flags::BILINEAR,
-    )?;
-
-    let mut receive_and_process_decoded_frames =
-        |decoder: &mut ffmpeg::decoder::Video| -> Result<(), ffmpeg::Error> {
-            let mut decoded = Video::empty();
-            while decoder.receive_frame(&mut decoded).is_ok() {
-                let mut rgb_frame = Video::empty();
-                scaler.run(&decoded, &mut rgb_frame)?;
-                tx.send(rgb_frame)
-                    .ok()
-                    .ok_or(ffmpeg::Error::BufferTooSmall)?;
-                // TODO: get frame schedule from ffmpeg
-                thread::sleep(Duration::from_millis(16));
-            }
-            Ok(())
+use super::clock::{PlaybackClock, Schedule};
+use super::error::AugmentError;
+use super::queue::QueueSender;
+
+/// Never drop more than this many frames in a row. If the decoder can't keep up with the
+/// source frame rate we resync the clock instead of freezing the picture.
+const MAX_DROPPED_FRAMES: u32 = 5;
+
+/// Upper bound on the memory used to hold decoded frames for ping-pong playback
+const MAX_PING_PONG_BYTES: usize = 512 * 1024 * 1024;
+
+/// Spare frames kept around by a `FramePool`. Enough for the decoder to run a little ahead plus
+/// the output of every filter stage.
+const MAX_POOLED_FRAMES: usize = 16;
+
+pub const PLAYBACK_RATES: [f64; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopMode {
+    /// Start over from the beginning when the stream ends
+    Loop,
+    /// Stop on the last frame
+    Once,
+    /// Play backwards to the beginning when the stream ends, then forwards again
+    PingPong,
+}
+
+impl LoopMode {
+    pub fn next(self) -> LoopMode {
+        match self {
+            LoopMode::Loop => LoopMode::Once,
+            LoopMode::Once => LoopMode::PingPong,
+            LoopMode::PingPong => LoopMode::Loop,
+        }
+    }
+}
+
+/// Messages from the render thread to the decoder thread
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackCommand {
+    TogglePause,
+    /// Jump to a position in the stream
+    Seek(Duration),
+    /// Jump forwards or backwards by this many seconds
+    SeekBy(f64),
+    /// Pause and advance by a single frame
+    Step,
+    SetRate(f64),
+    SetLoopMode(LoopMode),
+}
+
+/// The size that decoded frames are scaled to before they are handed to the renderer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputSize {
+    /// Keep the resolution of the source
+    Native,
+    /// Scale to exactly this size, ignoring the aspect ratio
+    Fixed(u32, u32),
+    /// Scale to the largest size that fits in this box while keeping the aspect ratio
+    Fit(u32, u32),
+}
+
+impl OutputSize {
+    pub fn resolve(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            OutputSize::Native => (width, height),
+            OutputSize::Fixed(w, h) => (w, h),
+            OutputSize::Fit(max_w, max_h) => {
+                let scale = f64::min(
+                    max_w as f64 / width as f64,
+                    max_h as f64 / height as f64,
+                );
+                // Keep the dimensions even so that chroma subsampled formats stay valid
+                let w = ((width as f64 * scale) as u32 / 2 * 2).max(2);
+                let h = ((height as f64 * scale) as u32 / 2 * 2).max(2);
+                (w, h)
+            }
+        }
+    }
+}
+
+impl FromStr for OutputSize {
+    type Err = String;
+
+    /// Parses "native", "WxH", or "fit:WxH"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "native" {
+            return Ok(OutputSize::Native);
+        }
+        let (fit, dims) = match s.strip_prefix("fit:") {
+            Some(dims) => (true, dims),
+            None => (false, s),
+        };
+        let mut parts = dims.splitn(2, 'x');
+        let parse_dim = |part: Option<&str>| -> Result<u32, String> {
+            part.and_then(|p| p.parse().ok())
+                .filter(|&d| d > 0)
+                .ok_or_else(|| format!("Invalid video size '{}'", s))
         };
+        let w = parse_dim(parts.next())?;
+        let h = parse_dim(parts.next())?;
+        if fit {
+            Ok(OutputSize::Fit(w, h))
+        } else {
+            Ok(OutputSize::Fixed(w, h))
+        }
+    }
+}
+
+pub fn parse_scaler(s: &str) -> Result<Flags, String> {
+    match s {
+        "fast-bilinear" => Ok(Flags::FAST_BILINEAR),
+        "bilinear" => Ok(Flags::BILINEAR),
+        "bicubic" => Ok(Flags::BICUBIC),
+        "point" => Ok(Flags::POINT),
+        "area" => Ok(Flags::AREA),
+        "gauss" => Ok(Flags::GAUSS),
+        "lanczos" => Ok(Flags::LANCZOS),
+        "spline" => Ok(Flags::SPLINE),
+        _ => Err(format!("Unknown scaler '{}'", s)),
+    }
+}
+
+/// Where a decoder reads its packets from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    File(PathBuf),
+    /// A video4linux2 capture device such as /dev/video0
+    Camera(PathBuf),
+    /// A printf style pattern of numbered images such as frames/%04d.png
+    ImageSequence { pattern: String, frame_rate: f64 },
+}
+
+impl Input {
+    /// Live inputs are presented as soon as they are decoded and can't be seeked
+    fn is_live(&self) -> bool {
+        matches!(self, Input::Camera(_))
+    }
+
+    fn open(&self) -> Result<context::Input, AugmentError> {
+        match self {
+            Input::File(path) if !path.exists() => Err(AugmentError::not_found(path)),
+            Input::Camera(device) if !device.exists() => Err(AugmentError::not_found(device)),
+            Input::File(path) => Ok(input(path)?),
+            Input::Camera(device) => {
+                ffmpeg::device::register_all();
+                let format = ffmpeg::device::input::video()
+                    .find(|format| format.name().split(',').any(|name| name == "v4l2"))
+                    .ok_or(ffmpeg::Error::DemuxerNotFound)?;
+                Ok(ffmpeg::format::open(device, &format)?.input())
+            }
+            Input::ImageSequence {
+                pattern,
+                frame_rate,
+            } => {
+                // The image2 demuxer is picked automatically for patterns containing %d
+                let mut options = ffmpeg::Dictionary::new();
+                options.set("framerate", &frame_rate.to_string());
+                Ok(ffmpeg::format::input_with_dictionary(pattern, options)?)
+            }
+        }
+    }
+}
+
+/// A decoded frame and its presentation time relative to the start of the stream
+pub struct TimedFrame {
+    pub frame: Video,
+    pub timestamp: Duration,
+}
+
+/// Frames that are done with and can be written over instead of allocating new ones. Clones
+/// share the same frames, so they can be handed back from another thread.
+#[derive(Clone, Default)]
+pub struct FramePool {
+    /// From the longest unused to the most recently given back
+    frames: Arc<Mutex<VecDeque<Video>>>,
+}
+
+impl std::fmt::Debug for FramePool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FramePool({} frames)", self.frames.lock().unwrap().len())
+    }
+}
+
+impl FramePool {
+    pub fn new() -> FramePool {
+        FramePool::default()
+    }
+
+    /// A frame with this format and size. It still holds whatever was last drawn into it.
+    pub fn take(&self, format: Pixel, width: u32, height: u32) -> Video {
+        let mut frames = self.frames.lock().unwrap();
+        let found = frames.iter().position(|frame| {
+            frame.format() == format && frame.width() == width && frame.height() == height
+        });
+        match found.and_then(|i| frames.remove(i)) {
+            Some(frame) => frame,
+            None => Video::new(format, width, height),
+        }
+    }
+
+    /// Returns a frame for reuse. When the pool is full the frame that has gone unused the
+    /// longest is freed, so frames of a size or format nobody asks for anymore don't crowd out
+    /// the ones in use.
+    pub fn give(&self, frame: Video) {
+        let mut frames = self.frames.lock().unwrap();
+        if frames.len() >= MAX_POOLED_FRAMES {
+            frames.pop_front();
+        }
+        frames.push_back(frame);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VideoOptions {
+    pub size: OutputSize,
+    pub flags: Flags,
+    /// Decoded frames are taken from here
+    pub pool: FramePool,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        VideoOptions {
+            size: OutputSize::Native,
+            flags: Flags::BILINEAR,
+            pool: FramePool::new(),
+        }
+    }
+}
+
+pub fn load_video(
+    input: &Input,
+    options: VideoOptions,
+    tx: QueueSender<TimedFrame>,
+    commands: Receiver<PlaybackCommand>,
+) -> Result<(), AugmentError> {
+    let mut playback = Playback {
+        decoder: Decoder::open(input, &options)?,
+        input,
+        options,
+        tx,
+        stopped: false,
+        clock: PlaybackClock::new(),
+        position: Duration::from_secs(0),
+        paused: false,
+        loop_mode: LoopMode::Loop,
+        dropped_frames: 0,
+        ping_pong: PingPong::default(),
+    };
+    playback.run(&commands)
+}
+
+/// Decodes the first frame of a file, such as a still image
+pub fn load_image(path: &Path, options: &VideoOptions) -> Result<Video, AugmentError> {
+    let mut decoder = Decoder::open(&Input::File(path.to_path_buf()), options)?;
+    let (decoded, _) = decoder
+        .next_frame()?
+        .ok_or(AugmentError::Decode(ffmpeg::Error::InvalidData))?;
+    decoder.scale(&decoded)
+}
+
+/// Decodes every frame in order as fast as possible, for rendering offline
+pub struct VideoReader {
+    decoder: Decoder,
+}
+
+impl VideoReader {
+    pub fn open(input: &Input, options: &VideoOptions) -> Result<VideoReader, AugmentError> {
+        if input.is_live() {
+            return Err(AugmentError::Decode(ffmpeg::Error::InvalidData));
+        }
+        Ok(VideoReader {
+            decoder: Decoder::open(input, options)?,
+        })
+    }
 
-    for (stream, packet) in ictx.packets() {
-        if stream.index() == video_stream_index {
-            decoder.send_packet(&packet)?;
-            receive_and_process_decoded_frames(&mut decoder)?;
+    /// Returns None at the end of the stream
+    pub fn next_frame(&mut self) -> Result<Option<TimedFrame>, AugmentError> {
+        match self.decoder.next_frame()? {
+            Some((decoded, timestamp)) => Ok(Some(TimedFrame {
+                frame: self.decoder.scale(&decoded)?,
+                timestamp,
+            })),
+            None => Ok(None),
         }
     }
-    decoder.send_eof()?;
-    receive_and_process_decoded_frames(&mut decoder)?;
-    Ok(())
+}
+
+/// Frames held onto during the forward pass so that they can be played back in reverse
+#[derive(Default)]
+struct PingPong {
+    frames: Vec<TimedFrame>,
+    bytes: usize,
+    /// Set when the clip was too long to hold in memory
+    overflowed: bool,
+    /// The timestamp at which playback turned around, if we are currently playing in reverse
+    turn: Option<Duration>,
+}
+
+impl PingPong {
+    fn push(&mut self, frame: &TimedFrame) {
+        if self.overflowed {
+            return;
+        }
+        let size = frame.frame.stride(0) * frame.frame.height() as usize;
+        if self.bytes + size > MAX_PING_PONG_BYTES {
+            println!("Video is too long for ping-pong playback, falling back to looping");
+            self.overflowed = true;
+            self.frames.clear();
+            return;
+        }
+        self.bytes += size;
+        self.frames.push(TimedFrame {
+            frame: frame.frame.clone(),
+            timestamp: frame.timestamp,
+        });
+    }
+
+    fn clear(&mut self) {
+        *self = PingPong::default();
+    }
+}
+
+struct Playback<'a> {
+    decoder: Decoder,
+    input: &'a Input,
+    options: VideoOptions,
+    /// Frames the renderer hasn't picked up in time are dropped and recycled
+    tx: QueueSender<TimedFrame>,
+    /// Set once the renderer has gone away, which ends playback
+    stopped: bool,
+    clock: PlaybackClock,
+    /// Timestamp of the last frame sent to the renderer
+    position: Duration,
+    paused: bool,
+    loop_mode: LoopMode,
+    dropped_frames: u32,
+    ping_pong: PingPong,
+}
+
+impl<'a> Playback<'a> {
+    fn run(&mut self, commands: &Receiver<PlaybackCommand>) -> Result<(), AugmentError> {
+        while !self.stopped {
+            let mut step = false;
+            for command in commands.try_iter() {
+                step |= self.handle_command(command)?;
+            }
+            if self.paused && !step {
+                // Nothing to do until the renderer tells us to move
+                match commands.recv() {
+                    Ok(command) => step = self.handle_command(command)?,
+                    Err(_) => return Ok(()),
+                }
+                if self.paused && !step {
+                    continue;
+                }
+            }
+            if !self.advance(step)? {
+                self.paused = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if a single frame should be shown even though playback is paused
+    fn handle_command(&mut self, command: PlaybackCommand) -> Result<bool, AugmentError> {
+        match command {
+            PlaybackCommand::TogglePause => {
+                self.paused = !self.paused;
+                if !self.paused {
+                    // Don't count the time spent paused against the next frame
+                    self.clock.reset(self.position);
+                }
+            }
+            PlaybackCommand::Seek(target) => self.seek(target)?,
+            PlaybackCommand::SeekBy(seconds) => {
+                let target = (self.position.as_secs_f64() + seconds).max(0.0);
+                self.seek(Duration::from_secs_f64(target))?;
+            }
+            PlaybackCommand::Step => {
+                self.paused = true;
+                return Ok(true);
+            }
+            PlaybackCommand::SetRate(rate) => self.clock.set_rate(rate),
+            PlaybackCommand::SetLoopMode(loop_mode) => {
+                self.loop_mode = loop_mode;
+                if loop_mode != LoopMode::PingPong {
+                    self.ping_pong.clear();
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn seek(&mut self, target: Duration) -> Result<(), AugmentError> {
+        if self.input.is_live() {
+            return Ok(());
+        }
+        self.ping_pong.clear();
+        match self.decoder.seek(target)? {
+            Some((decoded, timestamp)) => {
+                self.clock.reset(timestamp);
+                let frame = self.decoder.scale(&decoded)?;
+                self.send(frame, timestamp);
+                Ok(())
+            }
+            // Seeking past the end leaves us on the last frame
+            None => Ok(()),
+        }
+    }
+
+    /// Presents the next frame. Returns false if the stream has ended and shouldn't restart.
+    fn advance(&mut self, immediate: bool) -> Result<bool, AugmentError> {
+        if let Some(turn) = self.ping_pong.turn {
+            let cached = match self.ping_pong.frames.pop() {
+                Some(cached) => cached,
+                None => {
+                    self.restart()?;
+                    return Ok(true);
+                }
+            };
+            // Keep the clock moving forwards while the timestamps run backwards
+            let presentation_time = turn + (turn - cached.timestamp);
+            if immediate || self.wait_for(presentation_time) {
+                self.send(cached.frame, cached.timestamp);
+            }
+            return Ok(true);
+        }
+
+        let (decoded, timestamp) = match self.decoder.next_frame()? {
+            Some(next) => next,
+            None => return self.end_of_stream(),
+        };
+        if !immediate && !self.wait_for(timestamp) {
+            return Ok(true);
+        }
+        let frame = TimedFrame {
+            frame: self.decoder.scale(&decoded)?,
+            timestamp,
+        };
+        if self.loop_mode == LoopMode::PingPong {
+            self.ping_pong.push(&frame);
+        }
+        self.send(frame.frame, frame.timestamp);
+        Ok(true)
+    }
+
+    fn end_of_stream(&mut self) -> Result<bool, AugmentError> {
+        match self.loop_mode {
+            LoopMode::Once => Ok(false),
+            LoopMode::PingPong if !self.ping_pong.overflowed && !self.ping_pong.frames.is_empty() => {
+                // The newest frame is already on screen
+                self.ping_pong.frames.pop();
+                self.ping_pong.turn = Some(self.position);
+                Ok(true)
+            }
+            _ => {
+                self.restart()?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn restart(&mut self) -> Result<(), AugmentError> {
+        self.decoder = Decoder::open(self.input, &self.options)?;
+        self.ping_pong.clear();
+        self.clock.clear();
+        Ok(())
+    }
+
+    /// Sleeps until it is time to present `timestamp`. Returns false if the frame should be
+    /// dropped because it is already too late.
+    fn wait_for(&mut self, timestamp: Duration) -> bool {
+        if self.input.is_live() {
+            return true;
+        }
+        match self.clock.schedule(timestamp) {
+            Schedule::Wait(delay) => thread::sleep(delay),
+            Schedule::Late(lateness) if lateness > self.decoder.frame_duration => {
+                if self.dropped_frames < MAX_DROPPED_FRAMES {
+                    self.dropped_frames += 1;
+                    return false;
+                }
+                self.clock.reset(timestamp);
+            }
+            Schedule::Late(_) => {}
+        }
+        self.dropped_frames = 0;
+        true
+    }
+
+    fn send(&mut self, frame: Video, timestamp: Duration) {
+        self.position = timestamp;
+        match self.tx.send(TimedFrame { frame, timestamp }) {
+            Ok(Some(dropped)) => self.options.pool.give(dropped.frame),
+            Ok(None) => {}
+            Err(_) => self.stopped = true,
+        }
+    }
+}
+
+/// Reads and decodes frames from a single video stream
+struct Decoder {
+    ictx: context::Input,
+    decoder: ffmpeg::decoder::Video,
+    scaler: Context,
+    /// Size of the scaled frames
+    size: (u32, u32),
+    pool: FramePool,
+    stream_index: usize,
+    time_base: f64,
+    start_time: i64,
+    /// Variable frame rate files may not report an average, in which case we fall back to the
+    /// distance between consecutive timestamps
+    avg_frame_rate: f64,
+    frame_duration: Duration,
+    last_timestamp: Option<Duration>,
+    eof: bool,
+}
+
+impl Decoder {
+    fn open(input: &Input, options: &VideoOptions) -> Result<Decoder, AugmentError> {
+        let ictx = input.open()?;
+        let input = ictx
+            .streams()
+            .best(Type::Video)
+            .ok_or(ffmpeg::Error::StreamNotFound)?;
+        let stream_index = input.index();
+        let time_base = f64::from(input.time_base());
+        let start_time = if input.start_time() == ffmpeg::ffi::AV_NOPTS_VALUE {
+            0
+        } else {
+            input.start_time()
+        };
+        let avg_frame_rate = f64::from(input.avg_frame_rate());
+        let frame_duration = if avg_frame_rate > 0.0 {
+            Duration::from_secs_f64(1.0 / avg_frame_rate)
+        } else {
+            Duration::from_secs_f64(1.0 / 30.0)
+        };
+
+        let decoder = input.codec().decoder().video()?;
+
+        let (width, height) = options.size.resolve(decoder.width(), decoder.height());
+        let scaler = Context::get(
+            decoder.format(),
+            decoder.width(),
+            decoder.height(),
+            Pixel::RGB24,
+            width,
+            height,
+            options.flags,
+        )
+        .map_err(AugmentError::Scale)?;
+
+        Ok(Decoder {
+            ictx,
+            decoder,
+            scaler,
+            size: (width, height),
+            pool: options.pool.clone(),
+            stream_index,
+            time_base,
+            start_time,
+            avg_frame_rate,
+            frame_duration,
+            last_timestamp: None,
+            eof: false,
+        })
+    }
+
+    /// Decodes the next frame, or returns None at the end of the stream
+    fn next_frame(&mut self) -> Result<Option<(Video, Duration)>, ffmpeg::Error> {
+        let mut decoded = Video::empty();
+        loop {
+            if self.decoder.receive_frame(&mut decoded).is_ok() {
+                let timestamp = self.timestamp(&decoded);
+                return Ok(Some((decoded, timestamp)));
+            }
+            if self.eof {
+                return Ok(None);
+            }
+            match self.ictx.packets().next() {
+                Some((stream, packet)) => {
+                    if stream.index() == self.stream_index {
+                        self.decoder.send_packet(&packet)?;
+                    }
+                }
+                None => {
+                    self.decoder.send_eof()?;
+                    self.eof = true;
+                }
+            }
+        }
+    }
+
+    fn timestamp(&mut self, decoded: &Video) -> Duration {
+        let timestamp = match decoded.timestamp() {
+            Some(pts) => {
+                Duration::from_secs_f64(((pts - self.start_time) as f64 * self.time_base).max(0.0))
+            }
+            None => self
+                .last_timestamp
+                .map_or(Duration::from_secs(0), |t| t + self.frame_duration),
+        };
+        if let Some(last) = self.last_timestamp {
+            if self.avg_frame_rate <= 0.0 && timestamp > last {
+                self.frame_duration = timestamp - last;
+            }
+        }
+        self.last_timestamp = Some(timestamp);
+        timestamp
+    }
+
+    /// Jumps to the first frame at or after `target`
+    fn seek(&mut self, target: Duration) -> Result<Option<(Video, Duration)>, ffmpeg::Error> {
+        let start = (self.start_time as f64 * self.time_base * 1_000_000.0) as i64;
+        let ts = start + target.as_micros() as i64;
+        self.ictx.seek(ts, ..ts)?;
+        self.decoder.flush();
+        self.eof = false;
+        self.last_timestamp = None;
+        // Seeking lands on the preceding keyframe, so decode forward to the requested frame
+        while let Some((decoded, timestamp)) = self.next_frame()? {
+            if timestamp + self.frame_duration / 2 >= target {
+                return Ok(Some((decoded, timestamp)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn scale(&mut self, decoded: &Video) -> Result<Video, AugmentError> {
+        let mut rgb_frame = self.pool.take(Pixel::RGB24, self.size.0, self.size.1);
+        self.scaler
+            .run(decoded, &mut rgb_frame)
+            .map_err(AugmentError::Scale)?;
+        Ok(rgb_frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_pool_frees_the_longest_unused_frame_when_full() {
+        let pool = FramePool::new();
+        // A frame of a size that is no longer used, then a full pool of the current size
+        pool.give(Video::new(Pixel::RGB24, 320, 240));
+        for _ in 0..MAX_POOLED_FRAMES {
+            pool.give(Video::new(Pixel::RGB24, 640, 480));
+        }
+        let frames = pool.frames.lock().unwrap();
+        assert_eq!(frames.len(), MAX_POOLED_FRAMES);
+        assert!(frames.iter().all(|frame| frame.width() == 640));
+    }
+
+    #[test]
+    fn frame_pool_reuses_matching_frames() {
+        let pool = FramePool::new();
+        pool.give(Video::new(Pixel::GRAY8, 64, 48));
+        pool.give(Video::new(Pixel::RGB24, 64, 48));
+        let frame = pool.take(Pixel::RGB24, 64, 48);
+        assert_eq!(frame.format(), Pixel::RGB24);
+        assert_eq!(pool.frames.lock().unwrap().len(), 1);
+        let frame = pool.take(Pixel::RGB24, 64, 48);
+        assert_eq!((frame.width(), frame.height()), (64, 48));
+        assert_eq!(pool.frames.lock().unwrap().len(), 1);
+    }
 }
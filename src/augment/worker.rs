@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::*;
+use std::sync::Arc;
+use std::thread;
+
+use super::error::AugmentError;
+use super::filters::{ChainOutput, FilterChain, StageSpec};
+use super::queue::{self, QueueReceiver, QueueSender};
+use super::video::{FramePool, TimedFrame};
+
+/// Frames waiting to be filtered. Kept short so that the results stay close to what the source
+/// is showing; if the filters fall behind the oldest frames are skipped.
+const FILTER_QUEUE_FRAMES: usize = 2;
+
+/// What the filter chain made of one source frame
+pub struct FilteredFrame {
+    /// The sequence number the source frame was submitted with
+    pub sequence: u64,
+    /// Only holds the output of the stages that were asked for
+    pub output: Result<ChainOutput, AugmentError>,
+}
+
+struct Job {
+    frame: Arc<TimedFrame>,
+    sequence: u64,
+    stages: Vec<String>,
+    /// Commands up to this generation were sent before the job
+    generation: u64,
+}
+
+enum Command {
+    SetStages(Vec<StageSpec>),
+    Reset,
+}
+
+/// Runs the filter chain on its own thread so that slow filters don't hold up rendering and
+/// input handling
+pub struct FilterWorker {
+    jobs: QueueSender<Job>,
+    /// Each command starts a new generation, so that it only applies to the jobs after it
+    commands: Sender<(u64, Command)>,
+    generation: u64,
+    results: Receiver<FilteredFrame>,
+}
+
+impl FilterWorker {
+    /// Stage outputs are taken from `pool` and should be handed back to it
+    pub fn spawn(stages: &[StageSpec], pool: FramePool) -> FilterWorker {
+        let (job_tx, job_rx) = queue::bounded(FILTER_QUEUE_FRAMES);
+        let (command_tx, command_rx) = channel();
+        let (result_tx, result_rx) = channel();
+        let stages = stages.to_vec();
+        thread::spawn(move || {
+            // Filters hold OpenCV state, so the chain is built on the thread that uses it
+            let chain = FilterChain::new(&stages);
+            run(chain, job_rx, command_rx, result_tx, pool);
+        });
+        FilterWorker {
+            jobs: job_tx,
+            commands: command_tx,
+            generation: 0,
+            results: result_rx,
+        }
+    }
+
+    /// Queues a frame, skipping the oldest waiting one if the worker is behind. Only the output
+    /// of `stages` is sent back, tagged with `sequence`.
+    pub fn submit(&self, frame: Arc<TimedFrame>, sequence: u64, stages: Vec<String>) {
+        let job = Job {
+            frame,
+            sequence,
+            stages,
+            generation: self.generation,
+        };
+        if self.jobs.send(job).is_err() {
+            println!("Filter thread is not running");
+        }
+    }
+
+    /// Rebuilds the chain, which forgets anything the filters learned. Frames that are already
+    /// queued still go through the old chain.
+    pub fn set_stages(&mut self, stages: &[StageSpec]) {
+        self.send(Command::SetStages(stages.to_vec()));
+    }
+
+    /// Forgets the learned background models, starting from the next frame submitted
+    pub fn reset(&mut self) {
+        self.send(Command::Reset);
+    }
+
+    fn send(&mut self, command: Command) {
+        self.generation += 1;
+        let _ = self.commands.send((self.generation, command));
+    }
+
+    /// Returns the oldest result that is ready, without blocking
+    pub fn try_recv(&self) -> Option<FilteredFrame> {
+        self.results.try_recv().ok()
+    }
+
+    /// Blocks until the next result is ready. Returns None if the worker has stopped.
+    pub fn recv(&self) -> Option<FilteredFrame> {
+        self.results.recv().ok()
+    }
+}
+
+fn run(
+    mut chain: FilterChain,
+    jobs: QueueReceiver<Job>,
+    commands: Receiver<(u64, Command)>,
+    results: Sender<FilteredFrame>,
+    pool: FramePool,
+) {
+    // Commands that arrived while frames submitted before them were still queued
+    let mut waiting = VecDeque::new();
+    while let Some(job) = jobs.recv() {
+        // Commands apply to every frame submitted after them
+        waiting.extend(commands.try_iter());
+        while waiting
+            .front()
+            .map_or(false, |(generation, _)| *generation <= job.generation)
+        {
+            let (_, command) = waiting.pop_front().unwrap();
+            match command {
+                Command::SetStages(stages) => chain = FilterChain::new(&stages),
+                Command::Reset => {
+                    if let Err(err) = chain.reset() {
+                        println!("Error resetting filters: {}", err);
+                    }
+                }
+            }
+        }
+
+        let output = chain.run(&job.frame.frame, &pool).map(|mut output| {
+            let (kept, skipped): (Vec<_>, Vec<_>) = output
+                .stages
+                .drain(..)
+                .partition(|(name, _)| job.stages.contains(name));
+            output.stages = kept;
+            for (_, frame) in skipped {
+                pool.give(frame);
+            }
+            output
+        });
+        let filtered = FilteredFrame {
+            sequence: job.sequence,
+            output,
+        };
+        // Let go of the source frame first so that the renderer can recycle it
+        drop(job);
+        if results.send(filtered).is_err() {
+            return;
+        }
+    }
+}
@@ -4,14 +4,46 @@ extern crate cgmath;
 extern crate ffmpeg_next as ffmpeg;
 extern crate notify;
 
+use structopt::StructOpt;
+
 mod augment;
 mod particles;
 mod render_teapot;
 mod shadertoy;
 mod teapot;
+mod window;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "rust-gfx-playground",
+    about = "Shader and augmented video playground"
+)]
+struct Opt {
+    #[structopt(flatten)]
+    window: window::WindowOptions,
+
+    #[structopt(subcommand)]
+    demo: Demo,
+}
+
+#[derive(Debug, StructOpt)]
+enum Demo {
+    /// Run the object detection pipeline over a video
+    Augment(augment::AugmentOptions),
+    /// Render a hot-reloaded fullscreen fragment shader
+    Shadertoy(shadertoy::ShadertoyOptions),
+    /// Render a fountain of particles
+    Particles(particles::ParticlesOptions),
+    /// Render the glium teapot
+    Teapot,
+}
 
 fn main() {
-    // render_teapot::start();
-    // shadertoy::start();
-    augment::start();
+    let opt = Opt::from_args();
+    match opt.demo {
+        Demo::Augment(options) => augment::start(&opt.window, options),
+        Demo::Shadertoy(options) => shadertoy::start(&opt.window, options),
+        Demo::Particles(options) => particles::start(&opt.window, options),
+        Demo::Teapot => render_teapot::start(&opt.window),
+    }
 }
@@ -1,19 +1,37 @@
 use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
 
+pub use self::collision::CollisionMask;
+use self::cpu::CpuSimulation;
+pub use self::curve::{Curve, Interpolation};
+pub use self::forces::Force;
+use self::gpu::GpuSimulation;
+use crate::augment::error::AugmentError;
 use crate::augment::shaders::ProgramHandle;
-use cgmath::{prelude::*, Vector2, Vector3};
-use glium::{Display, Frame};
+use crate::window::{self, WindowOptions};
+use cgmath::{InnerSpace, Vector2};
+use glium::backend::Facade;
+use glium::{Display, Surface};
+use structopt::StructOpt;
+
+mod collision;
+mod cpu;
+mod curve;
+mod forces;
+mod gpu;
 
 #[derive(Copy, Clone)]
 struct Particle {
     position: Vector2<f32>,
     velocity: Vector2<f32>,
-    life: u32,
+    /// Seconds left to live
+    life: f32,
 }
 
 impl Particle {
     fn is_alive(&self) -> bool {
-        return self.life > 0;
+        return self.life > 0.;
     }
 }
 
@@ -22,21 +40,121 @@ impl Particle {
         Particle {
             position: Vector2::new(0., 0.),
             velocity: Vector2::new(0., 0.),
-            life: 0,
+            life: 0.,
         }
     }
 }
 
-struct ParticleSystem {
+/// Where on the emitter new particles appear
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmitterShape {
+    /// Right at the emitter's position
+    Point,
+    /// Anywhere in a box with these half extents, centered on the position
+    Box(Vector2<f32>),
+    /// Anywhere on this outline, given relative to the position
+    Contour(Contour),
+}
+
+impl EmitterShape {
+    /// The point at `u` and `v`, which go from 0 to 1, relative to the emitter's position
+    fn sample(&self, u: f32, v: f32) -> Vector2<f32> {
+        match self {
+            EmitterShape::Point => Vector2::new(0., 0.),
+            EmitterShape::Box(half) => Vector2::new(half.x * (2. * u - 1.), half.y * (2. * v - 1.)),
+            EmitterShape::Contour(contour) => contour.sample(u),
+        }
+    }
+}
+
+/// A closed outline, with the distance around it to each point worked out up front so that
+/// spawning a particle on it is a binary search
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contour {
+    points: Vec<Vector2<f32>>,
+    /// Distance from the first point to each point, then all the way around back to the first
+    distances: Vec<f32>,
+}
+
+impl Contour {
+    /// `points` go in order around the outline
+    pub fn new(points: Vec<Vector2<f32>>) -> Contour {
+        let mut distances = Vec::with_capacity(points.len() + 1);
+        let mut distance = 0.;
+        for (i, &point) in points.iter().enumerate() {
+            distances.push(distance);
+            distance += (points[(i + 1) % points.len()] - point).magnitude();
+        }
+        distances.push(distance);
+        Contour { points, distances }
+    }
+
+    fn perimeter(&self) -> f32 {
+        *self.distances.last().unwrap()
+    }
+
+    /// The point `u` of the way around, so long edges get as many particles as they deserve.
+    /// `shaders/particle_step.frag` does the same search.
+    fn sample(&self, u: f32) -> Vector2<f32> {
+        if self.points.is_empty() {
+            return Vector2::new(0., 0.);
+        }
+        let distance = u * self.perimeter();
+        // The last edge that starts at or before `distance`
+        let (mut low, mut high) = (0, self.points.len() - 1);
+        while low < high {
+            let middle = (low + high + 1) / 2;
+            if self.distances[middle] <= distance {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        let a = self.points[low];
+        let b = self.points[(low + 1) % self.points.len()];
+        let length = self.distances[low + 1] - self.distances[low];
+        if length > 0. {
+            a + (b - a) * ((distance - self.distances[low]) / length)
+        } else {
+            a
+        }
+    }
+}
+
+/// Describes an emitter. Positions and velocities are in GL coordinates, so the window goes from
+/// -1 to 1 both ways, and sizes are in pixels.
+pub struct ParticleSystem {
     position: Vector2<f32>,
     num_particles: usize,
     lifetime: f32,
     emission_rate: f32,
+    /// Direction and speed of new particles
+    velocity: Vector2<f32>,
+    /// New particles leave up to this many radians either side of `velocity`
+    spread: f32,
+    gravity: Vector2<f32>,
+    /// Fraction of its velocity a particle loses every second
+    drag: f32,
+    /// Diameter over the particle's age, which goes from 0 to 1
+    size: Curve<f32>,
+    /// RGBA over the particle's age
+    color: Curve<[f32; 4]>,
+    /// Multiplies the alpha of `color`
+    opacity: Curve<f32>,
+    /// Picks the random numbers, so the same seed always gives the same particles
+    seed: u32,
+    shape: EmitterShape,
+    /// Multiplies the opacity of every particle, to fade the whole system in or out
+    fade: f32,
+    /// On top of gravity and drag
+    forces: Vec<Force>,
+    /// Solid areas the particles bounce off
+    mask: Option<Rc<CollisionMask>>,
+    /// Fraction of a particle's speed into the mask that it bounces back with
+    bounce: f32,
+    /// Fraction of a particle's speed along the mask that it loses when it hits
+    friction: f32,
     // texture
-    // gravity
-    // size_curve
-    // color_curve
-    // opacity_curve
 }
 
 impl ParticleSystem {
@@ -46,6 +164,20 @@ impl ParticleSystem {
             num_particles: 10,
             lifetime: 5.,
             emission_rate: 2.,
+            velocity: Vector2::new(0., 0.5),
+            spread: 0.3,
+            gravity: Vector2::new(0., -0.3),
+            drag: 0.1,
+            size: Curve::constant(8.),
+            color: Curve::constant([1., 1., 1., 1.]),
+            opacity: Curve::constant(1.),
+            seed: 0,
+            shape: EmitterShape::Point,
+            fade: 1.,
+            forces: vec![],
+            mask: None,
+            bounce: 0.5,
+            friction: 0.1,
         };
     }
 
@@ -65,31 +197,682 @@ impl ParticleSystem {
         self.emission_rate = emission_rate;
         self
     }
+    pub fn set_velocity(&mut self, velocity: Vector2<f32>) -> &mut ParticleSystem {
+        self.velocity = velocity;
+        self
+    }
+    pub fn set_spread(&mut self, spread: f32) -> &mut ParticleSystem {
+        self.spread = spread;
+        self
+    }
+    pub fn set_gravity(&mut self, gravity: Vector2<f32>) -> &mut ParticleSystem {
+        self.gravity = gravity;
+        self
+    }
+    pub fn set_drag(&mut self, drag: f32) -> &mut ParticleSystem {
+        self.drag = drag;
+        self
+    }
+    pub fn set_size(&mut self, size: Curve<f32>) -> &mut ParticleSystem {
+        self.size = size;
+        self
+    }
+    pub fn set_color(&mut self, color: Curve<[f32; 4]>) -> &mut ParticleSystem {
+        self.color = color;
+        self
+    }
+    pub fn set_opacity(&mut self, opacity: Curve<f32>) -> &mut ParticleSystem {
+        self.opacity = opacity;
+        self
+    }
+    pub fn set_seed(&mut self, seed: u32) -> &mut ParticleSystem {
+        self.seed = seed;
+        self
+    }
+    pub fn set_shape(&mut self, shape: EmitterShape) -> &mut ParticleSystem {
+        self.shape = shape;
+        self
+    }
+    pub fn set_fade(&mut self, fade: f32) -> &mut ParticleSystem {
+        self.fade = fade;
+        self
+    }
+    pub fn set_forces(&mut self, forces: Vec<Force>) -> &mut ParticleSystem {
+        self.forces = forces;
+        self
+    }
+    pub fn set_mask(&mut self, mask: Option<Rc<CollisionMask>>) -> &mut ParticleSystem {
+        self.mask = mask;
+        self
+    }
+    pub fn set_bounce(&mut self, bounce: f32) -> &mut ParticleSystem {
+        self.bounce = bounce;
+        self
+    }
+    pub fn set_friction(&mut self, friction: f32) -> &mut ParticleSystem {
+        self.friction = friction;
+        self
+    }
+
+    /// Moves a live particle on by `delta` seconds, ending `time` seconds into the simulation.
+    /// `shaders/particle_step.frag` does the same on the GPU.
+    fn step(&self, particle: &mut Particle, time: f32, delta: f32) {
+        let mut acceleration = self.gravity;
+        for force in &self.forces {
+            acceleration += force.acceleration(particle.position, particle.velocity, time);
+        }
+        let drag = (1. - self.drag * delta).max(0.);
+        let mut velocity = (particle.velocity + acceleration * delta) * drag;
+        let mut position = particle.position + velocity * delta;
+        if let Some(mask) = &self.mask {
+            position = mask.collide(
+                particle.position,
+                position,
+                &mut velocity,
+                self.bounce,
+                self.friction,
+            );
+        }
+        particle.position = position;
+        particle.velocity = velocity;
+        particle.life -= delta;
+    }
+
+    /// The `index`th particle this system emits. `shaders/particle_step.frag` spawns particles the
+    /// same way on the GPU.
+    fn spawn(&self, index: u32) -> Particle {
+        let angle = self.spread * (2. * random(self.seed, index) - 1.);
+        let (sin, cos) = angle.sin_cos();
+        let velocity = self.velocity;
+        let offset = self.shape.sample(
+            random(self.seed.wrapping_add(1), index),
+            random(self.seed.wrapping_add(2), index),
+        );
+        Particle {
+            position: self.position + offset,
+            velocity: Vector2::new(
+                velocity.x * cos - velocity.y * sin,
+                velocity.x * sin + velocity.y * cos,
+            ),
+            life: self.lifetime,
+        }
+    }
+}
+
+/// Where the particles live and are stepped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// In a Vec, uploaded every frame. Best for small systems.
+    Cpu,
+    /// In float textures, stepped by a shader. Scales to hundreds of thousands of particles.
+    Gpu,
 }
 
-struct ParticleSystemRunner<'a> {
-    system: &'a ParticleSystem,
-    particles: Vec<Particle>,
-    program: ProgramHandle<'a>,
+/// The shaders a backend steps and draws particles with. Any number of runners on that backend
+/// can share one set, so that each program is only compiled and watched once.
+pub struct ParticlePrograms {
+    backend: Backend,
+    /// Only the GPU backend steps its particles with a shader
+    step: Option<ProgramHandle>,
+    draw: ProgramHandle,
 }
 
-impl<'a> ParticleSystemRunner<'a> {
-    pub fn new(display: &Display, system: &'a ParticleSystem) -> ParticleSystemRunner<'a> {
-        return ParticleSystemRunner {
+impl ParticlePrograms {
+    pub fn new<F: Facade>(facade: &F, backend: Backend) -> Result<ParticlePrograms, AugmentError> {
+        let load = |vertex: &str, fragment: &str| {
+            ProgramHandle::new(facade, Path::new(vertex), Path::new(fragment))
+        };
+        Ok(match backend {
+            Backend::Cpu => ParticlePrograms {
+                backend,
+                step: None,
+                draw: load("shaders/particle.vert", "shaders/particle.frag")?,
+            },
+            Backend::Gpu => ParticlePrograms {
+                backend,
+                step: Some(load(
+                    "shaders/particle_step.vert",
+                    "shaders/particle_step.frag",
+                )?),
+                draw: load("shaders/particle_gpu.vert", "shaders/particle.frag")?,
+            },
+        })
+    }
+
+    pub fn poll<F: Facade>(&mut self, facade: &F) {
+        if let Some(step) = &mut self.step {
+            step.poll(facade);
+        }
+        self.draw.poll(facade);
+    }
+}
+
+enum Simulation {
+    Cpu(CpuSimulation),
+    Gpu(GpuSimulation),
+}
+
+impl Simulation {
+    fn new<F: Facade>(
+        facade: &F,
+        backend: Backend,
+        num_particles: usize,
+    ) -> Result<Simulation, AugmentError> {
+        Ok(match backend {
+            Backend::Cpu => Simulation::Cpu(CpuSimulation::new(facade, num_particles)?),
+            Backend::Gpu => Simulation::Gpu(GpuSimulation::new(facade, num_particles)?),
+        })
+    }
+}
+
+/// Simulates a particle system and draws it as point sprites. Dead particles are reused for new
+/// ones, so there are never more than `num_particles`. Both backends emit the same particles
+/// into the same slots, so they can be swapped and compared.
+pub struct ParticleSystemRunner {
+    system: ParticleSystem,
+    backend: Backend,
+    emitter: Emitter,
+    simulation: Simulation,
+}
+
+impl ParticleSystemRunner {
+    pub fn new<F: Facade>(
+        facade: &F,
+        system: ParticleSystem,
+        backend: Backend,
+    ) -> Result<ParticleSystemRunner, AugmentError> {
+        let num_particles = system.num_particles;
+        return Ok(ParticleSystemRunner {
             system,
-            particles: vec![Particle::new(); system.num_particles],
-            program: ProgramHandle::new(
-                display,
-                Path::new("shaders/particle.vert"),
-                Path::new("shaders/particle.frag"),
-            )
-            .unwrap(),
+            backend,
+            emitter: Emitter::new(num_particles),
+            simulation: Simulation::new(facade, backend, num_particles)?,
+        });
+    }
+
+    /// Advances the simulation by `delta` seconds. `programs` must be for the runner's backend.
+    pub fn update<F: Facade>(
+        &mut self,
+        facade: &F,
+        programs: &ParticlePrograms,
+        delta: f32,
+    ) -> Result<(), AugmentError> {
+        debug_assert_eq!(programs.backend, self.backend);
+        let emission = self.emitter.step(&self.system, delta);
+        let time = self.emitter.time;
+        match &mut self.simulation {
+            Simulation::Cpu(simulation) => {
+                simulation.update(&self.system, &emission, time, delta);
+                Ok(())
+            }
+            Simulation::Gpu(simulation) => {
+                let program = match programs.step.as_ref().map(|step| step.as_program()) {
+                    Some(Ok(program)) => program,
+                    // Already printed when the program was loaded
+                    _ => return Ok(()),
+                };
+                simulation.update(facade, program, &self.system, &emission, time, delta)
+            }
+        }
+    }
+
+    /// Everything but the number of particles can be changed while the system runs
+    pub fn system_mut(&mut self) -> &mut ParticleSystem {
+        &mut self.system
+    }
+
+    pub fn num_particles(&self) -> usize {
+        self.system.num_particles
+    }
+
+    /// Starts over with room for `num_particles`. The particles that are alive are dropped.
+    pub fn set_num_particles<F: Facade>(
+        &mut self,
+        facade: &F,
+        num_particles: usize,
+    ) -> Result<(), AugmentError> {
+        self.simulation = Simulation::new(facade, self.backend, num_particles)?;
+        self.emitter = Emitter::new(num_particles);
+        self.system.set_num_particles(num_particles);
+        Ok(())
+    }
+
+    /// Draws over the whole target
+    pub fn draw<S: Surface>(
+        &mut self,
+        programs: &ParticlePrograms,
+        target: &mut S,
+    ) -> Result<(), AugmentError> {
+        self.draw_in(programs, target, [-1., -1.], [1., 1.])
+    }
+
+    /// Draws squeezed into part of the target, given in GL coordinates, so that the particles'
+    /// -1 to 1 fills it
+    pub fn draw_in<S: Surface>(
+        &mut self,
+        programs: &ParticlePrograms,
+        target: &mut S,
+        lower_left: [f32; 2],
+        upper_right: [f32; 2],
+    ) -> Result<(), AugmentError> {
+        debug_assert_eq!(programs.backend, self.backend);
+        let program = match programs.draw.as_program() {
+            Ok(program) => program,
+            Err(_) => return Ok(()),
         };
+        let area = [lower_left[0], lower_left[1], upper_right[0], upper_right[1]];
+        match &mut self.simulation {
+            Simulation::Cpu(simulation) => simulation.draw(program, &self.system, target, area),
+            Simulation::Gpu(simulation) => simulation.draw(program, &self.system, target, area),
+        }
     }
 
-    pub fn run(&mut self, display: &Display, target: &mut Frame) {
+    pub fn run<S: Surface>(
+        &mut self,
+        display: &Display,
+        programs: &mut ParticlePrograms,
+        target: &mut S,
+        delta: f32,
+    ) -> Result<(), AugmentError> {
         if cfg!(debug_assertions) {
-            self.program.poll(&display);
+            programs.poll(display);
+        }
+        self.update(display, programs, delta)?;
+        self.draw(programs, target)
+    }
+
+    /// Every slot, dead or alive. Reads the GPU's textures back, so it is only for checking.
+    fn snapshot(&self) -> Vec<Particle> {
+        match &self.simulation {
+            Simulation::Cpu(simulation) => simulation.snapshot(),
+            Simulation::Gpu(simulation) => simulation.snapshot(),
+        }
+    }
+}
+
+/// The particles a step emits: `count` slots from `first_slot` on, wrapping around at the end
+struct Emission {
+    first_slot: usize,
+    count: usize,
+    /// How many particles were emitted before these, which picks their random numbers
+    first_index: u32,
+}
+
+/// Decides where new particles go, the same way for both backends. Slots are handed out in a
+/// ring, so the next one always holds the oldest particle, and a new particle is dropped when
+/// that one is still alive.
+struct Emitter {
+    /// Fractions of a particle that were due but not emitted yet
+    pending: f32,
+    next_slot: usize,
+    /// Simulation time at which each slot's particle dies
+    deaths: Vec<f32>,
+    time: f32,
+    emitted: u32,
+}
+
+impl Emitter {
+    fn new(num_particles: usize) -> Emitter {
+        Emitter {
+            pending: 0.,
+            next_slot: 0,
+            deaths: vec![0.; num_particles],
+            time: 0.,
+            emitted: 0,
+        }
+    }
+
+    fn step(&mut self, system: &ParticleSystem, delta: f32) -> Emission {
+        self.time += delta;
+        self.pending += system.emission_rate * delta;
+        let due = self.pending.floor();
+        self.pending -= due;
+
+        let first_slot = self.next_slot;
+        let first_index = self.emitted;
+        let mut count = 0;
+        while count < due as usize && count < self.deaths.len() {
+            let slot = (first_slot + count) % self.deaths.len();
+            // When every particle is alive the rest are dropped rather than saved up
+            if self.deaths[slot] > self.time {
+                break;
+            }
+            self.deaths[slot] = self.time + system.lifetime;
+            count += 1;
+        }
+        if count > 0 {
+            self.next_slot = (first_slot + count) % self.deaths.len();
+        }
+        self.emitted = self.emitted.wrapping_add(count as u32);
+        Emission {
+            first_slot,
+            count,
+            first_index,
+        }
+    }
+}
+
+/// A random number between 0 and 1 for the `index`th particle a system emits. A particle that
+/// needs several takes them from different seeds. `shaders/particle_random.glsl` is the same
+/// function, so both backends agree.
+fn random(seed: u32, index: u32) -> f32 {
+    (hash(index ^ seed.wrapping_mul(0x9e37_79b9)) >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Scrambles the bits of `x`. `shaders/particle_forces.glsl` has the same hash for the noise.
+pub fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+fn draw_parameters() -> glium::DrawParameters<'static> {
+    glium::DrawParameters {
+        blend: glium::Blend::alpha_blending(),
+        ..Default::default()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ParticlesOptions {
+    /// Simulate the particles on the GPU instead of the CPU
+    #[structopt(long)]
+    pub gpu: bool,
+
+    /// Number of particles
+    #[structopt(long, default_value = "2000")]
+    pub count: usize,
+
+    /// Instead of opening a window, step both backends this many times offscreen and print how
+    /// far apart their particles end up
+    #[structopt(long)]
+    pub compare: Option<u32>,
+
+    /// Blow the particles around with wind, turbulence, a vortex and an attractor
+    #[structopt(long)]
+    pub forces: bool,
+
+    /// Put an invisible disc in the way for the particles to bounce off
+    #[structopt(long)]
+    pub obstacle: bool,
+}
+
+/// A fountain of particles rising from the bottom half of the window
+fn fountain(options: &ParticlesOptions) -> ParticleSystem {
+    let num_particles = options.count;
+    let lifetime = 4.;
+    let mut system = ParticleSystem::new();
+    system
+        .set_position(Vector2::new(0., -0.5))
+        .set_num_particles(num_particles)
+        .set_lifetime(lifetime)
+        // Just enough to keep every particle busy
+        .set_emission_rate(num_particles as f32 / lifetime)
+        .set_velocity(Vector2::new(0., 1.2))
+        .set_spread(0.25)
+        .set_gravity(Vector2::new(0., -0.8))
+        .set_drag(0.2)
+        // Sparks that flare up, cool from yellow to red and fade out
+        .set_size(Curve::constant(2.).add(1., 3., Interpolation::Bezier(14., 6.)))
+        .set_color(
+            Curve::constant([1., 0.9, 0.4, 1.])
+                .add(0.3, [1., 0.5, 0.1, 1.], Interpolation::Linear)
+                .add(1., [0.6, 0.1, 0.05, 1.], Interpolation::Linear),
+        )
+        .set_opacity(Curve::linear(1., 0.));
+    if options.forces {
+        system.set_forces(vec![
+            Force::Wind {
+                velocity: Vector2::new(0.3, 0.),
+                strength: 0.2,
+            },
+            Force::Turbulence {
+                strength: 0.05,
+                scale: 0.3,
+                speed: 0.5,
+                seed: 1,
+            },
+            Force::Vortex {
+                center: Vector2::new(-0.5, 0.5),
+                strength: 0.1,
+                radius: 0.1,
+            },
+            Force::Attractor {
+                position: Vector2::new(0.5, 0.),
+                strength: 0.05,
+                radius: 0.1,
+            },
+        ]);
+    }
+    if options.obstacle {
+        system.set_mask(Some(Rc::new(disc_mask(256, Vector2::new(0., 0.3), 0.2))));
+    }
+    system
+}
+
+/// A square mask, solid in a disc
+fn disc_mask(size: usize, center: Vector2<f32>, radius: f32) -> CollisionMask {
+    let mut data = Vec::with_capacity(size * size);
+    for row in 0..size {
+        for column in 0..size {
+            // Texel centers, from the top row down
+            let x = (column as f32 + 0.5) / size as f32 * 2. - 1.;
+            let y = 1. - (row as f32 + 0.5) / size as f32 * 2.;
+            let inside = (Vector2::new(x, y) - center).magnitude() < radius;
+            data.push(if inside { 255 } else { 0 });
+        }
+    }
+    CollisionMask::new(size, size, data).expect("a byte for every texel")
+}
+
+pub fn start(window_options: &WindowOptions, options: ParticlesOptions) {
+    use glium::glutin;
+
+    if let Some(steps) = options.compare {
+        compare(&options, steps);
+        return;
+    }
+
+    let event_loop = glutin::event_loop::EventLoop::new();
+    let display = window_options.create_display("Particles", &event_loop);
+    let backend = if options.gpu {
+        Backend::Gpu
+    } else {
+        Backend::Cpu
+    };
+    let started = ParticlePrograms::new(&display, backend).and_then(|programs| {
+        let runner = ParticleSystemRunner::new(&display, fountain(&options), backend)?;
+        Ok((programs, runner))
+    });
+    let (mut programs, mut runner) = match started {
+        Ok(started) => started,
+        Err(err) => {
+            println!("Error starting the particle system: {}", err);
+            return;
+        }
+    };
+
+    let mut last_frame = Instant::now();
+    event_loop.run(move |ev, _, control_flow| {
+        let delta = last_frame.elapsed().as_secs_f32();
+        last_frame = Instant::now();
+
+        let mut target = display.draw();
+        target.clear_color(0.0, 0.0, 0.0, 1.0);
+        if let Err(err) = runner.run(&display, &mut programs, &mut target, delta) {
+            println!("Error drawing particles: {}", err);
         }
+        target.finish().unwrap();
+
+        let next_frame_time = Instant::now() + std::time::Duration::from_nanos(16_666_667);
+        *control_flow = glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
+        match ev {
+            glutin::event::Event::WindowEvent { event, .. } => match event {
+                glutin::event::WindowEvent::CloseRequested => {
+                    *control_flow = glutin::event_loop::ControlFlow::Exit;
+                    return;
+                }
+                _ => return,
+            },
+            _ => (),
+        }
+    });
+}
+
+/// The fountain's particles on the CPU and on the GPU after stepping both through the same
+/// frames
+fn run_both<F: Facade>(
+    facade: &F,
+    options: &ParticlesOptions,
+    steps: u32,
+) -> Result<(Vec<Particle>, Vec<Particle>), AugmentError> {
+    let cpu_programs = ParticlePrograms::new(facade, Backend::Cpu)?;
+    let gpu_programs = ParticlePrograms::new(facade, Backend::Gpu)?;
+    let mut cpu = ParticleSystemRunner::new(facade, fountain(options), Backend::Cpu)?;
+    let mut gpu = ParticleSystemRunner::new(facade, fountain(options), Backend::Gpu)?;
+    for _ in 0..steps {
+        cpu.update(facade, &cpu_programs, 1. / 60.)?;
+        gpu.update(facade, &gpu_programs, 1. / 60.)?;
+    }
+    Ok((cpu.snapshot(), gpu.snapshot()))
+}
+
+/// Steps both backends through the same frames and prints how far apart their particles are.
+/// Runs offscreen, with software GL on machines without a GPU.
+fn compare(options: &ParticlesOptions, steps: u32) {
+    let headless = match window::create_headless(64, 64) {
+        Ok(headless) => headless,
+        Err(err) => {
+            println!("Error creating an offscreen context: {}", err);
+            return;
+        }
+    };
+    let (cpu, gpu) = match run_both(&headless.renderer, options, steps) {
+        Ok(particles) => particles,
+        Err(err) => {
+            println!("Error running the particles: {}", err);
+            return;
+        }
+    };
+
+    let alive = |particles: &[Particle]| particles.iter().filter(|p| p.is_alive()).count();
+    let mut mismatched = 0;
+    let mut max_distance: f32 = 0.;
+    for (a, b) in cpu.iter().zip(&gpu) {
+        if a.is_alive() != b.is_alive() {
+            mismatched += 1;
+        } else if a.is_alive() {
+            let (dx, dy) = (a.position.x - b.position.x, a.position.y - b.position.y);
+            max_distance = max_distance.max((dx * dx + dy * dy).sqrt());
+        }
+    }
+    println!(
+        "After {} steps: {} particles alive on the CPU and {} on the GPU, {} slots disagree about \
+         being alive, positions differ by up to {}",
+        steps,
+        alive(&cpu),
+        alive(&gpu),
+        mismatched,
+        max_distance
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How far apart the backends' particles may end up, in the -1 to 1 square. GLSL and Rust
+    /// round floats differently, so the paths drift apart a little with every step.
+    const POSITION_TOLERANCE: f32 = 1e-3;
+    /// How far apart the seconds left to live may end up
+    const LIFE_TOLERANCE: f32 = 1e-4;
+
+    fn assert_backends_agree(headless: &window::Headless, forces: bool) {
+        let options = ParticlesOptions {
+            gpu: false,
+            count: 256,
+            compare: None,
+            forces,
+            obstacle: false,
+        };
+        // Two seconds, shorter than the fountain's lifetime so that no particle is on the
+        // edge of dying
+        let (cpu, gpu) = run_both(&headless.renderer, &options, 120).unwrap();
+        assert_eq!(cpu.len(), gpu.len());
+        assert!(cpu.iter().any(Particle::is_alive));
+        for (i, (a, b)) in cpu.iter().zip(&gpu).enumerate() {
+            assert_eq!(a.is_alive(), b.is_alive(), "particle {}", i);
+            if a.is_alive() {
+                let distance = (a.position - b.position).magnitude();
+                assert!(
+                    distance < POSITION_TOLERANCE,
+                    "particle {} is {} apart",
+                    i,
+                    distance
+                );
+                assert!((a.life - b.life).abs() < LIFE_TOLERANCE, "particle {}", i);
+            }
+        }
+    }
+
+    /// Both cases share one context, as tests run in parallel and each context would need an
+    /// event loop of its own
+    #[test]
+    #[ignore = "needs an OpenGL context, from a display server or OSMesa"]
+    fn backends_agree() {
+        let headless = window::create_headless(64, 64).expect("no OpenGL context");
+        assert_backends_agree(&headless, false);
+        assert_backends_agree(&headless, true);
+    }
+
+    fn square() -> Contour {
+        Contour::new(vec![
+            Vector2::new(0., 0.),
+            Vector2::new(1., 0.),
+            Vector2::new(1., 1.),
+            Vector2::new(0., 1.),
+        ])
+    }
+
+    #[test]
+    fn contour_measures_its_edges_once() {
+        let contour = square();
+        assert_eq!(contour.distances, vec![0., 1., 2., 3., 4.]);
+        assert_eq!(contour.perimeter(), 4.);
+    }
+
+    #[test]
+    fn contour_samples_by_distance_around() {
+        let contour = square();
+        assert_eq!(contour.sample(0.), Vector2::new(0., 0.));
+        assert_eq!(contour.sample(0.125), Vector2::new(0.5, 0.));
+        assert_eq!(contour.sample(0.25), Vector2::new(1., 0.));
+        assert_eq!(contour.sample(0.625), Vector2::new(0.5, 1.));
+        // The last edge closes the outline
+        assert_eq!(contour.sample(0.875), Vector2::new(0., 0.5));
+    }
+
+    #[test]
+    fn contour_skips_repeated_points() {
+        let contour = Contour::new(vec![
+            Vector2::new(0., 0.),
+            Vector2::new(1., 0.),
+            Vector2::new(1., 0.),
+            Vector2::new(1., 1.),
+            Vector2::new(0., 1.),
+        ]);
+        assert_eq!(contour.perimeter(), 4.);
+        assert_eq!(contour.sample(0.25), Vector2::new(1., 0.));
+        assert_eq!(contour.sample(0.375), Vector2::new(1., 0.5));
+    }
+
+    #[test]
+    fn empty_contour_samples_its_origin() {
+        let contour = Contour::new(vec![]);
+        assert_eq!(contour.perimeter(), 0.);
+        assert_eq!(contour.sample(0.5), Vector2::new(0., 0.));
     }
 }
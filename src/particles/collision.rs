@@ -0,0 +1,219 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use cgmath::{InnerSpace, Vector2};
+
+use crate::augment::error::AugmentError;
+
+/// How many texels either side the normal is estimated from
+const NORMAL_REACH: i32 = 2;
+
+/// Solid areas that particles bounce off, covering the -1 to 1 square that particles live in
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollisionMask {
+    width: usize,
+    height: usize,
+    /// A byte per texel, from the top row down. Anything over 127 is solid.
+    data: Vec<u8>,
+}
+
+/// The data for a mask doesn't have a byte for every texel
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaskSizeError {
+    pub width: usize,
+    pub height: usize,
+    pub len: usize,
+}
+
+impl fmt::Display for MaskSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "A {}x{} mask needs {} bytes, not {}",
+            self.width,
+            self.height,
+            self.width * self.height,
+            self.len
+        )
+    }
+}
+
+impl Error for MaskSizeError {}
+
+/// The bytes don't fit the size they came with, so this is invalid data
+impl From<MaskSizeError> for AugmentError {
+    fn from(err: MaskSizeError) -> Self {
+        AugmentError::Io(io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+impl CollisionMask {
+    pub fn new(width: usize, height: usize, data: Vec<u8>) -> Result<CollisionMask, MaskSizeError> {
+        if data.len() != width * height {
+            return Err(MaskSizeError {
+                width,
+                height,
+                len: data.len(),
+            });
+        }
+        Ok(CollisionMask {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Whether this is the mask that `new` would make of the same arguments
+    pub fn matches(&self, width: usize, height: usize, data: &[u8]) -> bool {
+        self.width == width && self.height == height && self.data == data
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The rows from the bottom up, as GL textures want them
+    pub fn rows_bottom_up(&self) -> Vec<Vec<u8>> {
+        self.data
+            .chunks(self.width.max(1))
+            .rev()
+            .map(|row| row.to_vec())
+            .collect()
+    }
+
+    /// The texel under a point, counting rows from the bottom
+    fn texel(&self, position: Vector2<f32>) -> (i32, i32) {
+        (
+            ((position.x + 1.) / 2. * self.width as f32).floor() as i32,
+            ((position.y + 1.) / 2. * self.height as f32).floor() as i32,
+        )
+    }
+
+    /// Whether a texel is solid. Everything outside the mask is open.
+    fn solid_texel(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return false;
+        }
+        let row = self.height - 1 - y as usize;
+        self.data[row * self.width + x as usize] > 127
+    }
+
+    pub fn is_solid(&self, position: Vector2<f32>) -> bool {
+        let (x, y) = self.texel(position);
+        self.solid_texel(x, y)
+    }
+
+    /// Points out of the solid area near `position`, or is zero where that can't be told
+    fn normal(&self, position: Vector2<f32>) -> Vector2<f32> {
+        let (x, y) = self.texel(position);
+        let solid = |dx: i32, dy: i32| self.solid_texel(x + dx, y + dy) as i32 as f32;
+        let gradient = Vector2::new(
+            solid(NORMAL_REACH, 0) - solid(-NORMAL_REACH, 0),
+            solid(0, NORMAL_REACH) - solid(0, -NORMAL_REACH),
+        );
+        if gradient.magnitude2() > 0. {
+            -gradient.normalize()
+        } else {
+            gradient
+        }
+    }
+
+    /// Stops a particle that moved from `from` into a solid texel at `to` and bounces it off. A
+    /// `bounce` of 1 keeps all of its speed into the surface and 0 none of it, so it slides
+    /// along, losing `friction` of the speed along the surface. Particles that start inside a
+    /// solid area move freely, so they can get out. Returns where the particle ends up.
+    pub fn collide(
+        &self,
+        from: Vector2<f32>,
+        to: Vector2<f32>,
+        velocity: &mut Vector2<f32>,
+        bounce: f32,
+        friction: f32,
+    ) -> Vector2<f32> {
+        if !self.is_solid(to) || self.is_solid(from) {
+            return to;
+        }
+        let mut normal = self.normal(to);
+        if normal.magnitude2() == 0. && velocity.magnitude2() > 0. {
+            // Deep inside a thin wall or at a corner, so head back the way it came
+            normal = -velocity.normalize();
+        }
+        let into = velocity.dot(normal);
+        if into < 0. {
+            let along = *velocity - normal * into;
+            *velocity = along * (1. - friction) - normal * (into * bounce);
+        }
+        from
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 8 by 8, with the bottom half solid
+    fn floor() -> CollisionMask {
+        let mut data = vec![0; 64];
+        for byte in &mut data[32..] {
+            *byte = 255;
+        }
+        CollisionMask::new(8, 8, data).unwrap()
+    }
+
+    fn assert_close(a: Vector2<f32>, b: Vector2<f32>) {
+        assert!((a - b).magnitude() < 1e-6, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn new_rejects_data_of_the_wrong_size() {
+        assert_eq!(
+            CollisionMask::new(2, 2, vec![0; 3]),
+            Err(MaskSizeError {
+                width: 2,
+                height: 2,
+                len: 3
+            })
+        );
+    }
+
+    #[test]
+    fn matches_the_same_arguments() {
+        let mask = CollisionMask::new(2, 1, vec![0, 255]).unwrap();
+        assert!(mask.matches(2, 1, &[0, 255]));
+        assert!(!mask.matches(2, 1, &[255, 0]));
+        assert!(!mask.matches(1, 2, &[0, 255]));
+    }
+
+    #[test]
+    fn collide_reflects_and_damps_velocity() {
+        let mask = floor();
+        assert!(mask.is_solid(Vector2::new(0., -0.5)));
+        assert!(!mask.is_solid(Vector2::new(0., 0.5)));
+
+        let from = Vector2::new(0., 0.1);
+        let mut velocity = Vector2::new(0.3, -1.);
+        let at = mask.collide(from, Vector2::new(0.03, -0.1), &mut velocity, 0.5, 0.1);
+        // Stopped where it was, bounced up at half speed and slowed along the floor
+        assert_eq!(at, from);
+        assert_close(velocity, Vector2::new(0.27, 0.5));
+    }
+
+    #[test]
+    fn collide_lets_open_and_escaping_particles_move() {
+        let mask = floor();
+        let mut velocity = Vector2::new(0.3, -1.);
+        let (from, to) = (Vector2::new(0., 0.2), Vector2::new(0.03, 0.05));
+        assert_eq!(mask.collide(from, to, &mut velocity, 0.5, 0.1), to);
+        assert_eq!(velocity, Vector2::new(0.3, -1.));
+
+        // Particles that start inside the solid area move freely, so they can get out
+        let (from, to) = (Vector2::new(0., -0.5), Vector2::new(0., -0.4));
+        assert_eq!(mask.collide(from, to, &mut velocity, 0.5, 0.1), to);
+        assert_eq!(velocity, Vector2::new(0.3, -1.));
+    }
+}
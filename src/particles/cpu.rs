@@ -0,0 +1,97 @@
+use glium::backend::Facade;
+use glium::{implement_vertex, Surface};
+
+use super::{Emission, Particle, ParticleSystem};
+use crate::augment::error::AugmentError;
+
+/// What a particle looks like to `shaders/particle.vert`
+#[derive(Copy, Clone)]
+struct ParticleInstance {
+    particle_position: [f32; 2],
+    size: f32,
+    color: [f32; 4],
+    age: f32,
+}
+
+implement_vertex!(ParticleInstance, particle_position, size, color, age);
+
+/// Keeps the particles in a Vec and uploads the live ones every frame
+pub struct CpuSimulation {
+    particles: Vec<Particle>,
+    instances: glium::VertexBuffer<ParticleInstance>,
+    /// The live particles, in the order they are uploaded
+    scratch: Vec<ParticleInstance>,
+}
+
+impl CpuSimulation {
+    pub fn new<F: Facade>(facade: &F, num_particles: usize) -> Result<CpuSimulation, AugmentError> {
+        Ok(CpuSimulation {
+            particles: vec![Particle::new(); num_particles],
+            instances: glium::VertexBuffer::empty_dynamic(facade, num_particles)?,
+            scratch: Vec::with_capacity(num_particles),
+        })
+    }
+
+    pub fn update(&mut self, system: &ParticleSystem, emission: &Emission, time: f32, delta: f32) {
+        for particle in self.particles.iter_mut().filter(|p| p.is_alive()) {
+            system.step(particle, time, delta);
+        }
+
+        let num_particles = self.particles.len();
+        for offset in 0..emission.count {
+            let index = emission.first_index.wrapping_add(offset as u32);
+            self.particles[(emission.first_slot + offset) % num_particles] = system.spawn(index);
+        }
+    }
+
+    /// Draws with `shaders/particle.vert`
+    pub fn draw<S: Surface>(
+        &mut self,
+        program: &glium::Program,
+        system: &ParticleSystem,
+        target: &mut S,
+        area: [f32; 4],
+    ) -> Result<(), AugmentError> {
+        self.scratch.clear();
+        self.scratch
+            .extend(self.particles.iter().filter(|p| p.is_alive()).map(|p| {
+                let age = 1. - p.life / system.lifetime;
+                let mut color = system.color.value(age);
+                color[3] *= system.opacity.value(age) * system.fade;
+                ParticleInstance {
+                    particle_position: p.position.into(),
+                    size: system.size.value(age),
+                    color,
+                    age,
+                }
+            }));
+        if self.scratch.is_empty() {
+            return Ok(());
+        }
+        let instances = self.instances.slice(0..self.scratch.len()).unwrap();
+        instances.write(&self.scratch);
+        let per_instance = instances
+            .per_instance()
+            .map_err(|_| AugmentError::Gl("Instancing is not supported".to_string()))?;
+
+        let (width, height) = target.get_dimensions();
+        target.draw(
+            (
+                glium::vertex::EmptyVertexAttributes { len: 1 },
+                per_instance,
+            ),
+            &glium::index::NoIndices(glium::index::PrimitiveType::Points),
+            program,
+            &uniform! {
+                iResolution: [width as f32, height as f32, height as f32 / width as f32],
+                iArea: area,
+            },
+            &super::draw_parameters(),
+        )?;
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> Vec<Particle> {
+        self.particles.clone()
+    }
+}
@@ -0,0 +1,215 @@
+use cgmath::{Vector2, Vector3, Vector4};
+
+/// Values that a `Curve` can blend between
+pub trait Lerp: Copy {
+    /// `self` at 0, `other` at 1
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, other: f32, t: f32) -> f32 {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vector2<f32> {
+    fn lerp(self, other: Vector2<f32>, t: f32) -> Vector2<f32> {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vector3<f32> {
+    fn lerp(self, other: Vector3<f32>, t: f32) -> Vector3<f32> {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vector4<f32> {
+    fn lerp(self, other: Vector4<f32>, t: f32) -> Vector4<f32> {
+        self + (other - self) * t
+    }
+}
+
+/// RGBA
+impl Lerp for [f32; 4] {
+    fn lerp(self, other: [f32; 4], t: f32) -> [f32; 4] {
+        let mut out = self;
+        for (out, other) in out.iter_mut().zip(&other) {
+            *out = out.lerp(*other, t);
+        }
+        out
+    }
+}
+
+/// How a curve gets from one key to the next
+#[derive(Debug, Clone, PartialEq)]
+pub enum Interpolation<T> {
+    /// Keeps the value of the earlier key until the later one
+    Step,
+    Linear,
+    /// A cubic Bézier from the earlier key's value to the later one's, pulled towards the two
+    /// control values
+    Bezier(T, T),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key<T> {
+    pub time: f32,
+    pub value: T,
+    /// How the curve gets here from the key before
+    pub interpolation: Interpolation<T>,
+}
+
+/// A value that changes over time, set by keys. Before the first key and after the last the
+/// value stays the same. Particles use these over their age, which goes from 0 to 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve<T> {
+    /// Sorted by time, and never empty
+    keys: Vec<Key<T>>,
+}
+
+impl<T: Lerp> Curve<T> {
+    pub fn constant(value: T) -> Curve<T> {
+        Curve {
+            keys: vec![Key {
+                time: 0.,
+                value,
+                interpolation: Interpolation::Linear,
+            }],
+        }
+    }
+
+    /// Goes from `start` at 0 to `end` at 1 in a straight line
+    pub fn linear(start: T, end: T) -> Curve<T> {
+        Curve::constant(start).add(1., end, Interpolation::Linear)
+    }
+
+    /// Adds a key, after any others at the same time
+    pub fn add(mut self, time: f32, value: T, interpolation: Interpolation<T>) -> Curve<T> {
+        let index = self.keys.iter().take_while(|key| key.time <= time).count();
+        self.keys.insert(
+            index,
+            Key {
+                time,
+                value,
+                interpolation,
+            },
+        );
+        self
+    }
+
+    pub fn value(&self, time: f32) -> T {
+        // The first key that comes after `time`. At a key's time exactly the curve has its value.
+        let next = self.keys.iter().take_while(|key| key.time <= time).count();
+        if next == 0 {
+            return self.keys[0].value;
+        }
+        let (from, to) = match self.keys.get(next) {
+            Some(to) => (&self.keys[next - 1], to),
+            None => return self.keys[next - 1].value,
+        };
+        let t = (time - from.time) / (to.time - from.time);
+        match to.interpolation {
+            Interpolation::Step => from.value,
+            Interpolation::Linear => from.value.lerp(to.value, t),
+            Interpolation::Bezier(c1, c2) => {
+                // De Casteljau's algorithm, which only needs lerp
+                let a = from.value.lerp(c1, t);
+                let b = c1.lerp(c2, t);
+                let c = c2.lerp(to.value, t);
+                let ab = a.lerp(b, t);
+                let bc = b.lerp(c, t);
+                ab.lerp(bc, t)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_curves_have_one_value_everywhere() {
+        let curve = Curve::constant(2.5);
+        for &time in [-1., 0., 0.5, 1., 10.].iter() {
+            assert_eq!(curve.value(time), 2.5);
+        }
+    }
+
+    #[test]
+    fn values_hold_before_the_first_key_and_after_the_last() {
+        let key = |time, value| Key {
+            time,
+            value,
+            interpolation: Interpolation::Linear,
+        };
+        let curve = Curve {
+            keys: vec![key(0.25, 1.), key(0.75, 3.)],
+        };
+        assert_eq!(curve.value(0.5), 2.);
+        assert_eq!(curve.value(-5.), 1.);
+        assert_eq!(curve.value(0.), 1.);
+        assert_eq!(curve.value(1.), 3.);
+        assert_eq!(curve.value(5.), 3.);
+    }
+
+    #[test]
+    fn values_on_a_key_are_the_key_value() {
+        let curve = Curve::linear(0., 2.).add(0.5, 4., Interpolation::Linear);
+        assert_eq!(curve.value(0.), 0.);
+        assert_eq!(curve.value(0.5), 4.);
+        assert_eq!(curve.value(1.), 2.);
+        // A later key at the same time wins
+        let curve = curve.add(0.5, 8., Interpolation::Linear);
+        assert_eq!(curve.value(0.5), 8.);
+    }
+
+    #[test]
+    fn step_keeps_the_earlier_value_until_the_key() {
+        let curve = Curve::constant(1.).add(1., 5., Interpolation::Step);
+        assert_eq!(curve.value(0.), 1.);
+        assert_eq!(curve.value(0.999), 1.);
+        assert_eq!(curve.value(1.), 5.);
+    }
+
+    #[test]
+    fn linear_meets_both_ends_of_a_segment() {
+        let curve = Curve::linear(2., 4.);
+        assert_eq!(curve.value(0.), 2.);
+        assert_eq!(curve.value(0.5), 3.);
+        assert_eq!(curve.value(1.), 4.);
+    }
+
+    #[test]
+    fn bezier_meets_both_ends_and_bends_towards_the_controls() {
+        let curve = Curve::constant(0.).add(1., 1., Interpolation::Bezier(3., -2.));
+        assert_eq!(curve.value(0.), 0.);
+        assert_eq!(curve.value(1.), 1.);
+        // (0 + 3 * 3 + 3 * -2 + 1) / 8
+        assert_eq!(curve.value(0.5), 0.5);
+        assert!(curve.value(0.25) > Curve::linear(0., 1.).value(0.25));
+    }
+
+    #[test]
+    fn vectors_and_colors_lerp_every_component() {
+        assert_eq!(
+            Vector2::new(0., 2.).lerp(Vector2::new(4., -2.), 0.25),
+            Vector2::new(1., 1.)
+        );
+        assert_eq!(
+            Vector3::new(0., 2., 4.).lerp(Vector3::new(4., -2., 4.), 0.5),
+            Vector3::new(2., 0., 4.)
+        );
+        assert_eq!(
+            Vector4::new(0., 1., 2., 3.).lerp(Vector4::new(1., 1., 0., -1.), 1.),
+            Vector4::new(1., 1., 0., -1.)
+        );
+        assert_eq!(
+            [1., 0., 0., 1.].lerp([0., 0., 1., 0.], 0.75),
+            [0.25, 0., 0.75, 0.25]
+        );
+        let fade = Curve::linear([1., 1., 1., 1.], [1., 0., 0., 0.]);
+        assert_eq!(fade.value(0.5), [1., 0.5, 0.5, 0.5]);
+    }
+}
@@ -0,0 +1,146 @@
+use cgmath::{InnerSpace, Vector2};
+
+use super::hash;
+
+/// Moves every particle in a system. `shaders/particle_forces.glsl` is the same for the GPU.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Force {
+    /// Pulls particles towards `position`, or pushes them away when `strength` is negative. It
+    /// falls off with the square of the distance, softened within `radius` so it stays finite at
+    /// the center.
+    Attractor {
+        position: Vector2<f32>,
+        strength: f32,
+        radius: f32,
+    },
+    /// Swirls particles around `center`, counterclockwise when `strength` is positive
+    Vortex {
+        center: Vector2<f32>,
+        strength: f32,
+        radius: f32,
+    },
+    /// Curl noise, a swirling flow that never bunches particles up. `scale` is the size of the
+    /// swirls and `speed` how fast they change, and each `seed` gives a different flow.
+    Turbulence {
+        strength: f32,
+        scale: f32,
+        speed: f32,
+        seed: u32,
+    },
+    /// Blows particles towards moving with `velocity`, closing `strength` of the gap every
+    /// second
+    Wind {
+        velocity: Vector2<f32>,
+        strength: f32,
+    },
+}
+
+impl Force {
+    /// The acceleration of a particle at `position` moving at `velocity`, `time` seconds into
+    /// the simulation
+    pub fn acceleration(
+        &self,
+        position: Vector2<f32>,
+        velocity: Vector2<f32>,
+        time: f32,
+    ) -> Vector2<f32> {
+        match *self {
+            Force::Attractor {
+                position: center,
+                strength,
+                radius,
+            } => {
+                let offset = center - position;
+                let distance2 = offset.magnitude2() + radius * radius;
+                offset * (strength / (distance2 * distance2.sqrt()).max(1e-6))
+            }
+            Force::Vortex {
+                center,
+                strength,
+                radius,
+            } => {
+                let offset = position - center;
+                let distance2 = offset.magnitude2() + radius * radius;
+                Vector2::new(-offset.y, offset.x) * (strength / distance2.max(1e-6))
+            }
+            Force::Turbulence {
+                strength,
+                scale,
+                speed,
+                seed,
+            } => {
+                let x = position.x / scale;
+                let y = position.y / scale;
+                let z = time * speed;
+                // The curl of a noise potential, taken with central differences
+                let e = 0.01;
+                let dx = value_noise(x + e, y, z, seed) - value_noise(x - e, y, z, seed);
+                let dy = value_noise(x, y + e, z, seed) - value_noise(x, y - e, z, seed);
+                Vector2::new(dy, -dx) * (strength / (2. * e))
+            }
+            Force::Wind {
+                velocity: wind,
+                strength,
+            } => (wind - velocity) * strength,
+        }
+    }
+}
+
+/// Smooth noise between 0 and 1, made by blending random values at the corners of a grid
+fn value_noise(x: f32, y: f32, z: f32, seed: u32) -> f32 {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let fade = |t: f32| t * t * (3. - 2. * t);
+    let (u, v, w) = (fade(x - x0), fade(y - y0), fade(z - z0));
+    let (i, j, k) = (x0 as i32, y0 as i32, z0 as i32);
+    let corner = |di: i32, dj: i32, dk: i32| {
+        let h = hash((i + di) as u32 ^ hash((j + dj) as u32 ^ hash((k + dk) as u32 ^ seed)));
+        (h >> 8) as f32 / (1 << 24) as f32
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let face = |dk: i32| {
+        lerp(
+            lerp(corner(0, 0, dk), corner(1, 0, dk), u),
+            lerp(corner(0, 1, dk), corner(1, 1, dk), u),
+            v,
+        )
+    };
+    lerp(face(0), face(1), w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turbulence(seed: u32) -> Force {
+        Force::Turbulence {
+            strength: 1.,
+            scale: 0.3,
+            speed: 0.5,
+            seed,
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_turbulence() {
+        let position = Vector2::new(-0.25, 0.4);
+        let velocity = Vector2::new(0.1, 0.);
+        for &time in &[0., 0.7, 3.2] {
+            let a = turbulence(7).acceleration(position, velocity, time);
+            let b = turbulence(7).acceleration(position, velocity, time);
+            assert_eq!(a, b);
+            assert!(a.magnitude() > 0.);
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_turbulence() {
+        let position = Vector2::new(-0.25, 0.4);
+        let velocity = Vector2::new(0.1, 0.);
+        for &time in &[0., 0.7, 3.2] {
+            assert_ne!(
+                turbulence(7).acceleration(position, velocity, time),
+                turbulence(8).acceleration(position, velocity, time)
+            );
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::rc::Rc;
+
+use cgmath::Vector2;
+use glium::backend::Facade;
+use glium::framebuffer::MultiOutputFrameBuffer;
+use glium::texture::{MipmapsOption, Texture2d, UncompressedFloatFormat};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler};
+use glium::Surface;
+
+use super::{CollisionMask, Emission, EmitterShape, Force, Particle, ParticleSystem};
+use crate::augment::error::AugmentError;
+
+/// State textures are at most this wide, and as many rows tall as the particles need
+const MAX_WIDTH: usize = 1024;
+
+/// Samples of the size and color curves, which the draw shader looks up by age
+const RAMP_SIZE: usize = 256;
+
+/// Forces past this many are left out on the GPU
+const MAX_FORCES: usize = 16;
+
+/// The contour texture is this wide, and as many rows tall as the longest contour needs
+const CONTOUR_WIDTH: usize = 1024;
+
+/// Values of `iShape` in `shaders/particle_step.frag`
+const SHAPE_POINT: i32 = 0;
+const SHAPE_BOX: i32 = 1;
+const SHAPE_CONTOUR: i32 = 2;
+
+type Texel = (f32, f32, f32, f32);
+
+/// One copy of the simulation state, with a texel for each particle
+struct State {
+    /// Position in xy and velocity in zw
+    motion: Texture2d,
+    /// Seconds left to live in x
+    life: Texture2d,
+}
+
+impl State {
+    fn new<F: Facade>(facade: &F, width: usize, height: usize) -> Result<State, AugmentError> {
+        // All zeros, so every particle starts out dead
+        let zeros = || vec![vec![(0f32, 0f32, 0f32, 0f32); width]; height];
+        let texture = |data: Vec<Vec<Texel>>| {
+            Texture2d::with_format(
+                facade,
+                data,
+                UncompressedFloatFormat::F32F32F32F32,
+                MipmapsOption::NoMipmap,
+            )
+        };
+        Ok(State {
+            motion: texture(zeros())?,
+            life: texture(zeros())?,
+        })
+    }
+}
+
+/// Keeps the particles in float textures. Each step draws the next state from the previous one
+/// with `shaders/particle_step.frag`, which also emits the new particles, and the particles are
+/// drawn straight from the textures, so nothing is read back to the CPU.
+pub struct GpuSimulation {
+    num_particles: usize,
+    /// The state that was written last, and the one the next step writes
+    states: [State; 2],
+    current: usize,
+    /// Color with opacity applied, over age
+    color_ramp: Texture2d,
+    /// Size in x, over age
+    size_ramp: Texture2d,
+    /// Two texels per force, laid out for `shaders/particle_forces.glsl`
+    forces: Texture2d,
+    mask: Texture2d,
+    /// The mask that was last uploaded to `mask`
+    uploaded_mask: Option<Rc<CollisionMask>>,
+    /// A texel per point of an `EmitterShape::Contour`, with the point in xy and the distance
+    /// around the contour to it in z
+    contour: Texture2d,
+}
+
+impl GpuSimulation {
+    pub fn new<F: Facade>(facade: &F, num_particles: usize) -> Result<GpuSimulation, AugmentError> {
+        let width = num_particles.min(MAX_WIDTH).max(1);
+        let height = ((num_particles + width - 1) / width).max(1);
+        let ramp = || {
+            Texture2d::empty_with_format(
+                facade,
+                UncompressedFloatFormat::F32F32F32F32,
+                MipmapsOption::NoMipmap,
+                RAMP_SIZE as u32,
+                1,
+            )
+        };
+        let forces = Texture2d::empty_with_format(
+            facade,
+            UncompressedFloatFormat::F32F32F32F32,
+            MipmapsOption::NoMipmap,
+            2 * MAX_FORCES as u32,
+            1,
+        )?;
+        // Stands in while there is no mask, which the step shader doesn't look at then
+        let mask = Texture2d::with_format(
+            facade,
+            vec![vec![0u8]],
+            UncompressedFloatFormat::U8,
+            MipmapsOption::NoMipmap,
+        )?;
+        let contour = Texture2d::empty_with_format(
+            facade,
+            UncompressedFloatFormat::F32F32F32F32,
+            MipmapsOption::NoMipmap,
+            CONTOUR_WIDTH as u32,
+            1,
+        )?;
+        Ok(GpuSimulation {
+            num_particles,
+            states: [
+                State::new(facade, width, height)?,
+                State::new(facade, width, height)?,
+            ],
+            current: 0,
+            color_ramp: ramp()?,
+            size_ramp: ramp()?,
+            forces,
+            mask,
+            uploaded_mask: None,
+            contour,
+        })
+    }
+
+    /// Steps with `shaders/particle_step.frag`
+    pub fn update<F: Facade>(
+        &mut self,
+        facade: &F,
+        program: &glium::Program,
+        system: &ParticleSystem,
+        emission: &Emission,
+        time: f32,
+        delta: f32,
+    ) -> Result<(), AugmentError> {
+        self.upload_forces(facade, system)?;
+        let (shape, box_half, contour_points, perimeter) = self.upload_shape(facade, system)?;
+        let current = &self.states[self.current];
+        let next = &self.states[1 - self.current];
+        let mut framebuffer = MultiOutputFrameBuffer::new(
+            facade,
+            vec![("motion", &next.motion), ("life", &next.life)],
+        )
+        .map_err(|err| AugmentError::Gl(err.to_string()))?;
+        let gravity: [f32; 2] = system.gravity.into();
+        let position: [f32; 2] = system.position.into();
+        let velocity: [f32; 2] = system.velocity.into();
+        framebuffer.draw(
+            glium::vertex::EmptyVertexAttributes { len: 4 },
+            &glium::index::NoIndices(glium::index::PrimitiveType::TriangleStrip),
+            program,
+            &uniform! {
+                iMotion: nearest(&current.motion),
+                iLife: nearest(&current.life),
+                iTime: time,
+                iDelta: delta,
+                iGravity: gravity,
+                iDrag: system.drag,
+                iForces: nearest(&self.forces),
+                iNumForces: system.forces.len().min(MAX_FORCES) as i32,
+                iMask: nearest(&self.mask),
+                iUseMask: system.mask.is_some(),
+                iBounce: system.bounce,
+                iFriction: system.friction,
+                iEmitterPosition: position,
+                iEmitterVelocity: velocity,
+                iSpread: system.spread,
+                iLifetime: system.lifetime,
+                iNumParticles: self.num_particles as i32,
+                iEmitStart: emission.first_slot as i32,
+                iEmitCount: emission.count as i32,
+                iEmitIndex: emission.first_index,
+                iSeed: system.seed,
+                iShape: shape,
+                iBoxHalf: box_half,
+                iContour: nearest(&self.contour),
+                iContourPoints: contour_points,
+                iPerimeter: perimeter,
+            },
+            &Default::default(),
+        )?;
+
+        self.current = 1 - self.current;
+        Ok(())
+    }
+
+    /// Packs the forces into their texture, and uploads the mask when it has changed
+    fn upload_forces<F: Facade>(
+        &mut self,
+        facade: &F,
+        system: &ParticleSystem,
+    ) -> Result<(), AugmentError> {
+        let mut texels = vec![(0f32, 0f32, 0f32, 0f32); 2 * MAX_FORCES];
+        for (force, texels) in system.forces.iter().zip(texels.chunks_mut(2)) {
+            let (a, b) = match *force {
+                Force::Attractor {
+                    position,
+                    strength,
+                    radius,
+                } => ((1., strength, radius, 0.), (position.x, position.y, 0., 0.)),
+                Force::Vortex {
+                    center,
+                    strength,
+                    radius,
+                } => ((2., strength, radius, 0.), (center.x, center.y, 0., 0.)),
+                Force::Turbulence {
+                    strength,
+                    scale,
+                    speed,
+                    seed,
+                } => (
+                    (3., strength, scale, speed),
+                    // Split in halves, which floats can hold exactly
+                    (0., 0., (seed & 0xffff) as f32, (seed >> 16) as f32),
+                ),
+                Force::Wind { velocity, strength } => {
+                    ((4., strength, 0., 0.), (velocity.x, velocity.y, 0., 0.))
+                }
+            };
+            texels[0] = a;
+            texels[1] = b;
+        }
+        let rect = glium::Rect {
+            left: 0,
+            bottom: 0,
+            width: 2 * MAX_FORCES as u32,
+            height: 1,
+        };
+        self.forces.write(rect, vec![texels]);
+
+        let changed = match (&system.mask, &self.uploaded_mask) {
+            (Some(mask), Some(uploaded)) => !Rc::ptr_eq(mask, uploaded),
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if changed {
+            let mask = system.mask.as_ref().unwrap();
+            let (width, height) = (mask.width() as u32, mask.height() as u32);
+            // Masks almost always keep the size of the video, so the texture is written over
+            if (self.mask.width(), self.mask.height()) == (width, height) {
+                let rect = glium::Rect {
+                    left: 0,
+                    bottom: 0,
+                    width,
+                    height,
+                };
+                self.mask.write(rect, mask.rows_bottom_up());
+            } else {
+                self.mask = Texture2d::with_format(
+                    facade,
+                    mask.rows_bottom_up(),
+                    UncompressedFloatFormat::U8,
+                    MipmapsOption::NoMipmap,
+                )?;
+            }
+            self.uploaded_mask = Some(mask.clone());
+        }
+        Ok(())
+    }
+
+    /// The uniforms that pick the emitter shape: which one it is, the half extents of a box, and
+    /// the number of points and perimeter of a contour. A contour's points go in `contour`.
+    fn upload_shape<F: Facade>(
+        &mut self,
+        facade: &F,
+        system: &ParticleSystem,
+    ) -> Result<(i32, [f32; 2], i32, f32), AugmentError> {
+        let contour = match &system.shape {
+            EmitterShape::Point => return Ok((SHAPE_POINT, [0., 0.], 0, 0.)),
+            EmitterShape::Box(half) => return Ok((SHAPE_BOX, (*half).into(), 0, 0.)),
+            EmitterShape::Contour(contour) => contour,
+        };
+        let rows = ((contour.points.len() + CONTOUR_WIDTH - 1) / CONTOUR_WIDTH).max(1);
+        if rows > self.contour.height() as usize {
+            self.contour = Texture2d::empty_with_format(
+                facade,
+                UncompressedFloatFormat::F32F32F32F32,
+                MipmapsOption::NoMipmap,
+                CONTOUR_WIDTH as u32,
+                rows as u32,
+            )?;
+        }
+        let mut texels: Vec<Texel> = contour
+            .points
+            .iter()
+            .zip(&contour.distances)
+            .map(|(point, &distance)| (point.x, point.y, distance, 0.))
+            .collect();
+        texels.resize(rows * CONTOUR_WIDTH, (0., 0., 0., 0.));
+        let rect = glium::Rect {
+            left: 0,
+            bottom: 0,
+            width: CONTOUR_WIDTH as u32,
+            height: rows as u32,
+        };
+        self.contour.write(
+            rect,
+            texels
+                .chunks(CONTOUR_WIDTH)
+                .map(|row| row.to_vec())
+                .collect::<Vec<_>>(),
+        );
+        Ok((
+            SHAPE_CONTOUR,
+            [0., 0.],
+            contour.points.len() as i32,
+            contour.perimeter(),
+        ))
+    }
+
+    /// Draws with `shaders/particle_gpu.vert`
+    pub fn draw<S: Surface>(
+        &mut self,
+        program: &glium::Program,
+        system: &ParticleSystem,
+        target: &mut S,
+        area: [f32; 4],
+    ) -> Result<(), AugmentError> {
+        if self.num_particles == 0 {
+            return Ok(());
+        }
+        // The curves can change at any time, and a few hundred texels are cheap to upload
+        let ages = (0..RAMP_SIZE).map(|i| i as f32 / (RAMP_SIZE - 1) as f32);
+        let colors: Vec<Texel> = ages
+            .clone()
+            .map(|age| {
+                let [r, g, b, a] = system.color.value(age);
+                (r, g, b, a * system.opacity.value(age) * system.fade)
+            })
+            .collect();
+        let sizes: Vec<Texel> = ages
+            .map(|age| (system.size.value(age), 0., 0., 0.))
+            .collect();
+        let rect = glium::Rect {
+            left: 0,
+            bottom: 0,
+            width: RAMP_SIZE as u32,
+            height: 1,
+        };
+        self.color_ramp.write(rect, vec![colors]);
+        self.size_ramp.write(rect, vec![sizes]);
+
+        let state = &self.states[self.current];
+        let (width, height) = target.get_dimensions();
+        target.draw(
+            (
+                glium::vertex::EmptyVertexAttributes { len: 1 },
+                glium::vertex::EmptyInstanceAttributes {
+                    len: self.num_particles,
+                },
+            ),
+            &glium::index::NoIndices(glium::index::PrimitiveType::Points),
+            program,
+            &uniform! {
+                iResolution: [width as f32, height as f32, height as f32 / width as f32],
+                iMotion: nearest(&state.motion),
+                iLife: nearest(&state.life),
+                iColorRamp: linear(&self.color_ramp),
+                iSizeRamp: linear(&self.size_ramp),
+                iLifetime: system.lifetime,
+                iArea: area,
+            },
+            &super::draw_parameters(),
+        )?;
+        Ok(())
+    }
+
+    /// Reads the particles back from the GPU, in slot order. Slow, and only meant for checking
+    /// the simulation against the CPU.
+    pub fn snapshot(&self) -> Vec<Particle> {
+        let state = &self.states[self.current];
+        // Both textures are F32F32F32F32, which desktop GL can always read back as floats
+        let (motion, life): (Vec<Vec<Texel>>, Vec<Vec<Texel>>) =
+            unsafe { (state.motion.unchecked_read(), state.life.unchecked_read()) };
+        motion
+            .iter()
+            .flatten()
+            .zip(life.iter().flatten())
+            .take(self.num_particles)
+            .map(|(&(x, y, vx, vy), &(life, _, _, _))| Particle {
+                position: Vector2::new(x, y),
+                velocity: Vector2::new(vx, vy),
+                life,
+            })
+            .collect()
+    }
+}
+
+/// State textures hold data rather than images, so they must never be blended between texels
+fn nearest(texture: &Texture2d) -> Sampler<'_, Texture2d> {
+    texture
+        .sampled()
+        .minify_filter(MinifySamplerFilter::Nearest)
+        .magnify_filter(MagnifySamplerFilter::Nearest)
+}
+
+fn linear(texture: &Texture2d) -> Sampler<'_, Texture2d> {
+    texture
+        .sampled()
+        .minify_filter(MinifySamplerFilter::Linear)
+        .magnify_filter(MagnifySamplerFilter::Linear)
+}
@@ -1,15 +1,13 @@
 use super::teapot;
+use crate::window::WindowOptions;
 use cgmath::{Matrix4, Vector4};
 use glium::implement_vertex;
 
-#[allow(dead_code)]
-pub fn start() {
+pub fn start(window_options: &WindowOptions) {
     use glium::{glutin, Surface};
 
     let event_loop = glutin::event_loop::EventLoop::new();
-    let wb = glutin::window::WindowBuilder::new();
-    let cb = glutin::ContextBuilder::new().with_depth_buffer(24);
-    let display = glium::Display::new(wb, cb, &event_loop).unwrap();
+    let display = window_options.create_display("Teapot", &event_loop);
 
     #[derive(Copy, Clone)]
     struct Vertex {
@@ -1,16 +1,40 @@
+use crate::augment::shaders::{ProgramHandle, ProgramSource};
+use crate::window::WindowOptions;
 use glium::implement_vertex;
-use notify::{watcher, RecursiveMode, Watcher};
-use std::sync::mpsc::channel;
+use std::path::PathBuf;
 use std::time::*;
+use structopt::StructOpt;
 
-#[allow(dead_code)]
-pub fn start() {
+#[derive(Debug, StructOpt)]
+pub struct ShadertoyOptions {
+    /// Fragment shader to render
+    #[structopt(parse(from_os_str), default_value = "shader.frag")]
+    pub fragment_shader: PathBuf,
+
+    /// Vertex shader for the fullscreen quad
+    #[structopt(long, parse(from_os_str), default_value = "shader.vert")]
+    pub vertex_shader: PathBuf,
+
+    /// Adds `#define NAME VALUE` to both shaders. Can be given more than once.
+    #[structopt(short = "D", long = "define", parse(try_from_str = parse_define))]
+    pub defines: Vec<(String, String)>,
+}
+
+/// NAME=VALUE, or just NAME to define it as nothing
+fn parse_define(define: &str) -> Result<(String, String), String> {
+    let mut parts = define.splitn(2, '=');
+    let name = parts.next().unwrap_or("").trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("{:?} is not a valid name for a define", name));
+    }
+    Ok((name.to_string(), parts.next().unwrap_or("").to_string()))
+}
+
+pub fn start(window_options: &WindowOptions, options: ShadertoyOptions) {
     use glium::{glutin, Surface};
 
     let event_loop = glutin::event_loop::EventLoop::new();
-    let wb = glutin::window::WindowBuilder::new();
-    let cb = glutin::ContextBuilder::new().with_depth_buffer(24);
-    let display = glium::Display::new(wb, cb, &event_loop).unwrap();
+    let display = window_options.create_display("Shadertoy", &event_loop);
 
     #[derive(Copy, Clone)]
     struct Vertex {
@@ -35,31 +59,19 @@ pub fn start() {
     let vertex_buffer = glium::VertexBuffer::new(&display, &shape).unwrap();
     let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
 
-    let mut program = load_shader(&display);
-
-    let (tx, rx) = channel();
-    let mut watcher = watcher(tx, Duration::from_millis(50)).unwrap();
-    watcher
-        .watch("shader.vert", RecursiveMode::NonRecursive)
-        .unwrap();
-    watcher
-        .watch("shader.frag", RecursiveMode::NonRecursive)
-        .unwrap();
+    // Editing the shaders while they run is the point, so they are reloaded in release builds too
+    let source = ProgramSource::new(&options.vertex_shader, &options.fragment_shader);
+    let mut program: ProgramHandle = match ProgramHandle::load(&display, source, &options.defines) {
+        Ok(program) => program.always_reload(),
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
     let now = Instant::now();
 
     event_loop.run(move |ev, _, control_flow| {
-        if rx.try_recv().is_ok() {
-            program = load_shader(&display);
-            if program.is_err() {
-                println!("Error loading shader: {:?}", program.as_ref().err());
-            }
-            watcher
-                .watch("shader.vert", RecursiveMode::NonRecursive)
-                .unwrap();
-            watcher
-                .watch("shader.frag", RecursiveMode::NonRecursive)
-                .unwrap();
-        }
+        program.poll(&display);
 
         let mut target = display.draw();
         target.clear_color(0.0, 0.0, 0.0, 1.0);
@@ -67,11 +79,11 @@ pub fn start() {
         let (width, height) = target.get_dimensions();
         let aspect_ratio = height as f32 / width as f32;
         let resolution = [width as f32, height as f32, aspect_ratio];
-        if program.is_ok() {
+        if let Ok(program) = program.as_program() {
             let result = target.draw(
                 &vertex_buffer,
                 &indices,
-                &program.as_ref().unwrap(),
+                program,
                 &uniform! {
                     iResolution: resolution,
                     iTime: now.elapsed().as_secs_f32(),
@@ -99,9 +111,3 @@ pub fn start() {
         }
     });
 }
-
-fn load_shader(display: &glium::Display) -> Result<glium::Program, Box<dyn std::error::Error>> {
-    let vert = std::fs::read_to_string("shader.vert")?;
-    let frag = std::fs::read_to_string("shader.frag")?;
-    Ok(glium::Program::from_source(display, &vert, &frag, None)?)
-}
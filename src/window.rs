@@ -0,0 +1,116 @@
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+))]
+use glium::glutin::platform::unix::{EventLoopExtUnix, HeadlessContextExt};
+use glium::glutin::{
+    self,
+    dpi::{LogicalSize, PhysicalSize},
+    event_loop::EventLoop,
+    window::Fullscreen,
+};
+use std::error::Error;
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct WindowOptions {
+    /// Initial window width
+    #[structopt(long, default_value = "1024")]
+    pub width: u32,
+
+    /// Initial window height
+    #[structopt(long, default_value = "768")]
+    pub height: u32,
+
+    /// Start in borderless fullscreen
+    #[structopt(long)]
+    pub fullscreen: bool,
+
+    /// Sync buffer swaps to the monitor refresh rate
+    #[structopt(long)]
+    pub vsync: bool,
+}
+
+impl WindowOptions {
+    pub fn create_display(&self, title: &str, event_loop: &EventLoop<()>) -> glium::Display {
+        let wb = glutin::window::WindowBuilder::new()
+            .with_title(title)
+            .with_inner_size(LogicalSize::new(self.width, self.height));
+        let cb = glutin::ContextBuilder::new()
+            .with_depth_buffer(24)
+            .with_vsync(self.vsync);
+        let display = glium::Display::new(wb, cb, event_loop).unwrap();
+        if self.fullscreen {
+            set_fullscreen(&display, true);
+        }
+        display
+    }
+}
+
+/// An OpenGL context that renders offscreen only
+pub struct Headless {
+    pub renderer: glium::HeadlessRenderer,
+    /// The surfaceless context has to outlive the event loop it was created from
+    _event_loop: Option<EventLoop<()>>,
+}
+
+/// Creates a surfaceless EGL context when there is a display server to talk to, and falls back
+/// to OSMesa software rendering on machines without a display or GPU. Works from any thread, so
+/// tests can use it.
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+))]
+pub fn create_headless(width: u32, height: u32) -> Result<Headless, Box<dyn Error>> {
+    let has_display =
+        std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some();
+    if has_display {
+        let event_loop = EventLoop::new_any_thread();
+        let context = glutin::ContextBuilder::new()
+            .with_depth_buffer(24)
+            .build_surfaceless(&event_loop);
+        match context {
+            Ok(context) => {
+                return Ok(Headless {
+                    renderer: glium::HeadlessRenderer::new(context)?,
+                    _event_loop: Some(event_loop),
+                })
+            }
+            Err(err) => println!("No surfaceless context, falling back to OSMesa: {}", err),
+        }
+    }
+    let context = glutin::ContextBuilder::new()
+        .with_depth_buffer(24)
+        .build_osmesa(PhysicalSize::new(width, height))?;
+    Ok(Headless {
+        renderer: glium::HeadlessRenderer::new(context)?,
+        _event_loop: None,
+    })
+}
+
+/// Creates a hidden context, as there is no surfaceless EGL or OSMesa here
+#[cfg(not(all(
+    unix,
+    not(any(target_os = "macos", target_os = "ios", target_os = "android"))
+)))]
+pub fn create_headless(width: u32, height: u32) -> Result<Headless, Box<dyn Error>> {
+    let event_loop = EventLoop::new();
+    let context = glutin::ContextBuilder::new()
+        .with_depth_buffer(24)
+        .build_headless(&event_loop, PhysicalSize::new(width, height))?;
+    Ok(Headless {
+        renderer: glium::HeadlessRenderer::new(context)?,
+        _event_loop: Some(event_loop),
+    })
+}
+
+pub fn set_fullscreen(display: &glium::Display, fullscreen: bool) {
+    let gl_window = display.gl_window();
+    let window = gl_window.window();
+    if fullscreen {
+        let monitor_handle = window.available_monitors().next();
+        window.set_fullscreen(Some(Fullscreen::Borderless(monitor_handle)));
+    } else {
+        window.set_fullscreen(None);
+    }
+}